@@ -1,51 +1,172 @@
-#version 450
-
-//My gtx1080 allows only for a maximum of 1536 local invocations
-//so we are kind of limited here, but this is more for speedup than anything.
-//Bigger local invocation groups could help however with keeping the
-//work group counts low, which also have a limit, but that limit is way higher.
-layout(local_size_x = 8, local_size_y = 8, local_size_z = 8) in;
-layout(rgba32f, binding = 0) uniform image3D img_output;
-
-#define SCENE_SCALE 512
-
-// struct Sphere {
-//     vec3 position;
-//     float radius;
-// };
-//
-// struct Box {
-//     vec3 position;
-//     vec3 size;
-// };
-//
-// struct InfPlane {
-//     float height;
-// };
-
-// float dot2( in vec2 v ) { return dot(v,v); }
-// float dot2( in vec3 v ) { return dot(v,v); }
+//Distance function library for the bake shader.
+//This file is not a complete shader by itself, scene::codegen wraps it with
+//the version, layout and image declarations and appends a generated map() and main().
+
+//Mostly taken from http://iquilezles.org/www/articles/distfunctions/distfunctions.htm
+//Every function here has a CPU twin in scene/primitive.rs, keep them in sync!
+
+float dot2( in vec2 v ) { return dot(v,v); }
+float dot2( in vec3 v ) { return dot(v,v); }
 // float ndot( in vec2 a, in vec2 b ) { return a.x*b.x - a.y*b.y; }
 
 float sdSphere(vec3 p, float s) {
     return length(p) - s;
 }
 
-float sdPlane(vec3 p) {
-    return p.y;
+float sdPlane(vec3 p, vec3 n, float h) {
+    return dot(p, n) + h;
+}
+
+float sdBox(vec3 p, vec3 b) {
+    vec3 q = abs(p) - b;
+    return length(max(q, 0.0)) + min(max(q.x, max(q.y, q.z)), 0.0);
+}
+
+float sdRoundBox(vec3 p, vec3 b, float r) {
+    return sdBox(p, b - vec3(r)) - r;
 }
 
-void main() {
-    ivec3 pixel_coords = ivec3(gl_GlobalInvocationID.xyz);
-    vec3 world_pos = pixel_coords;
+float sdTorus(vec3 p, vec2 t) {
+    vec2 q = vec2(length(p.xz) - t.x, p.y);
+    return length(q) - t.y;
+}
+
+float sdCapsule(vec3 p, float h, float r) {
+    p.y -= clamp(p.y, -h, h);
+    return length(p) - r;
+}
 
-    float mat_id = 0.0;
-    float dist = sdSphere(world_pos - vec3(128.0, 32.0, 128.0), 16.0) / SCENE_SCALE;
-    dist = min(dist, sdSphere(world_pos - vec3(128.0, 32.0, 32.0), 16.0) / SCENE_SCALE);
-    dist = min(dist, sdPlane(world_pos - vec3(0.0, 1.0, 0.0)) / SCENE_SCALE);
+float sdCylinder(vec3 p, float h, float r) {
+    vec2 d = abs(vec2(length(p.xz), p.y)) - vec2(r, h);
+    return min(max(d.x, d.y), 0.0) + length(max(d, 0.0));
+}
+
+float sdCappedCone(vec3 p, float h, float r1, float r2) {
+    vec2 q = vec2(length(p.xz), p.y);
+    vec2 k1 = vec2(r2, h);
+    vec2 k2 = vec2(r2 - r1, 2.0 * h);
+    vec2 ca = vec2(q.x - min(q.x, (q.y < 0.0) ? r1 : r2), abs(q.y) - h);
+    vec2 cb = q - k1 + k2 * clamp(dot(k1 - q, k2) / dot2(k2), 0.0, 1.0);
+    float s = (cb.x < 0.0 && ca.y < 0.0) ? -1.0 : 1.0;
+    return s * sqrt(min(dot2(ca), dot2(cb)));
+}
 
-    vec2 pixel_data = vec2(dist, mat_id);
-    vec4 pixel = vec4(pixel_data, pixel_data);
+float sdEllipsoid(vec3 p, vec3 r) {
+    float k0 = length(p / r);
+    float k1 = length(p / (r * r));
+    if (k1 == 0.0) return -min(r.x, min(r.y, r.z));
+    return k0 * (k0 - 1.0) / k1;
+}
+
+float sdHexPrism(vec3 p, vec2 h) {
+    const vec3 k = vec3(-0.8660254, 0.5, 0.57735);
+    p = abs(p);
+    p.xy -= 2.0 * min(dot(k.xy, p.xy), 0.0) * k.xy;
+    vec2 d = vec2(
+        length(p.xy - vec2(clamp(p.x, -k.z * h.x, k.z * h.x), h.x)) * sign(p.y - h.x),
+        p.z - h.y);
+    return min(max(d.x, d.y), 0.0) + length(max(d, 0.0));
+}
+
+float sdLink(vec3 p, float le, float r1, float r2) {
+    vec3 q = vec3(p.x, max(abs(p.y) - le, 0.0), p.z);
+    return length(vec2(length(q.xy) - r1, q.z)) - r2;
+}
+
+//A precomputed distance volume, e.g. a baked mesh. Voxel i sits at origin + i * spacing, distances in world units.
+//Outside of the volume the distance to its bounds is the better estimate. CPU twin is Volume::sample_unbounded.
+float sdVolume(sampler3D volume, vec3 p, vec3 origin, vec3 spacing, vec3 size) {
+    vec3 hi = origin + (size - 1.0) * spacing;
+    vec3 q = clamp(p, origin, hi);
+    float outside = length(p - q);
+    float d = textureLod(volume, ((q - origin) / spacing + 0.5) / size, 0.0).x;
+    return outside > 0.0 ? max(outside, d - outside) : d;
+}
+
+//Terrain covering size on XZ around the origin, from 0 up to height where the map is 1.
+//lipschitz is how much steeper than 1 the terrain gets, dividing by it keeps the estimate from overstepping.
+//The terrain gets cut off at its bounding box. CPU twin is Heightmap::distance.
+float sdHeightmap(sampler2D heightmap, vec3 p, vec2 size, float height, float lipschitz) {
+    vec2 uv = clamp(p.xz / size + 0.5, 0.0, 1.0);
+    float terrain = (p.y - textureLod(heightmap, uv, 0.0).x * height) / lipschitz;
+    vec3 q = abs(p - vec3(0.0, height * 0.5, 0.0)) - vec3(size.x, height, size.y) * 0.5;
+    float bounds = length(max(q, 0.0)) + min(max(q.x, max(q.y, q.z)), 0.0);
+    return max(terrain, bounds);
+}
+
+//Operators work on vec2(distance, material id) so the material of the closest surface survives
+vec2 opUnion(vec2 a, vec2 b) {
+    return (a.x < b.x) ? a : b;
+}
+
+vec2 opSubtraction(vec2 a, vec2 b) {
+    return vec2(max(a.x, -b.x), a.y);
+}
+
+vec2 opIntersection(vec2 a, vec2 b) {
+    return (a.x > b.x) ? a : b;
+}
+
+//Blended operators, from http://iquilezles.org/www/articles/smin/smin.htm and http://mercury.sexy/hg_sdf/
+//Every blend is written as a union, subtraction and intersection follow from max(a, b) = -min(-a, -b).
+//These only work on distances, the generator takes the material from the hard operator.
+//The checks on the radius are explained on the CPU side in scene/operation.rs.
+
+float fSmoothUnion(float a, float b, float k) {
+    if (k <= 0.0) {
+        return min(a, b);
+    }
+    float h = clamp(0.5 + 0.5 * (b - a) / k, 0.0, 1.0);
+    return mix(b, a, h) - k * h * (1.0 - h);
+}
+
+//Shifted by the minimum so exp2 can't overflow on large distances
+float fExpUnion(float a, float b, float k) {
+    if (k <= 0.0) {
+        return min(a, b);
+    }
+    float m = min(a, b);
+    float res = exp2(-(a - m) / k) + exp2(-(b - m) / k);
+    return m - k * log2(res);
+}
+
+float fChamferUnion(float a, float b, float r) {
+    return min(min(a, b), (a - r + b) * sqrt(0.5));
+}
+
+float fStairsUnion(float a, float b, float r, float n) {
+    if (r <= 0.0) {
+        return min(a, b);
+    }
+    float s = r / max(n, 1.0);
+    float u = b - r;
+    return min(min(a, b), 0.5 * (u + a + abs(mod(u - a + s, 2.0 * s) - s)));
+}
+
+//Domain operators, the generator takes care of correcting the distance afterwards where needed.
+//GLSL round() may go either way on .5, so floor(x + 0.5) is used to match the CPU side.
+
+vec3 opRepeat(vec3 p, vec3 c) {
+    return p - c * floor(p / c + 0.5);
+}
+
+vec3 opRepeatLimited(vec3 p, vec3 c, vec3 l) {
+    return p - c * clamp(floor(p / c + 0.5), -l, l);
+}
+
+vec3 opTwist(vec3 p, float k) {
+    float c = cos(k * p.y);
+    float s = sin(k * p.y);
+    return vec3(c * p.x - s * p.z, p.y, s * p.x + c * p.z);
+}
+
+vec3 opBend(vec3 p, float k) {
+    float c = cos(k * p.x);
+    float s = sin(k * p.x);
+    return vec3(c * p.x - s * p.y, s * p.x + c * p.y, p.z);
+}
 
-    imageStore(img_output, pixel_coords, pixel);
+float opElongateInterior(vec3 p, vec3 h) {
+    vec3 q = abs(p) - h;
+    return min(max(q.x, max(q.y, q.z)), 0.0);
 }
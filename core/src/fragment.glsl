@@ -3,8 +3,6 @@ out vec3 frag_color;
 in vec3 origin;
 in vec3 ray;
 
-#define SCENE_SCALE 512
-
 #define MAX_STEPS 1024
 #define DIST_MULT 1.0
 
@@ -34,31 +32,70 @@ struct RaycastHit {
 
 // uniform Material materials[32];
 
-uniform sampler3D depth_tex;
+//Only bricks near the surface are stored in the atlas, every texel of brick_tex is one brick of the scene.
+//r is the slot in the atlas, or -1 for empty bricks, which keep a lower bound of their distance in g.
+//VOLUME_ORIGIN, VOXEL_SPACING and the rest get defined in front of this file, see scene::codegen.
+uniform sampler3D atlas_tex;
+uniform sampler3D brick_tex;
+
+vec3 toVoxel(vec3 position) {
+    return (position - VOLUME_ORIGIN) / VOXEL_SPACING;
+}
+
+//Distance in x, y is 1 if it came from a stored brick and 0 if it's only a bound
+vec2 mapBrick(vec3 position) {
+    ivec3 grid = textureSize(brick_tex, 0);
+    vec3 voxel = toVoxel(position);
+    vec3 clamped = clamp(voxel, vec3(0.0), vec3(grid * BRICK_SIZE) - 0.001);
+    float outside = length((voxel - clamped) * VOXEL_SPACING);
+
+    ivec3 brick = ivec3(clamped / BRICK_SIZE);
+    vec2 entry = texelFetch(brick_tex, brick, 0).xy;
+    if (entry.x < 0.0) {
+        return vec2(max(outside, entry.y * VOXEL_SIZE), 0.0);
+    }
+
+    ivec3 atlas_size = textureSize(atlas_tex, 0);
+    ivec3 slots = atlas_size / BRICK_SAMPLES;
+    int index = int(entry.x);
+    ivec3 slot = ivec3(index % slots.x, (index / slots.x) % slots.y, index / (slots.x * slots.y));
+    //Samples sit on the corners of the cells, so the brick's samples are from texel centre to texel centre
+    vec3 local = clamped - vec3(brick * BRICK_SIZE);
+    vec3 uv = (vec3(slot * BRICK_SAMPLES) + local + 0.5) / vec3(atlas_size);
+    float dist = texture(atlas_tex, uv).x * VOXEL_SIZE;
+
+    //The surface is inside the grid, so outside it both of these are lower bounds
+    if (outside > 0.0) {
+        return vec2(max(outside, dist - outside), 0.0);
+    }
+    return vec2(dist, 1.0);
+}
 
 float map(vec3 position) {
-    // return texture(depth_tex, position / SCENE_SCALE, 0).x * SCENE_SCALE;
-    // return texelFetch(depth_tex, ivec3(position), 0).x * SCENE_SCALE;
-    // return length(position - vec3(5.0)) - 0.5;
-
-    return texture(depth_tex, position / SCENE_SCALE, 0).x * SCENE_SCALE;
-
-    // vec3 bias = (position - floor(position));
-    // float dist = texelFetch(depth_tex, ivec3(position), 0).x * SCENE_SCALE;
-    // float dist_l = texelFetch(depth_tex, ivec3(position) - ivec3(1,0,0), 0).x * SCENE_SCALE * bias.x;
-    // float dist_r = texelFetch(depth_tex, ivec3(position) + ivec3(1,0,0), 0).x * SCENE_SCALE * bias.x;
-    // float dist_u = texelFetch(depth_tex, ivec3(position) + ivec3(0,1,0), 0).x * SCENE_SCALE * bias.y;
-    // float dist_d = texelFetch(depth_tex, ivec3(position) - ivec3(0,1,0), 0).x * SCENE_SCALE * bias.y;
-    // float dist_b = texelFetch(depth_tex, ivec3(position) - ivec3(0,0,1), 0).x * SCENE_SCALE * bias.z;
-    // float dist_f = texelFetch(depth_tex, ivec3(position) + ivec3(0,0,1), 0).x * SCENE_SCALE * bias.z;
-    // return (dist + dist_l + dist_r + dist_u + dist_d + dist_b + dist_f) / 7.0;
+    return mapBrick(position).x;
+}
+
+//Distance along the ray until it leaves the brick around p. Done in voxels, where bricks are cubes,
+//scaling the direction along with the position keeps t the same.
+float brickExit(vec3 p, vec3 rd) {
+    vec3 voxel = toVoxel(p);
+    vec3 dir = rd / VOXEL_SPACING;
+    vec3 corner = floor(voxel / BRICK_SIZE) * BRICK_SIZE + step(0.0, dir) * BRICK_SIZE;
+    vec3 t = (corner - voxel) / max(abs(dir), 1e-6) * sign(dir);
+    t = mix(t, vec3(1e20), equal(dir, vec3(0.0)));
+    return min(min(t.x, t.y), t.z);
+}
+
+//How far rays have to go before they're past the volume
+float volumeFar(vec3 origin) {
+    return length(origin - (VOLUME_ORIGIN + VOLUME_EXTENT * 0.5)) + length(VOLUME_EXTENT) * 0.5;
 }
 
 RaycastHit castRay(vec3 origin, vec3 direction) {
     RaycastHit hit;
 
     float tmin = 0.02;
-    float tmax = 512.0;
+    float tmax = volumeFar(origin);
 
     float t = tmin;
 
@@ -68,7 +105,14 @@ RaycastHit castRay(vec3 origin, vec3 direction) {
             hit.dist = -1.0;
             break;
         }
-        float dist = map(origin + direction * t);
+        vec3 p = origin + direction * t;
+        vec2 brick = mapBrick(p);
+        //Nothing to hit in empty bricks, so they get skipped as a whole
+        if (brick.y == 0.0) {
+            t += max(brick.x, brickExit(p, direction) + 0.01 * VOXEL_SIZE);
+            continue;
+        }
+        float dist = brick.x;
         if (abs(dist) < 0.001 * t) {
             hit.colour = vec3(1.0);
             hit.dist = t;
@@ -86,7 +130,15 @@ float calcSoftshadow( in vec3 ro, in vec3 rd, in float tmin, in float tmax, floa
     float res = 1.0;
     float ph = 1e20;
     for (float t=tmin; t<tmax;) {
-        float h = map(ro + rd * t);
+        vec3 p = ro + rd * t;
+        vec2 brick = mapBrick(p);
+        //Empty bricks are far enough from the surface to not matter for the penumbra
+        if (brick.y == 0.0) {
+            t += max(brick.x, brickExit(p, rd) + 0.01 * VOXEL_SIZE);
+            ph = 1e20;
+            continue;
+        }
+        float h = brick.x;
         if (h < 0.001)
             return 0.0;
         float y = h*h/(2.0*ph);
@@ -138,7 +190,7 @@ void main() {
     if (hit.dist >= 0) {
         vec3 hit_pos = origin + rayDir * hit.dist;
         vec3 normal = calcNormal(hit_pos);
-        float attenuation = calcSoftshadow(hit_pos, -LIGHT_DIR, 0.02, 512.0, 2.0);
+        float attenuation = calcSoftshadow(hit_pos, -LIGHT_DIR, 0.02, volumeFar(hit_pos), 2.0);
         frag_color = hit.colour * dot(normal, -LIGHT_DIR) * attenuation;
         // frag_color = normal;
         // frag_color = pow(frag_color, vec3(0.4545));
@@ -21,6 +21,13 @@ use luminance::{
 };
 
 mod render;
+mod scene;
+mod volume;
+mod mesh;
+mod ui;
+mod watch;
+
+use render::shaders::ShaderSources;
 
 fn main() {
     pretty_env_logger::formatted_builder()
@@ -29,6 +36,20 @@ fn main() {
 
     debug!("Hello, world!");
 
+    //Usage: core [scene.ron] [--hot-reload] [--cpu-bake]
+    let mut scene_path = None;
+    let mut hot_reload = false;
+    let mut cpu_bake = false;
+    for arg in std::env::args().skip(1) {
+        if arg == "--hot-reload" {
+            hot_reload = true;
+        } else if arg == "--cpu-bake" {
+            cpu_bake = true;
+        } else {
+            scene_path = Some(arg);
+        }
+    }
+
     let (mut surface, gl, _gl_context) = open_window(1280, 720).expect("Failed to open window!");
 
     let mut imgui = imgui::Context::create();
@@ -79,43 +100,175 @@ fn main() {
 
     render::initialize(&gl);
 
+    //Without compute shaders the bake runs on the CPU, which also works everywhere else with --cpu-bake
+    let compute = !cpu_bake && render::supports_compute(&gl);
+    if !compute {
+        info!("Baking on the CPU");
+    }
+
     let screen_rect = render::get_screen_rect(&mut surface);
-    let program = render::get_program(include_str!("vertex.glsl"), include_str!("fragment.glsl"));
+    //With hot reloading on, the shaders get read from the source directory and watched for changes
+    let shader_dir = ShaderSources::source_dir();
+    let mut shader_sources = if hot_reload {
+        ShaderSources::load(&shader_dir).unwrap_or_else(|err| {
+            error!("Failed to load shaders from {}, falling back to the embedded ones: {}", shader_dir.display(), err);
+            ShaderSources::embedded()
+        })
+    } else {
+        ShaderSources::embedded()
+    };
+    let shader_watcher = if hot_reload {
+        match watch::FileWatcher::new(ShaderSources::paths(&shader_dir)) {
+            Ok(watcher) => Some(watcher),
+            Err(err) => {
+                error!("Failed to watch shaders: {}", err);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    //Compile errors show up in the UI instead of killing the viewer.
+    //The embedded shaders are known to work, so startup falls back on those until the files are fixed.
+    let mut render_error: Option<render::ShaderError> = None;
+    let mut bake_error: Option<render::ShaderError> = None;
+
+    //Size and layout of the baked volume, both shaders and the textures get built from it
+    let mut shader_options = scene::codegen::ShaderOptions::default();
+    let mut program = match render::get_program(&shader_sources.vertex, &scene::codegen::generate_fragment_shader(&shader_sources.fragment, &shader_options)) {
+        Ok(program) => program,
+        Err(err) => {
+            error!("{}", err);
+            render_error = Some(err);
+            let embedded = ShaderSources::embedded();
+            render::get_program(&embedded.vertex, &scene::codegen::generate_fragment_shader(&embedded.fragment, &shader_options)).expect("Failed to compile the embedded shaders!")
+        }
+    };
     let render_state = RenderState::default();
 
-    let work_group_count = render::get_workgroup_count(&gl);
-    debug!("Max global work group counts: [x: {}; y: {}; z: {}]", work_group_count.0, work_group_count.1, work_group_count.2);
-    let work_group_size = render::get_workgroup_size(&gl);
-    debug!("Max local work group size: [x: {}; y: {}; z: {}]", work_group_size.0, work_group_size.1, work_group_size.2);
-    let work_group_invoc = render::get_workgroup_invocations(&gl);
-    debug!("Max local work group invocations: {}", work_group_invoc);
+    if compute {
+        let work_group_count = render::get_workgroup_count(&gl);
+        debug!("Max global work group counts: [x: {}; y: {}; z: {}]", work_group_count.0, work_group_count.1, work_group_count.2);
+        let work_group_size = render::get_workgroup_size(&gl);
+        debug!("Max local work group size: [x: {}; y: {}; z: {}]", work_group_size.0, work_group_size.1, work_group_size.2);
+        let work_group_invoc = render::get_workgroup_invocations(&gl);
+        debug!("Max local work group invocations: {}", work_group_invoc);
+    }
 
     let st_now = Instant::now();
-    let scene_tex = render::get_3d_texture(&gl, 512, 512, 512);
-    debug!("Creating 3d texture took {} ms", (Instant::now() - st_now).as_millis());
-    let depth_shader = render::get_compute_program(&gl, include_str!("compute.glsl"));
+    let mut brick_map = shader_options.volume.brick_map();
+    let mut brick_textures = render::BrickTextures::new(&gl, &brick_map, shader_options.volume.format);
+    debug!("Creating brick atlas took {} ms", (Instant::now() - st_now).as_millis());
+    let mut scene = match &scene_path {
+        Some(path) => scene::file::load(path).unwrap_or_else(|err| {
+            error!("{}", err);
+            scene::Scene::default()
+        }),
+        None => scene::Scene::default(),
+    };
+    apply_scene_camera(&scene, &mut camera, &mut cam_rot_x, &mut cam_rot_y);
+    let mut asset_cache = scene::AssetCache::new();
+    let mut scene_dir = scene::assets::scene_dir(scene_path.as_deref().unwrap_or(""));
+    for err in asset_cache.resolve(&mut scene, &scene_dir).errors {
+        error!("{}", err);
+    }
+    let mut volume_textures = render::VolumeTextures::new();
+    let mut heightmap_textures = render::HeightmapTextures::new();
+    let mut depth_shader = if compute {
+        let compute_src = scene::codegen::generate_compute_shader(&scene, &shader_sources.compute, &shader_options);
+        Some(match render::get_compute_program(&gl, &compute_src) {
+            Ok(program) => program,
+            Err(err) => {
+                error!("{}", err);
+                bake_error = Some(err);
+                let compute_src = scene::codegen::generate_compute_shader(&scene, &ShaderSources::embedded().compute, &shader_options);
+                render::get_compute_program(&gl, &compute_src).expect("Failed to compile the embedded bake shader!")
+            }
+        })
+    } else {
+        None
+    };
+    let mut scene_editor = ui::SceneEditor::new(scene_path.as_deref().unwrap_or("scene.ron"));
+    let mut export_window = ui::ExportWindow::new();
+    let mut import_window = ui::ImportWindow::new();
+    let mut volume_window = ui::VolumeWindow::new(&shader_options.volume);
+    //What the volume holds, so edits only re-bake where they happened
+    let mut dirty_tracker = scene::DirtyTracker::new();
+    //The scene file gets watched as well, so it can be edited in a text editor while the viewer is open
+    let mut scene_watcher = scene_path.as_ref().and_then(|path| watch_scene(path));
 
     debug!("Setup complete!");
 
-    let st_fill_now = Instant::now();
-    unsafe {
-        // gl::BindImageTexture(0, scene_tex, 0, gl::TRUE, 0, gl::WRITE_ONLY, gl::RGBA32F);
-        gl.use_program(Some(depth_shader));
-        gl.active_texture(glow::TEXTURE0);
-        gl.bind_texture(glow::TEXTURE_3D, Some(scene_tex));
-        gl.uniform_1_i32(gl.get_uniform_location(depth_shader, "img_output"), 0);
-        gl.dispatch_compute(512 / 8, 512 / 8, 512 / 8);
-        gl::MemoryBarrier(gl::SHADER_IMAGE_ACCESS_BARRIER_BIT);
-        // gl.use_program(None);
-        // gl.bind_texture(glow::TEXTURE_3D, None);
-    }
-    let st_fill_duration = Instant::now() - st_fill_now;
-    debug!("Filling 3d texture took {} ms", st_fill_duration.as_millis() as f32 + (st_fill_duration.as_nanos() as f32 / 1_000_000.0));
+    //The first bake runs over the first frames like any other, so big volumes don't hold up the window
+    let baker = match depth_shader {
+        Some(program) => render::bake::Baker::Compute {
+            program,
+            volumes: volume_textures.update(&gl, &scene.mesh_volumes()),
+            heightmaps: heightmap_textures.update(&gl, &scene.height_fields()),
+        },
+        None => render::bake::Baker::Cpu { scene: scene.clone(), config: shader_options.volume },
+    };
+    let mut bake_job = Some(start_bake(baker, None, &scene, &dirty_tracker.update(&scene), &mut brick_map, &brick_textures, &shader_options.volume));
 
     'main: loop {
         let back_buffer = surface.back_buffer().expect("Couldn't get the back buffer!");
 
+        let mut rebake = false;
+
         //Update
+        if let Some((path, watcher)) = &scene_watcher {
+            if !watcher.poll().is_empty() {
+                match scene::file::load(path) {
+                    Ok(new_scene) => {
+                        //Keep the camera where it is, having it jump back on every save gets old fast
+                        rebake |= new_scene.nodes != scene.nodes;
+                        let camera = scene.camera;
+                        scene = new_scene;
+                        scene.camera = camera;
+                        scene_editor.status = Some(format!("Reloaded {}", path));
+                        debug!("Reloaded scene {}", path);
+                    },
+                    Err(err) => {
+                        error!("{}", err);
+                        scene_editor.status = Some(format!("{}\nKeeping the last valid scene.", err));
+                    },
+                }
+            }
+        }
+
+        if let Some(watcher) = &shader_watcher {
+            let changed = watcher.poll();
+            if !changed.is_empty() {
+                match ShaderSources::load(&shader_dir) {
+                    Ok(sources) => {
+                        shader_sources = sources;
+                        let compute_changed = changed.iter().any(|p| p.ends_with(render::shaders::COMPUTE_FILE));
+                        let render_changed = changed.iter().any(|p| !p.ends_with(render::shaders::COMPUTE_FILE));
+                        if render_changed {
+                            match render::get_program(&shader_sources.vertex, &scene::codegen::generate_fragment_shader(&shader_sources.fragment, &shader_options)) {
+                                Ok(new_program) => {
+                                    program = new_program;
+                                    render_error = None;
+                                    debug!("Reloaded render shaders");
+                                },
+                                Err(err) => {
+                                    error!("Failed to reload render shaders, keeping the previous ones:\n{}", err);
+                                    render_error = Some(err);
+                                },
+                            }
+                        }
+                        //New distance functions could change anything
+                        if compute_changed {
+                            dirty_tracker.invalidate();
+                            rebake = true;
+                        }
+                    },
+                    Err(err) => error!("Failed to read shaders: {}", err),
+                }
+            }
+        }
+
         //Cuts fps in half on laptop, but laptop gets much worse performance than my pc :)
         //Updating a 128x128x128 texture fully each frame drops
         //my gtx1080's performance from 1700 fps to about 250
@@ -212,12 +365,16 @@ fn main() {
                     unsafe {
                         gl.use_program(Some(handle.handle()));
 
-                        let loc = gl.get_uniform_location(handle.handle(), "depth_tex");
+                        let loc = gl.get_uniform_location(handle.handle(), "atlas_tex");
                         gl.uniform_1_i32(loc, 0);
+                        let loc = gl.get_uniform_location(handle.handle(), "brick_tex");
+                        gl.uniform_1_i32(loc, 1);
 
                         // gl::BindImageTexture(0, scene_tex, 0, gl::TRUE, 0, gl::READ_WRITE, gl::RGBA32F);
+                        gl.active_texture(glow::TEXTURE1);
+                        gl.bind_texture(glow::TEXTURE_3D, Some(brick_textures.indirection));
                         gl.active_texture(glow::TEXTURE0);
-                        gl.bind_texture(glow::TEXTURE_3D, Some(scene_tex));
+                        gl.bind_texture(glow::TEXTURE_3D, Some(brick_textures.atlas));
                     }
 
                     iface.inv_projection_view.update(inv_projview_matrix.into());
@@ -251,33 +408,212 @@ fn main() {
             ui.text(format!("MS: {:.2}", delta_s * 1000.0));
         });
 
+        scene.camera.position = camera.position;
+        scene.camera.pitch = cam_rot_x;
+        scene.camera.yaw = cam_rot_y;
+        scene.camera.fovy = camera.fovy;
+        let editor_response = scene_editor.build(&ui, &mut scene);
+        let read_back_volume = export_window.build(&ui, &scene, &shader_options.volume);
+        let imported_volume = import_window.build(&ui, &shader_options.volume);
+        let volume_config = volume_window.build(&ui, &shader_options.volume);
+
+        let shader_errors: Vec<&render::ShaderError> = render_error.iter().chain(bake_error.iter()).collect();
+        if !shader_errors.is_empty() {
+            ui::shader_error_window(&ui, &shader_errors);
+        }
+        if let Some(job) = &bake_job {
+            ui::bake_progress_window(&ui, job);
+        }
+
         imgui_sdl2.prepare_render(&ui, &surface.window);
         renderer.render(ui);
 
+        if editor_response == ui::EditorResponse::Loaded {
+            apply_scene_camera(&scene, &mut camera, &mut cam_rot_x, &mut cam_rot_y);
+            scene_dir = scene::assets::scene_dir(scene_editor.path.to_str());
+            scene_watcher = watch_scene(scene_editor.path.to_str());
+        }
+
+        rebake |= editor_response != ui::EditorResponse::Unchanged;
+
+        //A new layout needs new textures and both shaders rebuilt, the bake shader gets done with the re-bake below
+        if let Some(config) = volume_config {
+            bake_job = None;
+            shader_options.volume = config;
+            brick_map = shader_options.volume.brick_map();
+            std::mem::replace(&mut brick_textures, render::BrickTextures::new(&gl, &brick_map, shader_options.volume.format)).delete(&gl);
+            dirty_tracker.invalidate();
+            match render::get_program(&shader_sources.vertex, &scene::codegen::generate_fragment_shader(&shader_sources.fragment, &shader_options)) {
+                Ok(new_program) => {
+                    program = new_program;
+                    render_error = None;
+                },
+                Err(err) => {
+                    error!("Failed to rebuild render shaders for the new volume:\n{}", err);
+                    render_error = Some(err);
+                },
+            }
+            rebake = true;
+        }
+
+        if read_back_volume {
+            if bake_job.is_some() {
+                warn!("Exporting the volume before the bake is done, parts of it are still missing or out of date");
+            }
+            let volume = render::read_volume(&brick_textures, &brick_map, &shader_options.volume);
+            export_window.save_volume(&volume);
+        }
+
+        if let Some(volume) = imported_volume {
+            bake_job = None;
+            render::upload_volume(&brick_textures, &mut brick_map, &volume, &shader_options.volume);
+            dirty_tracker.invalidate();
+        }
+
+        //Meshes and heightmaps get loaded as soon as they show up in the scene, from the editor or a reload,
+        //or once a file that failed to load changes
+        let resolved = asset_cache.resolve(&mut scene, &scene_dir);
+        for err in resolved.errors {
+            error!("{}", err);
+            scene_editor.status = Some(err);
+        }
+        for &index in &resolved.loaded {
+            dirty_tracker.mark(&scene.nodes[index]);
+            rebake = true;
+        }
+
+        //If the new bake shader doesn't compile, the volume keeps whatever the last working one produced
+        if rebake {
+            let baker = if compute {
+                let compute_src = scene::codegen::generate_compute_shader(&scene, &shader_sources.compute, &shader_options);
+                match render::get_compute_program(&gl, &compute_src) {
+                    Ok(new_shader) => {
+                        if let Some(old_shader) = depth_shader.replace(new_shader) {
+                            unsafe {
+                                gl.delete_program(old_shader);
+                            }
+                        }
+                        bake_error = None;
+                        Some(render::bake::Baker::Compute {
+                            program: new_shader,
+                            volumes: volume_textures.update(&gl, &scene.mesh_volumes()),
+                            heightmaps: heightmap_textures.update(&gl, &scene.height_fields()),
+                        })
+                    },
+                    Err(err) => {
+                        error!("Failed to compile bake shader, keeping the previous one:\n{}", err);
+                        bake_error = Some(err);
+                        None
+                    },
+                }
+            } else {
+                Some(render::bake::Baker::Cpu { scene: scene.clone(), config: shader_options.volume })
+            };
+            if let Some(baker) = baker {
+                bake_job = Some(start_bake(baker, bake_job.take(), &scene, &dirty_tracker.update(&scene), &mut brick_map, &brick_textures, &shader_options.volume));
+            }
+        }
+
+        if let Some(job) = &mut bake_job {
+            job.step(&gl, &brick_map, &brick_textures, shader_options.brick_binding);
+            if job.is_done() {
+                bake_job = None;
+            }
+        }
+
         surface.swap_buffer();
     }
 }
 
-fn open_window(width: u32, height: u32) -> Result<(luminance_sdl2::SDL2Surface, glow::Context, sdl2::video::GLContext), &'static str> {
-    let surface = luminance_sdl2::SDL2Surface::new(
-        (4, 5), //Opengl version
-        "SDF Modelling",
-        (width, height),
-        false //VSync
-    );
-
-    match surface {
-        Err(e) => {
-            error!("Couldn't initialize photic!\n{}", e);
-            return Err("Couldn't initialize photic!");
+//Starts baking whatever changed since the last bake. A bake that's still running gets cancelled,
+//the bricks it didn't get to yet are still stale so the new one takes them over.
+fn start_bake(baker: render::bake::Baker, running: Option<render::bake::BakeJob>, scene: &scene::Scene, dirty: &scene::Dirty, brick_map: &mut volume::bricks::BrickMap, brick_textures: &render::BrickTextures, config: &volume::config::VolumeConfig) -> render::bake::BakeJob {
+    let mut bricks = plan_bake(scene, dirty, brick_map, brick_textures, config);
+    if let Some(job) = running {
+        if !job.is_done() {
+            debug!("Cancelled bake with {} of {} bricks left", job.remaining().len(), job.len());
+            bricks.extend_from_slice(job.remaining());
+        }
+    }
+    render::bake::BakeJob::new(baker, bricks)
+}
+
+//Picks the bricks near the surface of the scene and returns the ones that need baking. Picking them is cheap and keeps
+//the bounds of the empty bricks right, the bake only runs on new bricks and the ones near whatever changed.
+fn plan_bake(scene: &scene::Scene, dirty: &scene::Dirty, brick_map: &mut volume::bricks::BrickMap, brick_textures: &render::BrickTextures, config: &volume::config::VolumeConfig) -> Vec<usize> {
+    //Smooth blends and some modifiers overestimate, dividing by the Lipschitz bound keeps the distances lower bounds.
+    //The brick map works in voxels, so positions and distances get converted on the way.
+    let scale = scene.lipschitz() * config.voxel_size();
+    let update = brick_map.update(|v| scene.distance(config.position(v)) / scale);
+    if update.overflow > 0 {
+        warn!("{} bricks near the surface didn't fit in the atlas and will show up as holes", update.overflow);
+    }
+    brick_textures.upload_indirection(brick_map);
+    brick_textures.clear_slots(brick_map, &update.allocated);
+
+    let bricks = match dirty {
+        scene::Dirty::Everything => brick_map.stored_indices(),
+        scene::Dirty::Nothing => update.allocated.clone(),
+        //Further than the reach of the bricks from a changed node, it can't be the closest thing in any stored sample
+        scene::Dirty::Regions(regions) => {
+            let mut indices = update.allocated.clone();
+            for (bounds, lipschitz) in regions {
+                let bounds = bounds.expand(volume::bricks::BrickMap::reach() * scale * lipschitz);
+                indices.extend(brick_map.stored_within(config.to_voxel(bounds.min), config.to_voxel(bounds.max)));
+            }
+            indices.sort_unstable();
+            indices.dedup();
+            indices
         },
-        Ok(surface) => {
-            let gl_context = surface.window.gl_create_context().expect("Couldn't create GL context");
-            let gl = glow::Context::from_loader_function(|s| {
-                    surface.video.gl_get_proc_address(s) as *const c_void
-                });
-            debug!("Photic initialized!");
-            return Ok((surface, gl, gl_context));
+    };
+    debug!("Baking {} of {} bricks, {} new and {} released", bricks.len(), update.stored, update.allocated.len(), update.released);
+    bricks
+}
+
+fn watch_scene(path: &str) -> Option<(String, watch::FileWatcher)> {
+    match watch::FileWatcher::new(vec![path.into()]) {
+        Ok(watcher) => Some((path.to_string(), watcher)),
+        Err(err) => {
+            error!("Failed to watch scene file {}: {}", path, err);
+            None
         }
     }
 }
+
+fn apply_scene_camera(scene: &scene::Scene, camera: &mut render::camera::Camera, cam_rot_x: &mut f32, cam_rot_y: &mut f32) {
+    camera.position = scene.camera.position;
+    camera.fovy = scene.camera.fovy;
+    *cam_rot_x = scene.camera.pitch;
+    *cam_rot_y = scene.camera.yaw;
+}
+
+//Compute shaders need 4.5, older drivers still get a window and bake on the CPU
+const GL_VERSIONS: [(u8, u8); 2] = [(4, 5), (3, 3)];
+
+fn open_window(width: u32, height: u32) -> Result<(luminance_sdl2::SDL2Surface, glow::Context, sdl2::video::GLContext), &'static str> {
+    for &version in GL_VERSIONS.iter() {
+        let surface = luminance_sdl2::SDL2Surface::new(
+            version, //Opengl version
+            "SDF Modelling",
+            (width, height),
+            false //VSync
+        );
+
+        match surface {
+            Err(e) => {
+                warn!("Couldn't initialize photic with OpenGL {}.{}\n{}", version.0, version.1, e);
+            },
+            Ok(surface) => {
+                let gl_context = surface.window.gl_create_context().expect("Couldn't create GL context");
+                let gl = glow::Context::from_loader_function(|s| {
+                        surface.video.gl_get_proc_address(s) as *const c_void
+                    });
+                debug!("Photic initialized with OpenGL {}.{}!", version.0, version.1);
+                return Ok((surface, gl, gl_context));
+            }
+        }
+    }
+
+    error!("Couldn't initialize photic!");
+    Err("Couldn't initialize photic!")
+}
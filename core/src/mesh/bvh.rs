@@ -0,0 +1,386 @@
+use std::f32::consts::PI;
+
+use cgmath::*;
+
+use super::Mesh;
+
+//How far away a cluster of triangles has to be, relative to its size, before
+//its winding number contribution gets approximated by a single dipole
+const WINDING_ACCURACY: f32 = 2.0;
+const LEAF_SIZE: usize = 4;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Triangle {
+    pub a: Vector3<f32>,
+    pub b: Vector3<f32>,
+    pub c: Vector3<f32>,
+    //Index of the triangle in the mesh
+    pub index: u32,
+}
+
+impl Triangle {
+    fn centroid(&self) -> Vector3<f32> {
+        (self.a + self.b + self.c) / 3.0
+    }
+
+    //Half the cross product, so its length is the area
+    fn area_normal(&self) -> Vector3<f32> {
+        (self.b - self.a).cross(self.c - self.a) * 0.5
+    }
+
+    //From Real-Time Collision Detection by Christer Ericson
+    pub fn closest_point(&self, p: Vector3<f32>) -> Vector3<f32> {
+        let (a, b, c) = (self.a, self.b, self.c);
+        let ab = b - a;
+        let ac = c - a;
+        let ap = p - a;
+        let d1 = ab.dot(ap);
+        let d2 = ac.dot(ap);
+        if d1 <= 0.0 && d2 <= 0.0 {
+            return a;
+        }
+
+        let bp = p - b;
+        let d3 = ab.dot(bp);
+        let d4 = ac.dot(bp);
+        if d3 >= 0.0 && d4 <= d3 {
+            return b;
+        }
+
+        let vc = d1 * d4 - d3 * d2;
+        if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+            return a + ab * (d1 / (d1 - d3));
+        }
+
+        let cp = p - c;
+        let d5 = ab.dot(cp);
+        let d6 = ac.dot(cp);
+        if d6 >= 0.0 && d5 <= d6 {
+            return c;
+        }
+
+        let vb = d5 * d2 - d1 * d6;
+        if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+            return a + ac * (d2 / (d2 - d6));
+        }
+
+        let va = d3 * d6 - d5 * d4;
+        if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+            return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+        }
+
+        let denom = 1.0 / (va + vb + vc);
+        a + ab * (vb * denom) + ac * (vc * denom)
+    }
+
+    //Möller-Trumbore, returns the distance along the ray
+    pub fn intersect(&self, origin: Vector3<f32>, dir: Vector3<f32>) -> Option<f32> {
+        let e1 = self.b - self.a;
+        let e2 = self.c - self.a;
+        let h = dir.cross(e2);
+        let det = e1.dot(h);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv = 1.0 / det;
+        let s = origin - self.a;
+        let u = s.dot(h) * inv;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = s.cross(e1);
+        let v = dir.dot(q) * inv;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = e2.dot(q) * inv;
+        if t > 0.0 {
+            Some(t)
+        } else {
+            None
+        }
+    }
+
+    //Signed solid angle seen from p, Van Oosterom and Strackee
+    fn solid_angle(&self, p: Vector3<f32>) -> f32 {
+        let a = self.a - p;
+        let b = self.b - p;
+        let c = self.c - p;
+        let (la, lb, lc) = (a.magnitude(), b.magnitude(), c.magnitude());
+        let det = a.dot(b.cross(c));
+        let denom = la * lb * lc + a.dot(b) * lc + b.dot(c) * la + c.dot(a) * lb;
+        2.0 * det.atan2(denom)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct BvhNode {
+    min: Vector3<f32>,
+    max: Vector3<f32>,
+    //Leaves have a count and index into the triangles, inner nodes have their children at first and first + 1
+    first: usize,
+    count: usize,
+    //Area weighted normal sum and centroid, plus the radius around it, for the winding number far field
+    normal: Vector3<f32>,
+    centre: Vector3<f32>,
+    radius: f32,
+}
+
+impl BvhNode {
+    fn distance2(&self, p: Vector3<f32>) -> f32 {
+        let dx = (self.min.x - p.x).max(p.x - self.max.x).max(0.0);
+        let dy = (self.min.y - p.y).max(p.y - self.max.y).max(0.0);
+        let dz = (self.min.z - p.z).max(p.z - self.max.z).max(0.0);
+        dx * dx + dy * dy + dz * dz
+    }
+
+    //Slab test, only needs to know whether the ray hits at all
+    fn hit(&self, origin: Vector3<f32>, inv_dir: Vector3<f32>) -> bool {
+        let mut tmin = 0.0f32;
+        let mut tmax = f32::INFINITY;
+        for i in 0..3 {
+            let t1 = (self.min[i] - origin[i]) * inv_dir[i];
+            let t2 = (self.max[i] - origin[i]) * inv_dir[i];
+            tmin = tmin.max(t1.min(t2));
+            tmax = tmax.min(t1.max(t2));
+        }
+        tmin <= tmax
+    }
+}
+
+//Bounding volume hierarchy over the triangles of a mesh, split at the median of the longest axis
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    triangles: Vec<Triangle>,
+}
+
+impl Bvh {
+    pub fn new(mesh: &Mesh) -> Bvh {
+        let triangles = mesh.triangles().enumerate().map(|(i, t)| Triangle {
+            a: mesh.positions[t[0] as usize],
+            b: mesh.positions[t[1] as usize],
+            c: mesh.positions[t[2] as usize],
+            index: i as u32,
+        }).collect();
+
+        let mut bvh = Bvh {
+            nodes: Vec::new(),
+            triangles,
+        };
+        if !bvh.triangles.is_empty() {
+            bvh.nodes.push(bvh.leaf(0, bvh.triangles.len()));
+            bvh.split(0);
+        }
+        bvh
+    }
+
+    pub fn triangles(&self) -> &[Triangle] {
+        &self.triangles
+    }
+
+    fn leaf(&self, first: usize, count: usize) -> BvhNode {
+        let triangles = &self.triangles[first..first + count];
+        let mut min = Vector3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+        let mut max = -min;
+        let mut normal = Vector3::zero();
+        let mut weighted = Vector3::zero();
+        let mut area = 0.0;
+        for t in triangles {
+            for v in &[t.a, t.b, t.c] {
+                min = Vector3::new(min.x.min(v.x), min.y.min(v.y), min.z.min(v.z));
+                max = Vector3::new(max.x.max(v.x), max.y.max(v.y), max.z.max(v.z));
+            }
+            let n = t.area_normal();
+            normal += n;
+            weighted += t.centroid() * n.magnitude();
+            area += n.magnitude();
+        }
+        let centre = if area > 0.0 { weighted / area } else { (min + max) * 0.5 };
+        let mut radius = 0.0f32;
+        for t in triangles {
+            for v in &[t.a, t.b, t.c] {
+                radius = radius.max((v - centre).magnitude());
+            }
+        }
+
+        BvhNode {
+            min,
+            max,
+            first,
+            count,
+            normal,
+            centre,
+            radius,
+        }
+    }
+
+    fn split(&mut self, node: usize) {
+        let BvhNode { first, count, min, max, .. } = self.nodes[node];
+        if count <= LEAF_SIZE {
+            return;
+        }
+
+        let size = max - min;
+        let axis = if size.x >= size.y && size.x >= size.z { 0 } else if size.y >= size.z { 1 } else { 2 };
+        let half = count / 2;
+        self.triangles[first..first + count].sort_by(|a, b| {
+            a.centroid()[axis].partial_cmp(&b.centroid()[axis]).unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        let left = self.nodes.len();
+        let left_node = self.leaf(first, half);
+        let right_node = self.leaf(first + half, count - half);
+        self.nodes.push(left_node);
+        self.nodes.push(right_node);
+        self.nodes[node].first = left;
+        self.nodes[node].count = 0;
+
+        self.split(left);
+        self.split(left + 1);
+    }
+
+    //Closest point on the mesh, along with the triangle it's on
+    pub fn closest(&self, p: Vector3<f32>) -> Option<(Vector3<f32>, &Triangle)> {
+        let mut best: Option<(f32, Vector3<f32>, &Triangle)> = None;
+        let mut stack = vec![0];
+        while let Some(i) = stack.pop() {
+            let node = match self.nodes.get(i) {
+                Some(node) => node,
+                None => break,
+            };
+            if let Some((d2, _, _)) = best {
+                if node.distance2(p) >= d2 {
+                    continue;
+                }
+            }
+
+            if node.count > 0 {
+                for t in &self.triangles[node.first..node.first + node.count] {
+                    let q = t.closest_point(p);
+                    let d2 = (q - p).magnitude2();
+                    if best.map_or(true, |(best_d2, _, _)| d2 < best_d2) {
+                        best = Some((d2, q, t));
+                    }
+                }
+            } else {
+                //Visit the nearer child first, so the other one is more likely to get culled
+                let (a, b) = (node.first, node.first + 1);
+                if self.nodes[a].distance2(p) < self.nodes[b].distance2(p) {
+                    stack.push(b);
+                    stack.push(a);
+                } else {
+                    stack.push(a);
+                    stack.push(b);
+                }
+            }
+        }
+        best.map(|(_, q, t)| (q, t))
+    }
+
+    //Every crossing of the ray, without caring about the order
+    pub fn count_hits(&self, origin: Vector3<f32>, dir: Vector3<f32>) -> usize {
+        if self.nodes.is_empty() {
+            return 0;
+        }
+        let inv_dir = Vector3::new(1.0 / dir.x, 1.0 / dir.y, 1.0 / dir.z);
+        let mut hits = 0;
+        let mut stack = vec![0];
+        while let Some(i) = stack.pop() {
+            let node = &self.nodes[i];
+            if !node.hit(origin, inv_dir) {
+                continue;
+            }
+            if node.count > 0 {
+                hits += self.triangles[node.first..node.first + node.count].iter().filter(|t| t.intersect(origin, dir).is_some()).count();
+            } else {
+                stack.push(node.first);
+                stack.push(node.first + 1);
+            }
+        }
+        hits
+    }
+
+    //Generalized winding number, 1 inside a closed mesh and 0 outside, somewhere in between
+    //around holes. Far away clusters are treated as a single dipole, after Barill et al. 2018.
+    pub fn winding_number(&self, p: Vector3<f32>) -> f32 {
+        if self.nodes.is_empty() {
+            return 0.0;
+        }
+        let mut angle = 0.0;
+        let mut stack = vec![0];
+        while let Some(i) = stack.pop() {
+            let node = &self.nodes[i];
+            let offset = node.centre - p;
+            let dist = offset.magnitude();
+            if dist > WINDING_ACCURACY * node.radius {
+                angle += offset.dot(node.normal) / (dist * dist * dist);
+            } else if node.count > 0 {
+                angle += self.triangles[node.first..node.first + node.count].iter().map(|t| t.solid_angle(p)).sum::<f32>();
+            } else {
+                stack.push(node.first);
+                stack.push(node.first + 1);
+            }
+        }
+        angle / (4.0 * PI)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::{mesh_scene, Mesher};
+    use crate::scene::{Scene, Node, Primitive};
+
+    //A closed sphere of radius 10 around the origin, big enough for a few levels of nodes
+    fn sphere() -> Mesh {
+        let mut scene = Scene::new();
+        scene.add(Node::primitive(Primitive::Sphere { radius: 10.0 }, 0));
+        mesh_scene(&scene, 24, Vector3::new(-12.0, -12.0, -12.0), Vector3::new(24.0, 24.0, 24.0), Mesher::MarchingCubes)
+    }
+
+    fn points() -> Vec<Vector3<f32>> {
+        let mut state = 777u32;
+        let mut next = || {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 8) as f32 / (1 << 24) as f32 * 2.0 - 1.0
+        };
+        (0..200).map(|_| Vector3::new(next(), next(), next()) * 20.0).collect()
+    }
+
+    #[test]
+    fn closest_matches_brute_force() {
+        let mesh = sphere();
+        let bvh = Bvh::new(&mesh);
+        assert_eq!(bvh.triangles().len(), mesh.triangle_count());
+        for p in points() {
+            let (closest, triangle) = bvh.closest(p).unwrap();
+            let expected = bvh.triangles().iter().map(|t| (t.closest_point(p) - p).magnitude()).fold(f32::INFINITY, f32::min);
+            assert!(((closest - p).magnitude() - expected).abs() < 1e-4, "{:?}", p);
+            assert!((triangle.closest_point(p) - closest).magnitude() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn inside_and_outside() {
+        let mesh = sphere();
+        let bvh = Bvh::new(&mesh);
+        let mut flipped = mesh.clone();
+        for t in flipped.indices.chunks_exact_mut(3) {
+            t.swap(1, 2);
+        }
+        let flipped = Bvh::new(&flipped);
+
+        for p in points() {
+            //Away from the surface, where the faceted mesh and the sphere agree
+            let dist = p.magnitude() - 10.0;
+            if dist.abs() < 1.0 {
+                continue;
+            }
+            let inside = dist < 0.0;
+            let expected = if inside { 1.0 } else { 0.0 };
+            assert!((bvh.winding_number(p) - expected).abs() < 0.05, "winding number {} at {:?}", bvh.winding_number(p), p);
+            assert!((flipped.winding_number(p) + expected).abs() < 0.05);
+            assert_eq!(bvh.count_hits(p, Vector3::new(0.000_321, 1.0, 0.000_234)) % 2 == 1, inside, "{:?}", p);
+        }
+    }
+}
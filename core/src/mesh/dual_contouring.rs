@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+
+use cgmath::*;
+
+use super::Mesh;
+use super::qef::Qef;
+use crate::volume::Volume;
+
+//Dual contouring, http://www.frankpetterson.com/publications/dualcontour/dualcontour.pdf
+//Puts one vertex in every cell the surface passes through, at the point that best fits
+//the tangent planes of the surface where it crosses the cell edges. Unlike marching cubes
+//that keeps the hard edges and corners of boxes and chamfers.
+//`distance` is used to refine the edge crossings and to get gradients, the volume
+//only decides which edges get crossed. Padded with empty space just like marching_cubes.
+
+//How many bisection steps are used to find the exact crossing on an edge
+const REFINE_STEPS: u32 = 8;
+
+pub fn dual_contouring<F: Fn(Vector3<f32>) -> f32>(volume: &Volume, distance: F) -> Mesh {
+    let mut contourer = Contourer {
+        volume: volume,
+        distance: distance,
+        mesh: Mesh::new(),
+        cells: HashMap::new(),
+    };
+
+    //Every lattice edge that crosses the surface gets a quad connecting the 4 cells around it
+    let [sx, sy, sz] = volume.size;
+    for z in -1..=sz as i64 {
+        for y in -1..=sy as i64 {
+            for x in -1..=sx as i64 {
+                for axis in 0..3 {
+                    contourer.edge([x, y, z], axis);
+                }
+            }
+        }
+    }
+
+    contourer.mesh
+}
+
+struct Contourer<'a, F: Fn(Vector3<f32>) -> f32> {
+    volume: &'a Volume,
+    distance: F,
+    mesh: Mesh,
+    cells: HashMap<[i64; 3], u32>,
+}
+
+impl<'a, F: Fn(Vector3<f32>) -> f32> Contourer<'a, F> {
+    fn value(&self, p: [i64; 3]) -> f32 {
+        let [sx, sy, sz] = self.volume.size;
+        if p[0] < 0 || p[1] < 0 || p[2] < 0 || p[0] >= sx as i64 || p[1] >= sy as i64 || p[2] >= sz as i64 {
+            return 1.0;
+        }
+        self.volume.get(p[0] as usize, p[1] as usize, p[2] as usize)
+    }
+
+    fn position(&self, p: [i64; 3]) -> Vector3<f32> {
+        let s = self.volume.spacing();
+        self.volume.origin + Vector3::new(p[0] as f32 * s.x, p[1] as f32 * s.y, p[2] as f32 * s.z)
+    }
+
+    fn gradient(&self, p: Vector3<f32>) -> Vector3<f32> {
+        let e = 0.01 * self.volume.spacing().x.min(self.volume.spacing().y).min(self.volume.spacing().z);
+        let g = Vector3::new(
+            (self.distance)(p + Vector3::new(e, 0.0, 0.0)) - (self.distance)(p - Vector3::new(e, 0.0, 0.0)),
+            (self.distance)(p + Vector3::new(0.0, e, 0.0)) - (self.distance)(p - Vector3::new(0.0, e, 0.0)),
+            (self.distance)(p + Vector3::new(0.0, 0.0, e)) - (self.distance)(p - Vector3::new(0.0, 0.0, e)),
+        );
+        if g.magnitude2() > 0.0 { g.normalize() } else { Vector3::zero() }
+    }
+
+    //Where the surface crosses the edge between a and b, found by bisection on the distance function
+    fn crossing(&self, a: [i64; 3], b: [i64; 3]) -> Vector3<f32> {
+        let (va, vb) = (self.value(a), self.value(b));
+        let (mut lo, mut hi) = (self.position(a), self.position(b));
+        let lo_inside = va < 0.0;
+
+        //Padding isn't part of the distance function, fall back on interpolating the grid
+        let padded = |p: [i64; 3]| p.iter().zip(self.volume.size.iter()).any(|(&c, &s)| c < 0 || c >= s as i64);
+        if padded(a) || padded(b) {
+            let t = va / (va - vb);
+            return lo + (hi - lo) * t;
+        }
+
+        for _ in 0..REFINE_STEPS {
+            let mid = (lo + hi) * 0.5;
+            if ((self.distance)(mid) < 0.0) == lo_inside {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        (lo + hi) * 0.5
+    }
+
+    //Vertex of the cell with its minimum corner at c, created on first use
+    fn cell(&mut self, c: [i64; 3]) -> u32 {
+        if let Some(&index) = self.cells.get(&c) {
+            return index;
+        }
+
+        let mut qef = Qef::new();
+        for axis in 0..3 {
+            let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+            for &(du, dv) in &[(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let mut a = c;
+                a[u] += du;
+                a[v] += dv;
+                let mut b = a;
+                b[axis] += 1;
+                if (self.value(a) < 0.0) != (self.value(b) < 0.0) {
+                    let p = self.crossing(a, b);
+                    qef.add(p, self.gradient(p));
+                }
+            }
+        }
+
+        //Keep the vertex inside its cell, a QEF solution outside of it folds the mesh
+        let min = self.position(c);
+        let max = self.position([c[0] + 1, c[1] + 1, c[2] + 1]);
+        let mut position = qef.solve();
+        if position.x < min.x || position.y < min.y || position.z < min.z || position.x > max.x || position.y > max.y || position.z > max.z {
+            position = qef.mass_point();
+        }
+
+        let normal = self.gradient(position);
+        let material = self.volume.material(position);
+        let index = self.mesh.add_vertex(position, normal, material);
+        self.cells.insert(c, index);
+        index
+    }
+
+    fn edge(&mut self, a: [i64; 3], axis: usize) {
+        let mut b = a;
+        b[axis] += 1;
+        let (va, vb) = (self.value(a), self.value(b));
+        if (va < 0.0) == (vb < 0.0) {
+            return;
+        }
+
+        //Cells around the edge, counter clockwise when looking down the axis
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        let mut quad = [0; 4];
+        for (i, &(du, dv)) in [(-1, -1), (0, -1), (0, 0), (-1, 0)].iter().enumerate() {
+            let mut c = a;
+            c[u] += du;
+            c[v] += dv;
+            quad[i] = self.cell(c);
+        }
+
+        //The quad faces along the axis, which is outwards if the start of the edge is inside
+        if va >= 0.0 {
+            quad.reverse();
+        }
+
+        //Split along the diagonal that keeps the two halves the flattest, that avoids folds on sharp edges
+        let normal = |a: u32, b: u32, c: u32| {
+            let (pa, pb, pc) = (self.mesh.positions[a as usize], self.mesh.positions[b as usize], self.mesh.positions[c as usize]);
+            let n = (pb - pa).cross(pc - pa);
+            if n.magnitude2() > 0.0 { n.normalize() } else { n }
+        };
+        let flatness_02 = normal(quad[0], quad[1], quad[2]).dot(normal(quad[0], quad[2], quad[3]));
+        let flatness_13 = normal(quad[0], quad[1], quad[3]).dot(normal(quad[1], quad[2], quad[3]));
+
+        if flatness_02 >= flatness_13 {
+            self.mesh.indices.extend_from_slice(&[quad[0], quad[1], quad[2]]);
+            self.mesh.indices.extend_from_slice(&[quad[0], quad[2], quad[3]]);
+        } else {
+            self.mesh.indices.extend_from_slice(&[quad[0], quad[1], quad[3]]);
+            self.mesh.indices.extend_from_slice(&[quad[1], quad[2], quad[3]]);
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use super::Mesh;
+use crate::scene::Material;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MeshFormat {
+    Obj,
+    StlAscii,
+    StlBinary,
+    Ply,
+    Gltf,
+    Glb,
+}
+
+impl MeshFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            MeshFormat::Obj => "OBJ",
+            MeshFormat::StlAscii => "STL (ASCII)",
+            MeshFormat::StlBinary => "STL (binary)",
+            MeshFormat::Ply => "PLY",
+            MeshFormat::Gltf => "glTF",
+            MeshFormat::Glb => "glTF (binary)",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            MeshFormat::Obj => "obj",
+            MeshFormat::StlAscii | MeshFormat::StlBinary => "stl",
+            MeshFormat::Ply => "ply",
+            MeshFormat::Gltf => "gltf",
+            MeshFormat::Glb => "glb",
+        }
+    }
+
+    pub fn all() -> [MeshFormat; 6] {
+        [MeshFormat::Obj, MeshFormat::StlAscii, MeshFormat::StlBinary, MeshFormat::Ply, MeshFormat::Gltf, MeshFormat::Glb]
+    }
+
+    //Binary STL is picked for .stl, as it's a lot smaller
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<MeshFormat> {
+        let ext = path.as_ref().extension()?.to_str()?.to_lowercase();
+        match ext.as_str() {
+            "obj" => Some(MeshFormat::Obj),
+            "stl" => Some(MeshFormat::StlBinary),
+            "ply" => Some(MeshFormat::Ply),
+            "gltf" => Some(MeshFormat::Gltf),
+            "glb" => Some(MeshFormat::Glb),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for MeshFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}", self.name())
+    }
+}
+
+//Only glTF carries materials, the other formats ignore them
+pub fn save<P: AsRef<Path>>(mesh: &Mesh, materials: &[Material], path: P, format: MeshFormat) -> io::Result<()> {
+    match format {
+        MeshFormat::Gltf => return super::gltf::save(mesh, materials, path, false),
+        MeshFormat::Glb => return super::gltf::save(mesh, materials, path, true),
+        _ => {},
+    }
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    match format {
+        MeshFormat::Obj => write_obj(mesh, &mut writer)?,
+        MeshFormat::StlAscii => write_stl_ascii(mesh, &mut writer)?,
+        MeshFormat::StlBinary => write_stl_binary(mesh, &mut writer)?,
+        MeshFormat::Ply => write_ply(mesh, &mut writer)?,
+        MeshFormat::Gltf | MeshFormat::Glb => unreachable!("handled above"),
+    }
+    writer.flush()
+}
+
+pub fn write_obj<W: Write>(mesh: &Mesh, w: &mut W) -> io::Result<()> {
+    writeln!(w, "# sdf_preview")?;
+    for p in &mesh.positions {
+        writeln!(w, "v {} {} {}", p.x, p.y, p.z)?;
+    }
+    for n in &mesh.normals {
+        writeln!(w, "vn {} {} {}", n.x, n.y, n.z)?;
+    }
+    //OBJ indices start at 1
+    for t in mesh.triangles() {
+        writeln!(w, "f {0}//{0} {1}//{1} {2}//{2}", t[0] + 1, t[1] + 1, t[2] + 1)?;
+    }
+    Ok(())
+}
+
+pub fn write_stl_ascii<W: Write>(mesh: &Mesh, w: &mut W) -> io::Result<()> {
+    writeln!(w, "solid sdf_preview")?;
+    for t in mesh.triangles() {
+        let n = mesh.face_normal(t);
+        writeln!(w, "  facet normal {} {} {}", n.x, n.y, n.z)?;
+        writeln!(w, "    outer loop")?;
+        for &i in &t {
+            let p = mesh.positions[i as usize];
+            writeln!(w, "      vertex {} {} {}", p.x, p.y, p.z)?;
+        }
+        writeln!(w, "    endloop")?;
+        writeln!(w, "  endfacet")?;
+    }
+    writeln!(w, "endsolid sdf_preview")
+}
+
+//80 byte header, triangle count, then per triangle a normal, 3 vertices and a 2 byte attribute
+pub fn write_stl_binary<W: Write>(mesh: &Mesh, w: &mut W) -> io::Result<()> {
+    let mut header = [0u8; 80];
+    let name = b"sdf_preview";
+    header[..name.len()].copy_from_slice(name);
+    w.write_all(&header)?;
+    w.write_all(&(mesh.triangle_count() as u32).to_le_bytes())?;
+
+    for t in mesh.triangles() {
+        let n = mesh.face_normal(t);
+        for v in &[n.x, n.y, n.z] {
+            w.write_all(&v.to_le_bytes())?;
+        }
+        for &i in &t {
+            let p = mesh.positions[i as usize];
+            for v in &[p.x, p.y, p.z] {
+                w.write_all(&v.to_le_bytes())?;
+            }
+        }
+        w.write_all(&0u16.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+pub fn write_ply<W: Write>(mesh: &Mesh, w: &mut W) -> io::Result<()> {
+    writeln!(w, "ply")?;
+    writeln!(w, "format binary_little_endian 1.0")?;
+    writeln!(w, "comment sdf_preview")?;
+    writeln!(w, "element vertex {}", mesh.vertex_count())?;
+    for name in &["x", "y", "z", "nx", "ny", "nz"] {
+        writeln!(w, "property float {}", name)?;
+    }
+    writeln!(w, "element face {}", mesh.triangle_count())?;
+    writeln!(w, "property list uchar int vertex_indices")?;
+    writeln!(w, "end_header")?;
+
+    for (p, n) in mesh.positions.iter().zip(mesh.normals.iter()) {
+        for v in &[p.x, p.y, p.z, n.x, n.y, n.z] {
+            w.write_all(&v.to_le_bytes())?;
+        }
+    }
+    for t in mesh.triangles() {
+        w.write_all(&[3u8])?;
+        for &i in &t {
+            w.write_all(&(i as i32).to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,341 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use cgmath::*;
+use serde_json::json;
+
+use super::Mesh;
+use crate::scene::Material;
+
+//glTF 2.0 export, https://github.com/KhronosGroup/glTF/tree/master/specification/2.0
+//All triangles sharing a material id end up in their own primitive with a matching
+//PBR material, and the albedo is written as vertex colours as well for tools that prefer those.
+//The vertex attributes are shared between all primitives.
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+const TRIANGLES: u32 = 4;
+
+//Binary writes a single .glb file, otherwise a .gltf with the buffer next to it in a .bin
+pub fn save<P: AsRef<Path>>(mesh: &Mesh, materials: &[Material], path: P, binary: bool) -> io::Result<()> {
+    let path = path.as_ref();
+
+    //Zero sized accessors and buffers and a mesh without primitives are all invalid glTF
+    if mesh.triangle_count() == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "The mesh is empty, there's nothing to export"));
+    }
+
+    if binary {
+        let (document, buffer) = build(mesh, materials, None);
+        fs::write(path, glb(&document, &buffer))
+    } else {
+        let bin_path = path.with_extension("bin");
+        let bin_name = bin_path.file_name().and_then(|n| n.to_str()).unwrap_or("scene.bin").to_string();
+        let (document, buffer) = build(mesh, materials, Some(&bin_name));
+        fs::write(&bin_path, &buffer)?;
+        fs::write(path, serde_json::to_string_pretty(&document).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?)
+    }
+}
+
+//Builds the JSON document and the binary buffer it refers to, the mesh needs at least one triangle
+pub fn build(mesh: &Mesh, materials: &[Material], buffer_uri: Option<&str>) -> (serde_json::Value, Vec<u8>) {
+    let material = |id: u32| materials.get(id as usize).copied().unwrap_or_else(Material::default);
+
+    let mut buffer = Vec::new();
+    let mut buffer_views = Vec::new();
+    let mut accessors = Vec::new();
+
+    //Vertex attributes
+    let (min, max) = bounds(&mesh.positions);
+    let positions = push_view(&mut buffer, &mut buffer_views, mesh.positions.iter().flat_map(|p| vec![p.x, p.y, p.z]), ARRAY_BUFFER);
+    accessors.push(json!({
+        "bufferView": positions,
+        "componentType": FLOAT,
+        "count": mesh.vertex_count(),
+        "type": "VEC3",
+        "min": [min.x, min.y, min.z],
+        "max": [max.x, max.y, max.z],
+    }));
+
+    //The validator wants unit length normals, so degenerate ones get replaced
+    let normals = push_view(&mut buffer, &mut buffer_views, mesh.normals.iter().flat_map(|n| {
+        let n = if n.magnitude2() > 0.0 { n.normalize() } else { Vector3::unit_y() };
+        vec![n.x, n.y, n.z]
+    }), ARRAY_BUFFER);
+    accessors.push(json!({
+        "bufferView": normals,
+        "componentType": FLOAT,
+        "count": mesh.vertex_count(),
+        "type": "VEC3",
+    }));
+
+    let colours = push_view(&mut buffer, &mut buffer_views, mesh.materials.iter().flat_map(|&id| {
+        let albedo = material(id).albedo;
+        vec![albedo.x, albedo.y, albedo.z]
+    }), ARRAY_BUFFER);
+    accessors.push(json!({
+        "bufferView": colours,
+        "componentType": FLOAT,
+        "count": mesh.vertex_count(),
+        "type": "VEC3",
+    }));
+
+    //Triangles grouped by the material of their first vertex, sorted so the output is stable
+    let mut groups: BTreeMap<u32, Vec<u32>> = BTreeMap::new();
+    for t in mesh.triangles() {
+        groups.entry(mesh.materials[t[0] as usize]).or_insert_with(Vec::new).extend_from_slice(&t);
+    }
+
+    let mut primitives = Vec::new();
+    let mut gltf_materials = Vec::new();
+    for (id, indices) in &groups {
+        let view = push_index_view(&mut buffer, &mut buffer_views, indices);
+        accessors.push(json!({
+            "bufferView": view,
+            "componentType": UNSIGNED_INT,
+            "count": indices.len(),
+            "type": "SCALAR",
+        }));
+
+        let m = material(*id);
+        gltf_materials.push(json!({
+            "name": format!("material_{}", id),
+            "pbrMetallicRoughness": {
+                "baseColorFactor": [m.albedo.x, m.albedo.y, m.albedo.z, 1.0],
+                "metallicFactor": m.metalness,
+                "roughnessFactor": m.roughness,
+            },
+        }));
+
+        primitives.push(json!({
+            "attributes": {
+                "POSITION": 0,
+                "NORMAL": 1,
+                "COLOR_0": 2,
+            },
+            "indices": accessors.len() - 1,
+            "material": gltf_materials.len() - 1,
+            "mode": TRIANGLES,
+        }));
+    }
+
+    let mut gltf_buffer = json!({ "byteLength": buffer.len() });
+    if let Some(uri) = buffer_uri {
+        gltf_buffer["uri"] = json!(uri);
+    }
+
+    let mut document = json!({
+        "asset": {
+            "version": "2.0",
+            "generator": "sdf_preview",
+        },
+        "scene": 0,
+        "scenes": [{ "nodes": [0] }],
+        "nodes": [{ "mesh": 0, "name": "sdf_scene" }],
+        "meshes": [{ "primitives": primitives }],
+        "buffers": [gltf_buffer],
+        "bufferViews": buffer_views,
+        "accessors": accessors,
+    });
+    //An empty materials array isn't allowed
+    if !gltf_materials.is_empty() {
+        document["materials"] = json!(gltf_materials);
+    }
+
+    (document, buffer)
+}
+
+//Binary glTF container: a 12 byte header followed by a JSON and a BIN chunk, both padded to 4 bytes
+pub fn glb(document: &serde_json::Value, buffer: &[u8]) -> Vec<u8> {
+    let mut json = document.to_string().into_bytes();
+    while json.len() % 4 != 0 {
+        json.push(b' ');
+    }
+    let mut bin = buffer.to_vec();
+    while bin.len() % 4 != 0 {
+        bin.push(0);
+    }
+
+    let total = 12 + 8 + json.len() + 8 + bin.len();
+    let mut out = Vec::with_capacity(total);
+    out.extend_from_slice(b"glTF");
+    out.extend_from_slice(&2u32.to_le_bytes());
+    out.extend_from_slice(&(total as u32).to_le_bytes());
+
+    out.extend_from_slice(&(json.len() as u32).to_le_bytes());
+    out.extend_from_slice(b"JSON");
+    out.extend_from_slice(&json);
+
+    out.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+    out.extend_from_slice(b"BIN\0");
+    out.extend_from_slice(&bin);
+
+    out
+}
+
+fn push_view<I: Iterator<Item = f32>>(buffer: &mut Vec<u8>, views: &mut Vec<serde_json::Value>, data: I, target: u32) -> usize {
+    let offset = buffer.len();
+    for v in data {
+        buffer.extend_from_slice(&v.to_le_bytes());
+    }
+    views.push(json!({
+        "buffer": 0,
+        "byteOffset": offset,
+        "byteLength": buffer.len() - offset,
+        "target": target,
+    }));
+    views.len() - 1
+}
+
+fn push_index_view(buffer: &mut Vec<u8>, views: &mut Vec<serde_json::Value>, indices: &[u32]) -> usize {
+    let offset = buffer.len();
+    for i in indices {
+        buffer.extend_from_slice(&i.to_le_bytes());
+    }
+    views.push(json!({
+        "buffer": 0,
+        "byteOffset": offset,
+        "byteLength": buffer.len() - offset,
+        "target": ELEMENT_ARRAY_BUFFER,
+    }));
+    views.len() - 1
+}
+
+fn bounds(positions: &[Vector3<f32>]) -> (Vector3<f32>, Vector3<f32>) {
+    if positions.is_empty() {
+        return (Vector3::zero(), Vector3::zero());
+    }
+    let mut min = positions[0];
+    let mut max = positions[0];
+    for p in positions {
+        min = Vector3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+        max = Vector3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+    }
+    (min, max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::{mesh_scene, Mesher};
+    use crate::scene::{Scene, Node, Primitive};
+
+    fn component_size(component_type: u64) -> usize {
+        match component_type as u32 {
+            FLOAT | UNSIGNED_INT => 4,
+            other => panic!("unexpected component type {}", other),
+        }
+    }
+
+    fn components(kind: &str) -> usize {
+        match kind {
+            "SCALAR" => 1,
+            "VEC3" => 3,
+            other => panic!("unexpected accessor type {}", other),
+        }
+    }
+
+    //The structural rules of the glTF validator that the exporter can get wrong
+    fn validate(document: &serde_json::Value, buffer: &[u8], vertex_count: usize) {
+        assert_eq!(document["buffers"][0]["byteLength"].as_u64().unwrap() as usize, buffer.len());
+
+        let views = document["bufferViews"].as_array().unwrap();
+        for view in views {
+            let offset = view["byteOffset"].as_u64().unwrap() as usize;
+            let length = view["byteLength"].as_u64().unwrap() as usize;
+            assert!(length > 0);
+            assert_eq!(offset % 4, 0, "misaligned buffer view {}", view);
+            assert!(offset + length <= buffer.len(), "buffer view {} runs past the buffer", view);
+        }
+
+        let accessors = document["accessors"].as_array().unwrap();
+        for accessor in accessors {
+            let view = &views[accessor["bufferView"].as_u64().unwrap() as usize];
+            let count = accessor["count"].as_u64().unwrap() as usize;
+            let element = component_size(accessor["componentType"].as_u64().unwrap()) * components(accessor["type"].as_str().unwrap());
+            assert!(count > 0);
+            assert!(count * element <= view["byteLength"].as_u64().unwrap() as usize, "accessor {} runs past its view", accessor);
+        }
+
+        let primitives = document["meshes"][0]["primitives"].as_array().unwrap();
+        assert!(!primitives.is_empty());
+        for primitive in primitives {
+            for attribute in primitive["attributes"].as_object().unwrap().values() {
+                let accessor = &accessors[attribute.as_u64().unwrap() as usize];
+                assert_eq!(accessor["count"].as_u64().unwrap() as usize, vertex_count);
+            }
+
+            //Every index has to point at an existing vertex
+            let accessor = &accessors[primitive["indices"].as_u64().unwrap() as usize];
+            assert_eq!(accessor["count"].as_u64().unwrap() % 3, 0);
+            let view = &views[accessor["bufferView"].as_u64().unwrap() as usize];
+            let offset = view["byteOffset"].as_u64().unwrap() as usize;
+            let length = view["byteLength"].as_u64().unwrap() as usize;
+            for index in buffer[offset..offset + length].chunks(4) {
+                let index = u32::from_le_bytes([index[0], index[1], index[2], index[3]]);
+                assert!((index as usize) < vertex_count);
+            }
+
+            let material = primitive["material"].as_u64().unwrap() as usize;
+            assert!(material < document["materials"].as_array().unwrap().len());
+        }
+    }
+
+    //Two spheres with different materials, so the mesh gets split into two primitives
+    fn test_mesh() -> Mesh {
+        let mut scene = Scene::new();
+        scene.add(Node::primitive(Primitive::Sphere { radius: 5.0 }, 0).translated(Vector3::new(8.0, 8.0, 8.0)));
+        scene.add(Node::primitive(Primitive::Sphere { radius: 5.0 }, 1).translated(Vector3::new(24.0, 8.0, 8.0)));
+        mesh_scene(&scene, 32, Vector3::zero(), Vector3::new(32.0, 16.0, 16.0), Mesher::MarchingCubes)
+    }
+
+    #[test]
+    fn document_is_valid() {
+        let mesh = test_mesh();
+        let materials = vec![Material::default(), Material::default()];
+        let (document, buffer) = build(&mesh, &materials, Some("mesh.bin"));
+
+        validate(&document, &buffer, mesh.vertex_count());
+        assert_eq!(document["meshes"][0]["primitives"].as_array().unwrap().len(), 2);
+        assert_eq!(document["buffers"][0]["uri"], "mesh.bin");
+    }
+
+    #[test]
+    fn glb_chunks() {
+        let mesh = test_mesh();
+        let (document, buffer) = build(&mesh, &[], None);
+        let glb = glb(&document, &buffer);
+        let word = |offset: usize| u32::from_le_bytes([glb[offset], glb[offset + 1], glb[offset + 2], glb[offset + 3]]) as usize;
+
+        assert_eq!(&glb[0..4], b"glTF");
+        assert_eq!(word(4), 2);
+        assert_eq!(word(8), glb.len());
+
+        let json_length = word(12);
+        assert_eq!(json_length % 4, 0);
+        assert_eq!(&glb[16..20], b"JSON");
+        let json: serde_json::Value = serde_json::from_slice(&glb[20..20 + json_length]).unwrap();
+        assert_eq!(json, document);
+
+        let bin = 20 + json_length;
+        let bin_length = word(bin);
+        assert_eq!(bin_length % 4, 0);
+        assert_eq!(&glb[bin + 4..bin + 8], b"BIN\0");
+        assert_eq!(bin + 8 + bin_length, glb.len());
+        assert_eq!(&glb[bin + 8..bin + 8 + buffer.len()], &buffer[..]);
+
+        validate(&json, &glb[bin + 8..bin + 8 + bin_length], mesh.vertex_count());
+    }
+
+    #[test]
+    fn empty_mesh_is_rejected() {
+        let path = std::env::temp_dir().join("sdf_preview_empty_mesh.glb");
+        let _ = fs::remove_file(&path);
+        assert!(save(&Mesh::new(), &[], &path, true).is_err());
+        assert!(!path.exists());
+    }
+}
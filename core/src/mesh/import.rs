@@ -0,0 +1,220 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use cgmath::*;
+
+use super::Mesh;
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+//Picks the loader from the extension, only OBJ and STL for now
+pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Mesh> {
+    let path = path.as_ref();
+    let ext = path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase()).unwrap_or_default();
+    let mut mesh = match ext.as_str() {
+        "obj" => parse_obj(&fs::read_to_string(path)?)?,
+        "stl" => parse_stl(&fs::read(path)?)?,
+        _ => return Err(invalid(format!("Don't know how to read {}", path.display()))),
+    };
+    if mesh.triangle_count() == 0 {
+        return Err(invalid(format!("{} has no triangles", path.display())));
+    }
+    compute_vertex_normals(&mut mesh);
+    Ok(mesh)
+}
+
+//Positions and faces only, polygons get split into a fan.
+//Indices can be negative, which counts back from the last vertex so far.
+pub fn parse_obj(src: &str) -> io::Result<Mesh> {
+    let mut mesh = Mesh::new();
+
+    for (number, line) in src.lines().enumerate() {
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("v") => {
+                let mut coords = [0.0; 3];
+                for c in coords.iter_mut() {
+                    let token = tokens.next().ok_or_else(|| invalid(format!("Line {}: vertex needs three coordinates", number + 1)))?;
+                    *c = token.parse().map_err(|_| invalid(format!("Line {}: invalid coordinate '{}'", number + 1, token)))?;
+                }
+                mesh.add_vertex(Vector3::new(coords[0], coords[1], coords[2]), Vector3::zero(), 0);
+            },
+            Some("f") => {
+                let mut face = Vec::new();
+                for token in tokens {
+                    //Only the position index matters, texture coordinates and normals come after a slash
+                    let index = token.split('/').next().unwrap_or("");
+                    let index: i64 = index.parse().map_err(|_| invalid(format!("Line {}: invalid index '{}'", number + 1, token)))?;
+                    let count = mesh.vertex_count() as i64;
+                    let resolved = if index < 0 { count + index } else { index - 1 };
+                    if resolved < 0 || resolved >= count {
+                        return Err(invalid(format!("Line {}: index {} is out of range", number + 1, index)));
+                    }
+                    face.push(resolved as u32);
+                }
+                for i in 1..face.len().saturating_sub(1) {
+                    mesh.indices.extend_from_slice(&[face[0], face[i], face[i + 1]]);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    Ok(mesh)
+}
+
+//Binary STL is an 80 byte header, a triangle count and 50 bytes per triangle.
+//Some binary files start with "solid" as well, so the size decides.
+pub fn parse_stl(data: &[u8]) -> io::Result<Mesh> {
+    if data.len() >= 84 {
+        let count = u32::from_le_bytes([data[80], data[81], data[82], data[83]]) as usize;
+        if data.len() == 84 + count * 50 {
+            return Ok(parse_stl_binary(&data[84..], count));
+        }
+    }
+
+    if data.starts_with(b"solid") {
+        parse_stl_ascii(&String::from_utf8_lossy(data))
+    } else {
+        Err(invalid("Not a valid STL file".to_string()))
+    }
+}
+
+fn parse_stl_binary(data: &[u8], count: usize) -> Mesh {
+    let mut mesh = Mesh::new();
+    let float = |b: &[u8]| f32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+
+    for triangle in data.chunks_exact(50).take(count) {
+        //Skip the stored normal, plenty of exporters leave it at zero
+        for v in 0..3 {
+            let o = 12 + v * 12;
+            let position = Vector3::new(float(&triangle[o..]), float(&triangle[o + 4..]), float(&triangle[o + 8..]));
+            let index = mesh.add_vertex(position, Vector3::zero(), 0);
+            mesh.indices.push(index);
+        }
+    }
+
+    mesh
+}
+
+fn parse_stl_ascii(src: &str) -> io::Result<Mesh> {
+    let mut mesh = Mesh::new();
+
+    for (number, line) in src.lines().enumerate() {
+        let mut tokens = line.split_whitespace();
+        if tokens.next() != Some("vertex") {
+            continue;
+        }
+        let mut coords = [0.0; 3];
+        for c in coords.iter_mut() {
+            let token = tokens.next().ok_or_else(|| invalid(format!("Line {}: vertex needs three coordinates", number + 1)))?;
+            *c = token.parse().map_err(|_| invalid(format!("Line {}: invalid coordinate '{}'", number + 1, token)))?;
+        }
+        let index = mesh.add_vertex(Vector3::new(coords[0], coords[1], coords[2]), Vector3::zero(), 0);
+        mesh.indices.push(index);
+    }
+
+    if mesh.indices.len() % 3 != 0 {
+        return Err(invalid("STL vertex count isn't a multiple of three".to_string()));
+    }
+    Ok(mesh)
+}
+
+//Area weighted, as the files either have no normals or ones that aren't worth trusting
+fn compute_vertex_normals(mesh: &mut Mesh) {
+    let mut normals = vec![Vector3::zero(); mesh.vertex_count()];
+    for t in mesh.triangles() {
+        let a = mesh.positions[t[0] as usize];
+        let n = (mesh.positions[t[1] as usize] - a).cross(mesh.positions[t[2] as usize] - a);
+        for i in &t {
+            normals[*i as usize] += n;
+        }
+    }
+    for n in normals.iter_mut() {
+        if n.magnitude2() > 0.0 {
+            *n = n.normalize();
+        }
+    }
+    mesh.normals = normals;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Quads, with texture and normal indices that should get ignored
+    const CUBE: &str = "# cube\nv -1 -1 -1\nv 1 -1 -1\nv 1 1 -1\nv -1 1 -1\nv -1 -1 1\nv 1 -1 1\nv 1 1 1\nv -1 1 1\n\
+        f 1/1/1 4/2/1 3/3/1 2/4/1\nf 5//2 6//2 7//2 8//2\nf 1 2 6 5\nf 4 8 7 3\nf 1 5 8 4\nf 2 3 7 6\n";
+
+    #[test]
+    fn obj_polygons_become_fans() {
+        let mesh = parse_obj(CUBE).unwrap();
+        assert_eq!(mesh.vertex_count(), 8);
+        assert_eq!(mesh.triangle_count(), 12);
+        assert_eq!(&mesh.indices[..6], &[0, 3, 2, 0, 2, 1]);
+        assert!(mesh.is_watertight());
+    }
+
+    #[test]
+    fn obj_negative_indices() {
+        let mesh = parse_obj("v 0 0 0\nv 1 0 0\nv 0 1 0\nf -3 -2 -1\nv 0 0 1\nf -4 -1 -3\n").unwrap();
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 3, 1]);
+    }
+
+    #[test]
+    fn obj_errors() {
+        assert!(parse_obj("v 0 0 0\nv 1 0 0\nf 1 2 3\n").is_err());
+        assert!(parse_obj("v 0 0 0\nf 0 1 1\n").is_err());
+        assert!(parse_obj("v 0 0\n").is_err());
+        assert!(parse_obj("v 0 0 x\n").is_err());
+    }
+
+    const TRIANGLES: [[[f32; 3]; 3]; 2] = [
+        [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+        [[0.0, 0.0, 0.0], [0.0, 0.0, 1.0], [1.0, 0.0, 0.0]],
+    ];
+
+    fn positions(mesh: &Mesh) -> Vec<[f32; 3]> {
+        mesh.indices.iter().map(|&i| mesh.positions[i as usize].into()).collect()
+    }
+
+    fn expected() -> Vec<[f32; 3]> {
+        TRIANGLES.iter().flat_map(|t| t.iter().copied()).collect()
+    }
+
+    #[test]
+    fn stl_binary() {
+        //A header starting with "solid" like some exporters write, the size has to win
+        let mut data = vec![0u8; 80];
+        data[..5].copy_from_slice(b"solid");
+        data.extend_from_slice(&(TRIANGLES.len() as u32).to_le_bytes());
+        for t in &TRIANGLES {
+            data.extend_from_slice(&[0u8; 12]);
+            for v in t.iter().flatten() {
+                data.extend_from_slice(&v.to_le_bytes());
+            }
+            data.extend_from_slice(&[0u8; 2]);
+        }
+        assert_eq!(positions(&parse_stl(&data).unwrap()), expected());
+    }
+
+    #[test]
+    fn stl_ascii() {
+        let mut src = "solid test\n".to_string();
+        for t in &TRIANGLES {
+            src += "  facet normal 0 0 0\n    outer loop\n";
+            for v in t {
+                src += &format!("      vertex {} {} {}\n", v[0], v[1], v[2]);
+            }
+            src += "    endloop\n  endfacet\n";
+        }
+        src += "endsolid test\n";
+        assert_eq!(positions(&parse_stl(src.as_bytes()).unwrap()), expected());
+
+        assert!(parse_stl(b"solid test\n vertex 0 0 0\n vertex 1 0 0\nendsolid\n").is_err());
+        assert!(parse_stl(b"not an stl file").is_err());
+    }
+}
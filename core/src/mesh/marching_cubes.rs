@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+
+use cgmath::*;
+
+use super::Mesh;
+use crate::volume::Volume;
+
+//Marching cubes with every cube split into six tetrahedra around the main diagonal.
+//That gets rid of the ambiguous cases of the classic lookup table, so neighbouring cubes
+//always agree on their shared faces and closed shapes come out watertight.
+//The volume is padded with one layer of empty space, so shapes touching the bounds get closed off too.
+
+//Corner i of a cube is offset by (i & 1, (i >> 1) & 1, (i >> 2) & 1)
+const TETRAHEDRA: [[usize; 4]; 6] = [
+    [0, 1, 3, 7],
+    [0, 3, 2, 7],
+    [0, 2, 6, 7],
+    [0, 6, 4, 7],
+    [0, 4, 5, 7],
+    [0, 5, 1, 7],
+];
+
+//Vertices sitting exactly on a corner are shared through the corner,
+//everything else through the edge it lies on
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum VertexKey {
+    Corner(i64),
+    Edge(i64, i64),
+}
+
+#[derive(Clone, Copy)]
+struct Corner {
+    index: i64,
+    position: Vector3<f32>,
+    value: f32,
+}
+
+pub fn marching_cubes(volume: &Volume) -> Mesh {
+    let mut mesher = Mesher {
+        volume: volume,
+        mesh: Mesh::new(),
+        vertices: HashMap::new(),
+    };
+
+    let [sx, sy, sz] = volume.size;
+    for z in -1..sz as i64 {
+        for y in -1..sy as i64 {
+            for x in -1..sx as i64 {
+                mesher.cube(x, y, z);
+            }
+        }
+    }
+
+    mesher.mesh
+}
+
+struct Mesher<'a> {
+    volume: &'a Volume,
+    mesh: Mesh,
+    vertices: HashMap<VertexKey, u32>,
+}
+
+impl<'a> Mesher<'a> {
+    fn corner(&self, x: i64, y: i64, z: i64) -> Corner {
+        let [sx, sy, sz] = self.volume.size;
+        let inside = x >= 0 && y >= 0 && z >= 0 && x < sx as i64 && y < sy as i64 && z < sz as i64;
+        let value = if inside {
+            self.volume.get(x as usize, y as usize, z as usize)
+        } else {
+            //Padding, far enough outside to never produce a surface of its own
+            self.volume.spacing().x.max(self.volume.spacing().y).max(self.volume.spacing().z)
+        };
+
+        let spacing = self.volume.spacing();
+        Corner {
+            //Linear index into the padded grid, only used as a key
+            index: (x + 1) + (y + 1) * (sx as i64 + 2) + (z + 1) * (sx as i64 + 2) * (sy as i64 + 2),
+            position: self.volume.origin + Vector3::new(x as f32 * spacing.x, y as f32 * spacing.y, z as f32 * spacing.z),
+            value: value,
+        }
+    }
+
+    fn cube(&mut self, x: i64, y: i64, z: i64) {
+        let mut corners = [self.corner(x, y, z); 8];
+        let mut any_inside = false;
+        let mut any_outside = false;
+        for (i, corner) in corners.iter_mut().enumerate() {
+            *corner = self.corner(x + (i & 1) as i64, y + ((i >> 1) & 1) as i64, z + ((i >> 2) & 1) as i64);
+            if corner.value < 0.0 {
+                any_inside = true;
+            } else {
+                any_outside = true;
+            }
+        }
+
+        if !(any_inside && any_outside) {
+            return;
+        }
+
+        for tet in &TETRAHEDRA {
+            self.tetrahedron([corners[tet[0]], corners[tet[1]], corners[tet[2]], corners[tet[3]]]);
+        }
+    }
+
+    fn tetrahedron(&mut self, corners: [Corner; 4]) {
+        let (inside, outside): (Vec<Corner>, Vec<Corner>) = corners.iter().partition(|c| c.value < 0.0);
+
+        match inside.len() {
+            1 => {
+                let a = inside[0];
+                let v0 = self.vertex(a, outside[0]);
+                let v1 = self.vertex(a, outside[1]);
+                let v2 = self.vertex(a, outside[2]);
+                self.triangle([v0, v1, v2], &inside, &outside);
+            },
+            3 => {
+                let a = outside[0];
+                let v0 = self.vertex(inside[0], a);
+                let v1 = self.vertex(inside[1], a);
+                let v2 = self.vertex(inside[2], a);
+                self.triangle([v0, v1, v2], &inside, &outside);
+            },
+            2 => {
+                let (a, b) = (inside[0], inside[1]);
+                let (c, d) = (outside[0], outside[1]);
+                let ac = self.vertex(a, c);
+                let ad = self.vertex(a, d);
+                let bd = self.vertex(b, d);
+                let bc = self.vertex(b, c);
+                self.triangle([ac, ad, bd], &inside, &outside);
+                self.triangle([ac, bd, bc], &inside, &outside);
+            },
+            _ => {},
+        }
+    }
+
+    //Vertex on the edge between an inside and an outside corner
+    fn vertex(&mut self, inside: Corner, outside: Corner) -> u32 {
+        let t = inside.value / (inside.value - outside.value);
+        let key = if t <= 0.0 {
+            VertexKey::Corner(inside.index)
+        } else if t >= 1.0 {
+            VertexKey::Corner(outside.index)
+        } else if inside.index < outside.index {
+            VertexKey::Edge(inside.index, outside.index)
+        } else {
+            VertexKey::Edge(outside.index, inside.index)
+        };
+
+        if let Some(&index) = self.vertices.get(&key) {
+            return index;
+        }
+
+        let position = inside.position + (outside.position - inside.position) * t.max(0.0).min(1.0);
+        let gradient = self.volume.gradient(position);
+        let normal = if gradient.magnitude2() > 0.0 { gradient.normalize() } else { Vector3::zero() };
+        let material = self.volume.material(position);
+
+        let index = self.mesh.add_vertex(position, normal, material);
+        self.vertices.insert(key, index);
+        index
+    }
+
+    //Winds the triangle so it faces away from the inside corners
+    fn triangle(&mut self, mut t: [u32; 3], inside: &[Corner], outside: &[Corner]) {
+        if t[0] == t[1] || t[1] == t[2] || t[2] == t[0] {
+            return;
+        }
+
+        let centroid = |corners: &[Corner]| corners.iter().fold(Vector3::zero(), |acc, c| acc + c.position) / corners.len() as f32;
+        let out = centroid(outside) - centroid(inside);
+
+        let a = self.mesh.positions[t[0] as usize];
+        let b = self.mesh.positions[t[1] as usize];
+        let c = self.mesh.positions[t[2] as usize];
+        if (b - a).cross(c - a).dot(out) < 0.0 {
+            t.swap(1, 2);
+        }
+
+        self.mesh.indices.extend_from_slice(&t);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::{Scene, Node, Primitive};
+
+    //Meshes a single primitive centred in a 32 voxel grid and checks the result against the exact distance
+    fn check(primitive: Primitive) {
+        let mut scene = Scene::new();
+        scene.add(Node::primitive(primitive, 0).translated(Vector3::new(16.0, 16.0, 16.0)));
+        let volume = Volume::from_scene(&scene, [32, 32, 32], Vector3::zero(), Vector3::new(32.0, 32.0, 32.0));
+        let mesh = marching_cubes(&volume);
+
+        assert!(mesh.vertex_count() > 0);
+        assert!(mesh.is_watertight());
+
+        let tolerance = 0.5 * volume.spacing().x;
+        for &p in &mesh.positions {
+            let dist = scene.distance(p);
+            assert!(dist.abs() <= tolerance, "vertex {:?} is {} away from the surface", p, dist);
+        }
+    }
+
+    #[test]
+    fn sphere_mesh() {
+        check(Primitive::Sphere { radius: 10.0 });
+    }
+
+    #[test]
+    fn box_mesh() {
+        check(Primitive::Box { half_extents: Vector3::new(8.0, 6.0, 10.0) });
+    }
+}
@@ -0,0 +1,124 @@
+use cgmath::*;
+
+pub mod marching_cubes;
+pub mod dual_contouring;
+pub mod qef;
+pub mod export;
+pub mod gltf;
+pub mod import;
+pub mod bvh;
+pub mod sdf;
+
+pub use marching_cubes::marching_cubes;
+pub use dual_contouring::dual_contouring;
+
+use crate::scene::Scene;
+use crate::volume::Volume;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mesher {
+    //Fast and always watertight, but rounds off sharp edges
+    MarchingCubes,
+    //Keeps sharp edges and corners intact
+    DualContouring,
+}
+
+impl Mesher {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Mesher::MarchingCubes => "Marching cubes",
+            Mesher::DualContouring => "Dual contouring",
+        }
+    }
+
+    pub fn all() -> [Mesher; 2] {
+        [Mesher::MarchingCubes, Mesher::DualContouring]
+    }
+}
+
+//Indexed triangle mesh, counter clockwise winding when looking at the outside
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mesh {
+    pub positions: Vec<Vector3<f32>>,
+    pub normals: Vec<Vector3<f32>>,
+    //Material id of the surface at each vertex
+    pub materials: Vec<u32>,
+    pub indices: Vec<u32>,
+}
+
+impl Mesh {
+    pub fn new() -> Mesh {
+        Mesh {
+            positions: Vec::new(),
+            normals: Vec::new(),
+            materials: Vec::new(),
+            indices: Vec::new(),
+        }
+    }
+
+    pub fn add_vertex(&mut self, position: Vector3<f32>, normal: Vector3<f32>, material: u32) -> u32 {
+        self.positions.push(position);
+        self.normals.push(normal);
+        self.materials.push(material);
+        (self.positions.len() - 1) as u32
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    pub fn triangles(&self) -> impl Iterator<Item = [u32; 3]> + '_ {
+        self.indices.chunks(3).map(|t| [t[0], t[1], t[2]])
+    }
+
+    pub fn face_normal(&self, triangle: [u32; 3]) -> Vector3<f32> {
+        let a = self.positions[triangle[0] as usize];
+        let b = self.positions[triangle[1] as usize];
+        let c = self.positions[triangle[2] as usize];
+        let n = (b - a).cross(c - a);
+        if n.magnitude2() > 0.0 {
+            n.normalize()
+        } else {
+            Vector3::zero()
+        }
+    }
+
+    //Replaces the normals, for example with the exact ones from the scene evaluator
+    pub fn recompute_normals<F: Fn(Vector3<f32>) -> Vector3<f32>>(&mut self, f: F) {
+        for (normal, position) in self.normals.iter_mut().zip(self.positions.iter()) {
+            *normal = f(*position);
+        }
+    }
+
+    //Every edge of a closed mesh is shared by exactly two triangles, in opposite directions
+    pub fn is_watertight(&self) -> bool {
+        use std::collections::HashMap;
+
+        let mut edges: HashMap<(u32, u32), i32> = HashMap::new();
+        for t in self.triangles() {
+            for &(a, b) in &[(t[0], t[1]), (t[1], t[2]), (t[2], t[0])] {
+                *edges.entry((a, b)).or_insert(0) += 1;
+            }
+        }
+
+        edges.iter().all(|(&(a, b), &count)| count == 1 && edges.get(&(b, a)) == Some(&1))
+    }
+}
+
+//Samples the scene on a grid of `resolution` voxels per axis over the given bounds and meshes it.
+//Normals come straight from the evaluator instead of the grid, which is a lot smoother.
+pub fn mesh_scene(scene: &Scene, resolution: usize, origin: Vector3<f32>, extent: Vector3<f32>, mesher: Mesher) -> Mesh {
+    let volume = Volume::from_scene(scene, [resolution; 3], origin, extent);
+    match mesher {
+        Mesher::MarchingCubes => {
+            let mut mesh = marching_cubes(&volume);
+            mesh.recompute_normals(|p| scene.normal(p));
+            mesh
+        },
+        Mesher::DualContouring => dual_contouring(&volume, |p| scene.distance(p)),
+    }
+}
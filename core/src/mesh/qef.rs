@@ -0,0 +1,108 @@
+use cgmath::*;
+
+//Quadratic error function for dual contouring: finds the point that is closest
+//to all the planes (p, n) going through the edge intersections of a cell.
+//Solved relative to the mass point with a truncated pseudo inverse, so flat and
+//edge-only cells don't blow up and stay close to the average of the intersections.
+pub struct Qef {
+    mass_point: Vector3<f32>,
+    count: u32,
+    planes: Vec<(Vector3<f32>, Vector3<f32>)>,
+}
+
+//Eigenvalues below this fraction of the largest one are treated as 0
+const TRUNCATION: f32 = 0.1;
+
+impl Qef {
+    pub fn new() -> Qef {
+        Qef {
+            mass_point: Vector3::zero(),
+            count: 0,
+            planes: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, position: Vector3<f32>, normal: Vector3<f32>) {
+        self.mass_point += position;
+        self.count += 1;
+        self.planes.push((position, normal));
+    }
+
+    pub fn solve(&self) -> Vector3<f32> {
+        if self.count == 0 {
+            return Vector3::zero();
+        }
+        let mass_point = self.mass_point();
+
+        //Build the normal equations around the mass point
+        let mut ata = Matrix3::zero();
+        let mut atb = Vector3::zero();
+        for &(p, n) in &self.planes {
+            ata += outer(n, n);
+            atb += n * n.dot(p - mass_point);
+        }
+
+        let (values, vectors) = symmetric_eigen(ata);
+        let max = values.x.abs().max(values.y.abs()).max(values.z.abs());
+
+        let mut x = Vector3::zero();
+        for i in 0..3 {
+            if max > 0.0 && values[i].abs() > TRUNCATION * max {
+                let v = vectors[i];
+                x += v * (v.dot(atb) / values[i]);
+            }
+        }
+
+        mass_point + x
+    }
+
+    pub fn mass_point(&self) -> Vector3<f32> {
+        if self.count == 0 {
+            return Vector3::zero();
+        }
+        self.mass_point / self.count as f32
+    }
+}
+
+fn outer(a: Vector3<f32>, b: Vector3<f32>) -> Matrix3<f32> {
+    Matrix3::from_cols(a * b.x, a * b.y, a * b.z)
+}
+
+//Jacobi eigenvalue iteration, plenty for a 3x3 symmetric matrix.
+//Returns the eigenvalues and the matching eigenvectors as the columns of the matrix.
+fn symmetric_eigen(m: Matrix3<f32>) -> (Vector3<f32>, Matrix3<f32>) {
+    let mut a = m;
+    let mut v = Matrix3::identity();
+
+    for _ in 0..16 {
+        //Largest off diagonal element
+        let (mut p, mut q) = (0, 1);
+        if a[2][0].abs() > a[q][p].abs() {
+            p = 0;
+            q = 2;
+        }
+        if a[2][1].abs() > a[q][p].abs() {
+            p = 1;
+            q = 2;
+        }
+        if a[q][p].abs() < 1e-9 {
+            break;
+        }
+
+        let theta = (a[q][q] - a[p][p]) / (2.0 * a[q][p]);
+        let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+        let c = 1.0 / (t * t + 1.0).sqrt();
+        let s = t * c;
+
+        let mut r = Matrix3::identity();
+        r[p][p] = c;
+        r[q][q] = c;
+        r[q][p] = s;
+        r[p][q] = -s;
+
+        a = r.transpose() * a * r;
+        v = v * r;
+    }
+
+    (Vector3::new(a[0][0], a[1][1], a[2][2]), v)
+}
@@ -0,0 +1,164 @@
+use cgmath::*;
+use serde::{Serialize, Deserialize};
+
+use super::Mesh;
+use super::bvh::Bvh;
+use crate::volume::Volume;
+
+//How to tell inside from outside, the distance itself always comes from the closest triangle
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SignMethod {
+    //Copes with holes and self intersections, but slower
+    WindingNumber,
+    //Majority vote of three rays, needs a closed mesh
+    RayParity,
+}
+
+impl SignMethod {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SignMethod::WindingNumber => "Winding number",
+            SignMethod::RayParity => "Ray parity",
+        }
+    }
+
+    pub fn all() -> [SignMethod; 2] {
+        [SignMethod::WindingNumber, SignMethod::RayParity]
+    }
+}
+
+//Slightly off the axes, so the rays don't run exactly along edges of axis aligned meshes
+const PARITY_DIRECTIONS: [[f32; 3]; 3] = [
+    [1.0, 0.000_123, 0.000_456],
+    [0.000_321, 1.0, 0.000_234],
+    [0.000_213, 0.000_432, 1.0],
+];
+
+pub fn bounds(mesh: &Mesh) -> (Vector3<f32>, Vector3<f32>) {
+    let mut min = Vector3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+    let mut max = -min;
+    for p in &mesh.positions {
+        min = Vector3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+        max = Vector3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+    }
+    (min, max)
+}
+
+//Samples the signed distance to the mesh on the given grid.
+//Materials come from the first vertex of the closest triangle.
+pub fn mesh_to_volume(mesh: &Mesh, size: [usize; 3], origin: Vector3<f32>, extent: Vector3<f32>, sign: SignMethod) -> Volume {
+    let bvh = Bvh::new(mesh);
+    let mut volume = Volume::new(size, origin, extent);
+
+    for z in 0..size[2] {
+        for y in 0..size[1] {
+            for x in 0..size[0] {
+                let p = volume.position(x, y, z);
+                let (closest, triangle) = match bvh.closest(p) {
+                    Some(hit) => hit,
+                    None => continue,
+                };
+
+                let inside = match sign {
+                    SignMethod::WindingNumber => bvh.winding_number(p) > 0.5,
+                    SignMethod::RayParity => {
+                        let votes = PARITY_DIRECTIONS.iter().filter(|d| bvh.count_hits(p, Vector3::from(**d)) % 2 == 1).count();
+                        votes >= 2
+                    },
+                };
+
+                let dist = (closest - p).magnitude();
+                let i = volume.index(x, y, z);
+                volume.distances[i] = if inside { -dist } else { dist };
+                volume.materials[i] = mesh.materials.get(mesh.indices[triangle.index as usize * 3] as usize).copied().unwrap_or(0);
+            }
+        }
+    }
+
+    volume
+}
+
+//Bakes the mesh into a volume around itself with cubic voxels, `resolution` of them along the longest side.
+//A few voxels of padding keep the surface away from the edges, where sampling gets clamped.
+pub fn bake_mesh(mesh: &Mesh, resolution: usize, sign: SignMethod) -> Volume {
+    const PADDING: usize = 3;
+    let (min, max) = bounds(mesh);
+    let size = max - min;
+    let longest = size.x.max(size.y).max(size.z).max(1e-6);
+    let inner = resolution.saturating_sub(2 * PADDING).max(1);
+    let spacing = longest / inner as f32;
+
+    let voxels = |s: f32| ((s / spacing).ceil() as usize).max(1) + 2 * PADDING + 1;
+    let dims = [voxels(size.x), voxels(size.y), voxels(size.z)];
+    let extent = Vector3::new(dims[0] as f32, dims[1] as f32, dims[2] as f32) * spacing;
+    //Centre the mesh in the grid, the last voxel sits one spacing before origin + extent
+    let origin = (min + max) * 0.5 - (extent - Vector3::new(spacing, spacing, spacing)) * 0.5;
+
+    mesh_to_volume(mesh, dims, origin, extent, sign)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::import::parse_obj;
+
+    //Unit cube around the origin, faces wound counter clockwise seen from outside
+    const CUBE: &str = "v -1 -1 -1\nv 1 -1 -1\nv 1 1 -1\nv -1 1 -1\nv -1 -1 1\nv 1 -1 1\nv 1 1 1\nv -1 1 1\n\
+        f 1 4 3 2\nf 5 6 7 8\nf 1 2 6 5\nf 4 8 7 3\nf 1 5 8 4\nf 2 3 7 6\n";
+
+    fn box_distance(p: Vector3<f32>) -> f32 {
+        let q = Vector3::new(p.x.abs() - 1.0, p.y.abs() - 1.0, p.z.abs() - 1.0);
+        Vector3::new(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0)).magnitude() + q.x.max(q.y).max(q.z).min(0.0)
+    }
+
+    fn each_voxel<F: FnMut(Vector3<f32>, f32)>(volume: &Volume, mut f: F) {
+        for z in 0..volume.size[2] {
+            for y in 0..volume.size[1] {
+                for x in 0..volume.size[0] {
+                    f(volume.position(x, y, z), volume.get(x, y, z));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn closed_cube() {
+        let cube = parse_obj(CUBE).unwrap();
+        for &sign in SignMethod::all().iter() {
+            let volume = bake_mesh(&cube, 16, sign);
+            each_voxel(&volume, |p, found| {
+                let expected = box_distance(p);
+                if expected.abs() > 1e-3 {
+                    assert!((found - expected).abs() < 1e-4, "{} gives {} instead of {} at {:?}", sign.name(), found, expected, p);
+                }
+            });
+        }
+    }
+
+    //The winding number still gets the inside of a cube with a side missing,
+    //only around the hole it drops below a half
+    #[test]
+    fn open_cube() {
+        let mut cube = parse_obj(CUBE).unwrap();
+        cube.indices.truncate(cube.indices.len() - 6);
+        let volume = bake_mesh(&cube, 16, SignMethod::WindingNumber);
+        assert!(volume.sample(Vector3::zero()) < -0.9);
+        assert!(volume.sample(Vector3::new(-0.5, 0.0, 0.0)) < -0.4);
+        each_voxel(&volume, |p, found| {
+            if box_distance(p) > 1e-3 {
+                assert!(found > 0.0, "{:?} is inside", p);
+            }
+        });
+    }
+
+    //Turned inside out, everything is outside
+    #[test]
+    fn flipped_cube() {
+        let mut cube = parse_obj(CUBE).unwrap();
+        for t in cube.indices.chunks_exact_mut(3) {
+            t.swap(1, 2);
+        }
+        let volume = bake_mesh(&cube, 16, SignMethod::WindingNumber);
+        each_voxel(&volume, |p, found| assert!((found - box_distance(p).abs()).abs() < 1e-4, "{:?}", p));
+    }
+}
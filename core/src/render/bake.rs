@@ -0,0 +1,109 @@
+use std::time::Instant;
+
+use crate::scene::Scene;
+use crate::volume;
+use crate::volume::bricks::BrickMap;
+use crate::volume::config::VolumeConfig;
+
+use super::{BrickTextures, dispatch_bake};
+
+//Bricks dispatched each frame. Filling the whole atlas of the default volume takes about half a second
+//at 60 fps this way, and a frame with a heavy scene doesn't stall the viewer for long.
+pub const BRICKS_PER_FRAME: usize = 1024;
+
+//What fills the bricks
+pub enum Baker {
+    //The bake compute shader, with the textures of the mesh and heightmap nodes it samples
+    Compute {
+        program: <glow::Context as glow::HasContext>::Program,
+        volumes: Vec<Option<<glow::Context as glow::HasContext>::Texture>>,
+        heightmaps: Vec<Option<<glow::Context as glow::HasContext>::Texture>>,
+    },
+    //The scene evaluator on the CPU, for drivers without compute shaders.
+    //It keeps its own copy of the scene, edits made while it runs go to the next bake.
+    Cpu {
+        scene: Scene,
+        config: VolumeConfig,
+    },
+}
+
+//A bake spread out over frames, so the viewer stays responsive while a big volume fills up.
+//Bricks go in the order of the BrickMap, which sweeps through the volume in slabs along z.
+//When the scene changes halfway the job gets dropped, a new one takes over whatever it didn't get to.
+pub struct BakeJob {
+    baker: Baker,
+    //Indices into the BrickMap
+    bricks: Vec<usize>,
+    baked: usize,
+    started: Instant,
+    frames: usize,
+}
+
+impl BakeJob {
+    pub fn new(baker: Baker, mut bricks: Vec<usize>) -> BakeJob {
+        bricks.sort_unstable();
+        bricks.dedup();
+        BakeJob {
+            baker,
+            bricks,
+            baked: 0,
+            started: Instant::now(),
+            frames: 0,
+        }
+    }
+
+    pub fn baked(&self) -> usize {
+        self.baked
+    }
+
+    pub fn len(&self) -> usize {
+        self.bricks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bricks.is_empty()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.baked >= self.bricks.len()
+    }
+
+    //From 0 to 1
+    pub fn progress(&self) -> f32 {
+        if self.is_empty() {
+            1.0
+        } else {
+            self.baked as f32 / self.bricks.len() as f32
+        }
+    }
+
+    //Bricks that still hold an older scene, or nothing at all
+    pub fn remaining(&self) -> &[usize] {
+        &self.bricks[self.baked..]
+    }
+
+    //Bakes the next slab. The map can have changed since the job started: released bricks get skipped
+    //and the rest goes into whatever slot they have now.
+    pub fn step(&mut self, gl: &glow::Context, map: &BrickMap, textures: &BrickTextures, brick_binding: u32) {
+        if self.is_done() {
+            return;
+        }
+        let end = (self.baked + BRICKS_PER_FRAME).min(self.bricks.len());
+        let bricks = map.bake_list(self.bricks[self.baked..end].iter().copied());
+        match &self.baker {
+            Baker::Compute { program, volumes, heightmaps } => dispatch_bake(gl, *program, textures, &bricks, brick_binding, volumes, heightmaps),
+            Baker::Cpu { scene, config } => {
+                let indices: Vec<usize> = bricks.iter().map(|&[x, y, z, _]| map.index(x as usize, y as usize, z as usize)).collect();
+                for (texels, [_, _, _, slot]) in volume::cpu::bake_bricks(scene, config, map, &indices).iter().zip(bricks) {
+                    textures.upload_brick(map, slot as u32, texels);
+                }
+            },
+        }
+        self.baked = end;
+        self.frames += 1;
+
+        if self.is_done() {
+            debug!("Baked {} bricks in {} ms over {} frames", self.bricks.len(), self.started.elapsed().as_millis(), self.frames);
+        }
+    }
+}
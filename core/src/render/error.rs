@@ -0,0 +1,157 @@
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ShaderStage {
+    Vertex,
+    Fragment,
+    Compute,
+    Link,
+}
+
+impl fmt::Display for ShaderStage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            ShaderStage::Vertex => write!(f, "vertex shader"),
+            ShaderStage::Fragment => write!(f, "fragment shader"),
+            ShaderStage::Compute => write!(f, "compute shader"),
+            ShaderStage::Link => write!(f, "program linking"),
+        }
+    }
+}
+
+//A single parsed line of a driver info log
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShaderLogEntry {
+    //Source string index, always 0 for us as every stage is a single string
+    pub file: u32,
+    //1-based line in `ShaderError::source`
+    pub line: u32,
+    pub message: String,
+}
+
+//Error raised when compiling or linking a shader program fails
+#[derive(Clone, Debug)]
+pub struct ShaderError {
+    pub stage: ShaderStage,
+    //The info log exactly as the driver returned it
+    pub log: String,
+    pub entries: Vec<ShaderLogEntry>,
+    //Source of the failing stage, so the offending lines can be shown
+    pub source: String,
+}
+
+impl ShaderError {
+    //`line_offset` gets subtracted from every line number, for when something got prepended to the source
+    pub fn new(stage: ShaderStage, log: String, source: &str, line_offset: u32) -> ShaderError {
+        ShaderError {
+            stage: stage,
+            entries: parse_info_log(&log, line_offset),
+            log: log,
+            source: source.to_string(),
+        }
+    }
+
+    pub fn source_line(&self, line: u32) -> Option<&str> {
+        if line == 0 {
+            return None;
+        }
+        self.source.lines().nth(line as usize - 1)
+    }
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "Failed {}:\n{}", self.stage, self.log)
+    }
+}
+
+//Drivers don't agree on a format, these are the ones this understands:
+//NVIDIA: `0(12) : error C0000: syntax error`
+//Mesa:   `0:12(5): error: syntax error`
+//AMD:    `ERROR: 0:12: 'foo' : undeclared identifier`
+//Lines that don't look like any of these are skipped, they're still in the raw log.
+pub fn parse_info_log(log: &str, line_offset: u32) -> Vec<ShaderLogEntry> {
+    log.lines().filter_map(|line| parse_log_line(line, line_offset)).collect()
+}
+
+fn parse_log_line(line: &str, line_offset: u32) -> Option<ShaderLogEntry> {
+    let line = line.trim();
+
+    //AMD puts the severity in front, move it to the message instead
+    let (severity, rest) = if let Some(rest) = line.strip_prefix("ERROR: ") {
+        ("error: ", rest)
+    } else if let Some(rest) = line.strip_prefix("WARNING: ") {
+        ("warning: ", rest)
+    } else {
+        ("", line)
+    };
+
+    let (file, rest) = take_number(rest)?;
+    let (line_number, rest) = if let Some(rest) = rest.strip_prefix("(") {
+        //NVIDIA
+        let (n, rest) = take_number(rest)?;
+        (n, rest.strip_prefix(")")?)
+    } else {
+        //Mesa and AMD, Mesa adds a column in braces
+        let (n, rest) = take_number(rest.strip_prefix(":")?)?;
+        let rest = match rest.strip_prefix("(") {
+            Some(rest) => &rest[rest.find(')')? + 1..],
+            None => rest,
+        };
+        (n, rest)
+    };
+
+    let message = rest.trim_start().trim_start_matches(':').trim();
+
+    Some(ShaderLogEntry {
+        file: file,
+        line: line_number.saturating_sub(line_offset),
+        message: format!("{}{}", severity, message),
+    })
+}
+
+fn take_number(s: &str) -> Option<(u32, &str)> {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or_else(|| s.len());
+    let n = s[..end].parse().ok()?;
+    Some((n, &s[end..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(line: u32, message: &str) -> ShaderLogEntry {
+        ShaderLogEntry {
+            file: 0,
+            line: line,
+            message: message.to_string(),
+        }
+    }
+
+    #[test]
+    fn driver_formats() {
+        let cases = [
+            ("0(12) : error C0000: syntax error, unexpected '}'", entry(12, "error C0000: syntax error, unexpected '}'")),
+            ("0:12(5): error: syntax error, unexpected '}'", entry(12, "error: syntax error, unexpected '}'")),
+            ("ERROR: 0:12: 'foo' : undeclared identifier", entry(12, "error: 'foo' : undeclared identifier")),
+            ("WARNING: 0:3: 'bar' : unused variable", entry(3, "warning: 'bar' : unused variable")),
+        ];
+        for (log, expected) in cases.iter() {
+            assert_eq!(parse_info_log(log, 0), vec![expected.clone()], "{}", log);
+        }
+    }
+
+    #[test]
+    fn line_offset_gets_subtracted() {
+        assert_eq!(parse_info_log("0(12) : error C0000: oops", 2), vec![entry(10, "error C0000: oops")]);
+        assert_eq!(parse_info_log("0:12(5): error: oops", 2), vec![entry(10, "error: oops")]);
+        //Errors in the prepended lines end up at 0 instead of wrapping around
+        assert_eq!(parse_info_log("ERROR: 0:1: oops", 2), vec![entry(0, "error: oops")]);
+    }
+
+    #[test]
+    fn unparseable_lines_are_skipped() {
+        let log = "Compile failed.\n\n0:7(1): error: first\nERROR: 1 compilation errors.  No code generated.\nwarning: something vague\n0(9) : error C1008: second\n";
+        assert_eq!(parse_info_log(log, 0), vec![entry(7, "error: first"), entry(9, "error C1008: second")]);
+    }
+}
@@ -4,15 +4,32 @@ use luminance::{
     context::GraphicsContext,
     shader::program::{
         Program,
+        ProgramError,
         Uniform,
         Uniformable
     },
+    shader::stage::{
+        StageError,
+        Type as StageType,
+    },
     linear::M44,
 };
 
 use glow::HasContext;
+use std::sync::Arc;
+
+use crate::scene;
+use crate::scene::HeightField;
+use crate::volume::Volume;
+use crate::volume::bricks::{BrickMap, BRICK_SIZE, BRICK_SAMPLES};
+use crate::volume::config::{VolumeConfig, TexelFormat};
 
 pub mod camera;
+pub mod shaders;
+pub mod error;
+pub mod bake;
+
+pub use error::{ShaderError, ShaderStage};
 
 #[derive(UniformInterface)]
 pub struct ShaderInterface {
@@ -44,7 +61,16 @@ pub fn initialize(gl: &glow::Context) {
     }
 }
 
-pub fn get_3d_texture(gl: &glow::Context, w: i32, h: i32, d: i32) -> <glow::Context as glow::HasContext>::Texture {
+fn internal_format(format: TexelFormat) -> u32 {
+    match format {
+        TexelFormat::R16F => gl::R16F,
+        TexelFormat::RG16F => gl::RG16F,
+        TexelFormat::R32F => gl::R32F,
+        TexelFormat::RG32F => gl::RG32F,
+    }
+}
+
+pub fn get_3d_texture(gl: &glow::Context, w: i32, h: i32, d: i32, format: TexelFormat) -> <glow::Context as glow::HasContext>::Texture {
     unsafe {
         let gl_texture = gl.create_texture().expect("Failed to create texture!");
         // let mut gl_texture = 0;
@@ -52,7 +78,7 @@ pub fn get_3d_texture(gl: &glow::Context, w: i32, h: i32, d: i32) -> <glow::Cont
         // gl::ActiveTexture(gl::TEXTURE0);
         gl::BindTexture(gl::TEXTURE_3D, gl_texture);
 
-        gl.tex_image_3d(glow::TEXTURE_3D, 0, glow::RGBA32F as i32, w, h, d, 0, glow::RGBA, glow::FLOAT, None);
+        gl.tex_image_3d(glow::TEXTURE_3D, 0, internal_format(format) as i32, w, h, d, 0, glow::RG, glow::FLOAT, None);
 
         gl.tex_parameter_i32(glow::TEXTURE_3D, glow::TEXTURE_MIN_FILTER, glow::LINEAR as i32);
         gl.tex_parameter_i32(glow::TEXTURE_3D, glow::TEXTURE_MAG_FILTER, glow::LINEAR as i32);
@@ -63,12 +89,18 @@ pub fn get_3d_texture(gl: &glow::Context, w: i32, h: i32, d: i32) -> <glow::Cont
         gl.tex_parameter_i32(glow::TEXTURE_3D, glow::TEXTURE_WRAP_T, glow::CLAMP_TO_EDGE as i32);
         gl.tex_parameter_i32(glow::TEXTURE_3D, glow::TEXTURE_WRAP_R, glow::CLAMP_TO_EDGE as i32);
 
-        gl::BindImageTexture(0, gl_texture, 0, gl::TRUE, 0, gl::READ_WRITE, gl::RGBA32F);
-
         gl_texture
     }
 }
 
+//The bake shader is GLSL 4.50, without that the scene gets baked on the CPU
+pub fn supports_compute(gl: &glow::Context) -> bool {
+    unsafe {
+        let version = (gl.get_parameter_i32(glow::MAJOR_VERSION), gl.get_parameter_i32(glow::MINOR_VERSION));
+        version >= (4, 5)
+    }
+}
+
 pub fn get_workgroup_count(gl: &glow::Context) -> (i32, i32, i32) {
     unsafe {(
         gl.get_parameter_indexed_i32(glow::MAX_COMPUTE_WORK_GROUP_COUNT, 0),
@@ -91,49 +123,341 @@ pub fn get_workgroup_invocations(gl: &glow::Context) -> i32 {
     }
 }
 
-pub fn get_compute_program(gl: &glow::Context, cs: &str) -> <glow::Context as glow::HasContext>::Program {
+pub fn get_compute_program(gl: &glow::Context, cs: &str) -> Result<<glow::Context as glow::HasContext>::Program, ShaderError> {
     unsafe {
-        let shader = match gl.create_shader(glow::COMPUTE_SHADER) {
-            Ok(shader) => shader,
-            Err(err) => {
-                error!("{}", err);
-                panic!("Failed to compile compute shader!");
-            }
-        };
+        let shader = gl.create_shader(glow::COMPUTE_SHADER).map_err(|err| ShaderError::new(ShaderStage::Compute, err, cs, 0))?;
         gl.shader_source(shader, cs);
         gl.compile_shader(shader);
 
         if !gl.get_shader_compile_status(shader) {
-            error!("{}", gl.get_shader_info_log(shader));
-            panic!("Failed to compile compute shader!");
+            let log = gl.get_shader_info_log(shader);
+            gl.delete_shader(shader);
+            return Err(ShaderError::new(ShaderStage::Compute, log, cs, 0));
         }
 
-        let program = gl.create_program().expect("Failed to create compute shader program!");
+        let program = match gl.create_program() {
+            Ok(program) => program,
+            Err(err) => {
+                gl.delete_shader(shader);
+                return Err(ShaderError::new(ShaderStage::Link, err, cs, 0));
+            }
+        };
         gl.attach_shader(program, shader);
         gl.link_program(program);
 
+        gl.detach_shader(program, shader);
+        gl.delete_shader(shader);
+
         if !gl.get_program_link_status(program) {
-            error!("{}", gl.get_program_info_log(program));
-            panic!("Failed to compile compute program!");
+            let log = gl.get_program_info_log(program);
+            gl.delete_program(program);
+            return Err(ShaderError::new(ShaderStage::Link, log, cs, 0));
         }
 
-        gl.detach_shader(program, shader);
-        gl.delete_shader(shader);
+        Ok(program)
+    }
+}
+
+//Two float texture with an entry per brick, see BrickMap::indirection.
+//The ray marcher reads it with texelFetch, filtering slot numbers would make no sense.
+pub fn get_indirection_texture(gl: &glow::Context, map: &BrickMap) -> <glow::Context as glow::HasContext>::Texture {
+    unsafe {
+        let gl_texture = gl.create_texture().expect("Failed to create texture!");
+        gl::BindTexture(gl::TEXTURE_3D, gl_texture);
+        gl::TexImage3D(gl::TEXTURE_3D, 0, gl::RG32F as i32, map.grid[0] as i32, map.grid[1] as i32, map.grid[2] as i32, 0, gl::RG, gl::FLOAT, std::ptr::null());
+
+        gl.tex_parameter_i32(glow::TEXTURE_3D, glow::TEXTURE_MIN_FILTER, glow::NEAREST as i32);
+        gl.tex_parameter_i32(glow::TEXTURE_3D, glow::TEXTURE_MAG_FILTER, glow::NEAREST as i32);
+        gl.tex_parameter_i32(glow::TEXTURE_3D, glow::TEXTURE_WRAP_S, glow::CLAMP_TO_EDGE as i32);
+        gl.tex_parameter_i32(glow::TEXTURE_3D, glow::TEXTURE_WRAP_T, glow::CLAMP_TO_EDGE as i32);
+        gl.tex_parameter_i32(glow::TEXTURE_3D, glow::TEXTURE_WRAP_R, glow::CLAMP_TO_EDGE as i32);
 
-        program
+        gl_texture
     }
 }
 
-pub fn get_program(vs: &str, fs: &str) -> Program<VertexSemantics, (), ShaderInterface> {
-    let program: Program<VertexSemantics, (), ShaderInterface> = match Program::from_strings(None, vs, None, fs) {
-            Ok(program) => program.ignore_warnings(),
-            Err(err) => {
-                error!("{}", err);
-                panic!("Failed to compile shaders!");
+//The GPU side of a BrickMap: the atlas the bake writes the stored bricks into,
+//the indirection texture the ray marcher looks bricks up in and the list of bricks to bake
+pub struct BrickTextures {
+    pub atlas: <glow::Context as glow::HasContext>::Texture,
+    pub indirection: <glow::Context as glow::HasContext>::Texture,
+    bricks: <glow::Context as glow::HasContext>::Buffer,
+    format: TexelFormat,
+}
+
+impl BrickTextures {
+    pub fn new(gl: &glow::Context, map: &BrickMap, format: TexelFormat) -> BrickTextures {
+        let [w, h, d] = map.atlas_size();
+        BrickTextures {
+            atlas: get_3d_texture(gl, w as i32, h as i32, d as i32, format),
+            indirection: get_indirection_texture(gl, map),
+            bricks: unsafe { gl.create_buffer().expect("Failed to create buffer!") },
+            format,
+        }
+    }
+
+    pub fn delete(self, gl: &glow::Context) {
+        unsafe {
+            gl.delete_texture(self.atlas);
+            gl.delete_texture(self.indirection);
+            gl.delete_buffer(self.bricks);
+        }
+    }
+
+    //Has to happen after every BrickMap::update, or the ray marcher looks in the wrong slots
+    pub fn upload_indirection(&self, map: &BrickMap) {
+        let data = map.indirection();
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_3D, self.indirection);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            gl::TexSubImage3D(gl::TEXTURE_3D, 0, 0, 0, 0, map.grid[0] as i32, map.grid[1] as i32, map.grid[2] as i32, gl::RG, gl::FLOAT, data.as_ptr() as *const std::ffi::c_void);
+        }
+    }
+
+    //Slots of new bricks still hold whatever brick had them before, or nothing at all. Until the bake gets to them
+    //they read as a voxel away from the surface, so the ray marcher takes small steps through instead of hitting junk.
+    pub fn clear_slots(&self, map: &BrickMap, bricks: &[usize]) {
+        let unbaked = [1.0f32, 0.0].repeat(BRICK_SAMPLES * BRICK_SAMPLES * BRICK_SAMPLES);
+        for [_, _, _, slot] in map.bake_list(bricks.iter().copied()) {
+            self.upload_brick(map, slot as u32, &unbaked);
+        }
+    }
+
+    //Writes the samples of one brick into its slot, two floats per sample with x fastest like the bake shader writes them
+    pub fn upload_brick(&self, map: &BrickMap, slot: u32, texels: &[f32]) {
+        let [x, y, z] = map.slot_origin(slot);
+        let samples = BRICK_SAMPLES as i32;
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_3D, self.atlas);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            gl::TexSubImage3D(gl::TEXTURE_3D, 0, x as i32, y as i32, z as i32, samples, samples, samples, gl::RG, gl::FLOAT, texels.as_ptr() as *const std::ffi::c_void);
+        }
+    }
+}
+
+//Fills the atlas by running the bake compute shader over the given bricks, as (x, y, z, slot) like BrickMap::stored.
+//`volumes` are the textures of the mesh nodes, in the order of Scene::mesh_volumes, they go on units 1 and up.
+pub fn dispatch_bake(gl: &glow::Context, program: <glow::Context as glow::HasContext>::Program, textures: &BrickTextures, bricks: &[[i32; 4]], brick_binding: u32, volumes: &[Option<<glow::Context as glow::HasContext>::Texture>], heightmaps: &[Option<<glow::Context as glow::HasContext>::Texture>]) {
+    if bricks.is_empty() {
+        return;
+    }
+    unsafe {
+        gl::BindImageTexture(0, textures.atlas, 0, gl::TRUE, 0, gl::READ_WRITE, internal_format(textures.format));
+        gl.use_program(Some(program));
+        gl.active_texture(glow::TEXTURE0);
+        gl.bind_texture(glow::TEXTURE_3D, Some(textures.atlas));
+        gl.uniform_1_i32(gl.get_uniform_location(program, "img_output"), 0);
+        for (i, volume) in volumes.iter().enumerate() {
+            if let Some(volume) = volume {
+                let unit = i as u32 + 1;
+                gl.active_texture(glow::TEXTURE0 + unit);
+                gl.bind_texture(glow::TEXTURE_3D, Some(*volume));
+                let name = format!("{}{}", scene::codegen::MESH_UNIFORM, i);
+                gl.uniform_1_i32(gl.get_uniform_location(program, &name), unit as i32);
             }
-        };
+        }
+        //Heightmaps take the units after the volumes
+        for (i, heightmap) in heightmaps.iter().enumerate() {
+            if let Some(heightmap) = heightmap {
+                let unit = (volumes.len() + i) as u32 + 1;
+                gl.active_texture(glow::TEXTURE0 + unit);
+                gl.bind_texture(glow::TEXTURE_2D, Some(*heightmap));
+                let name = format!("{}{}", scene::codegen::HEIGHTMAP_UNIFORM, i);
+                gl.uniform_1_i32(gl.get_uniform_location(program, &name), unit as i32);
+            }
+        }
+        gl.active_texture(glow::TEXTURE0);
+
+        gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, textures.bricks);
+        gl::BufferData(gl::SHADER_STORAGE_BUFFER, std::mem::size_of_val(bricks) as isize, bricks.as_ptr() as *const std::ffi::c_void, gl::DYNAMIC_DRAW);
+        gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, brick_binding, textures.bricks);
+
+        //One work group per brick, split up to stay below the smallest work group count drivers allow
+        const MAX_GROUPS: usize = 65535;
+        let offset = gl.get_uniform_location(program, "brick_offset");
+        for start in (0..bricks.len()).step_by(MAX_GROUPS) {
+            gl.uniform_1_i32(offset, start as i32);
+            gl.dispatch_compute(MAX_GROUPS.min(bricks.len() - start) as u32, 1, 1);
+        }
+        gl::MemoryBarrier(gl::SHADER_IMAGE_ACCESS_BARRIER_BIT);
+    }
+}
+
+//Single channel float texture holding the distances of a volume as they are, in world units
+pub fn get_volume_texture(gl: &glow::Context, volume: &Volume) -> <glow::Context as glow::HasContext>::Texture {
+    unsafe {
+        let gl_texture = gl.create_texture().expect("Failed to create texture!");
+        gl::BindTexture(gl::TEXTURE_3D, gl_texture);
+        gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+        gl::TexImage3D(gl::TEXTURE_3D, 0, gl::R32F as i32, volume.size[0] as i32, volume.size[1] as i32, volume.size[2] as i32, 0, gl::RED, gl::FLOAT, volume.distances.as_ptr() as *const std::ffi::c_void);
+
+        gl.tex_parameter_i32(glow::TEXTURE_3D, glow::TEXTURE_MIN_FILTER, glow::LINEAR as i32);
+        gl.tex_parameter_i32(glow::TEXTURE_3D, glow::TEXTURE_MAG_FILTER, glow::LINEAR as i32);
+        gl.tex_parameter_i32(glow::TEXTURE_3D, glow::TEXTURE_WRAP_S, glow::CLAMP_TO_EDGE as i32);
+        gl.tex_parameter_i32(glow::TEXTURE_3D, glow::TEXTURE_WRAP_T, glow::CLAMP_TO_EDGE as i32);
+        gl.tex_parameter_i32(glow::TEXTURE_3D, glow::TEXTURE_WRAP_R, glow::CLAMP_TO_EDGE as i32);
+
+        gl_texture
+    }
+}
+
+//Single channel float texture with the heights as they are, the terrain height gets applied in the shader
+pub fn get_heightmap_texture(gl: &glow::Context, field: &HeightField) -> <glow::Context as glow::HasContext>::Texture {
+    unsafe {
+        let gl_texture = gl.create_texture().expect("Failed to create texture!");
+        gl::BindTexture(gl::TEXTURE_2D, gl_texture);
+        gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+        gl::TexImage2D(gl::TEXTURE_2D, 0, gl::R32F as i32, field.width as i32, field.depth as i32, 0, gl::RED, gl::FLOAT, field.values.as_ptr() as *const std::ffi::c_void);
+
+        gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MIN_FILTER, glow::LINEAR as i32);
+        gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MAG_FILTER, glow::LINEAR as i32);
+        gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_WRAP_S, glow::CLAMP_TO_EDGE as i32);
+        gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_WRAP_T, glow::CLAMP_TO_EDGE as i32);
+
+        gl_texture
+    }
+}
+
+//Anything scene nodes sample in the bake shader
+pub trait TextureSource {
+    fn upload(&self, gl: &glow::Context) -> <glow::Context as glow::HasContext>::Texture;
+}
+
+impl TextureSource for Volume {
+    fn upload(&self, gl: &glow::Context) -> <glow::Context as glow::HasContext>::Texture {
+        get_volume_texture(gl, self)
+    }
+}
+
+impl TextureSource for HeightField {
+    fn upload(&self, gl: &glow::Context) -> <glow::Context as glow::HasContext>::Texture {
+        get_heightmap_texture(gl, self)
+    }
+}
 
-    program
+//Textures for the baked meshes or heightmaps of a scene. They stay around as long as the scene uses the data,
+//so re-baking after an unrelated edit doesn't upload everything again
+pub struct TextureCache<T> {
+    entries: Vec<(Arc<T>, <glow::Context as glow::HasContext>::Texture)>,
+}
+
+pub type VolumeTextures = TextureCache<Volume>;
+pub type HeightmapTextures = TextureCache<HeightField>;
+
+impl<T: TextureSource> TextureCache<T> {
+    pub fn new() -> TextureCache<T> {
+        TextureCache {
+            entries: Vec::new(),
+        }
+    }
+
+    //A texture for every source, in the same order
+    pub fn update(&mut self, gl: &glow::Context, sources: &[Option<Arc<T>>]) -> Vec<Option<<glow::Context as glow::HasContext>::Texture>> {
+        let mut kept: Vec<(Arc<T>, <glow::Context as glow::HasContext>::Texture)> = Vec::new();
+        let mut textures = Vec::with_capacity(sources.len());
+        for source in sources {
+            let source = match source {
+                Some(source) => source,
+                None => {
+                    textures.push(None);
+                    continue;
+                },
+            };
+
+            let texture = if let Some((_, texture)) = kept.iter().find(|(s, _)| Arc::ptr_eq(s, source)) {
+                *texture
+            } else {
+                let texture = match self.entries.iter().position(|(s, _)| Arc::ptr_eq(s, source)) {
+                    Some(i) => self.entries.swap_remove(i).1,
+                    None => source.upload(gl),
+                };
+                kept.push((source.clone(), texture));
+                texture
+            };
+            textures.push(Some(texture));
+        }
+
+        //Whatever is left isn't used anymore
+        for (_, texture) in self.entries.drain(..) {
+            unsafe {
+                gl.delete_texture(texture);
+            }
+        }
+        self.entries = kept;
+        textures
+    }
+}
+
+//Copies the baked atlas back to the CPU and puts the bricks back together into a dense volume.
+//The bake stores distances in multiples of the voxel size in r and the material in g, this undoes that
+//so the result matches Volume::from_scene near the surface. Empty bricks only get their distance bound.
+pub fn read_volume(textures: &BrickTextures, map: &BrickMap, config: &VolumeConfig) -> Volume {
+    let [w, h, d] = map.atlas_size();
+    let mut texels = vec![0.0f32; w * h * d * 2];
+    unsafe {
+        //Only there with compute shaders, which are the only thing that needs it
+        if gl::MemoryBarrier::is_loaded() {
+            gl::MemoryBarrier(gl::TEXTURE_UPDATE_BARRIER_BIT);
+        }
+        gl::BindTexture(gl::TEXTURE_3D, textures.atlas);
+        gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+        gl::GetTexImage(gl::TEXTURE_3D, 0, gl::RG, gl::FLOAT, texels.as_mut_ptr() as *mut std::ffi::c_void);
+    }
+
+    let voxel_size = config.voxel_size();
+    map.to_volume(config.origin, config.spacing(), voxel_size, |[x, y, z]| {
+        let i = (x + w * (y + h * z)) * 2;
+        (texels[i] * voxel_size, texels[i + 1].round().max(0.0) as u32)
+    })
+}
+
+//The other way around, picks the bricks near the surface of the volume and writes them into the atlas
+//in the same layout the bake uses. The volume has to be laid out like the config already, see Volume::resample.
+pub fn upload_volume(textures: &BrickTextures, map: &mut BrickMap, volume: &Volume, config: &VolumeConfig) {
+    let voxel_size = config.voxel_size();
+    map.clear();
+    let update = map.update(|v| volume.sample_unbounded(config.position(v)) / voxel_size);
+    if update.overflow > 0 {
+        warn!("{} bricks of the volume didn't fit in the atlas and will show up as holes", update.overflow);
+    }
+    textures.upload_indirection(map);
+
+    let clamp = |v: usize, size: usize| v.min(size - 1);
+    let mut texels = Vec::with_capacity(BRICK_SAMPLES * BRICK_SAMPLES * BRICK_SAMPLES * 2);
+    for [bx, by, bz, slot] in map.stored() {
+        texels.clear();
+        for z in 0..BRICK_SAMPLES {
+            for y in 0..BRICK_SAMPLES {
+                for x in 0..BRICK_SAMPLES {
+                    let i = volume.index(
+                        clamp(bx as usize * BRICK_SIZE + x, volume.size[0]),
+                        clamp(by as usize * BRICK_SIZE + y, volume.size[1]),
+                        clamp(bz as usize * BRICK_SIZE + z, volume.size[2]),
+                    );
+                    texels.extend_from_slice(&[volume.distances[i] / voxel_size, volume.materials[i] as f32]);
+                }
+            }
+        }
+        textures.upload_brick(map, slot as u32, &texels);
+    }
+}
+
+//Luminance prepends a #version and #extension line to every stage,
+//which shifts the line numbers in the info log
+const LUMINANCE_HEADER_LINES: u32 = 2;
+
+pub fn get_program(vs: &str, fs: &str) -> Result<Program<VertexSemantics, (), ShaderInterface>, ShaderError> {
+    match Program::from_strings(None, vs, None, fs) {
+        Ok(program) => Ok(program.ignore_warnings()),
+        Err(ProgramError::StageError(StageError::CompilationFailed(StageType::VertexShader, log))) => {
+            Err(ShaderError::new(ShaderStage::Vertex, log, vs, LUMINANCE_HEADER_LINES))
+        },
+        Err(ProgramError::StageError(StageError::CompilationFailed(StageType::FragmentShader, log))) => {
+            Err(ShaderError::new(ShaderStage::Fragment, log, fs, LUMINANCE_HEADER_LINES))
+        },
+        Err(ProgramError::LinkFailed(log)) => Err(ShaderError::new(ShaderStage::Link, log, fs, LUMINANCE_HEADER_LINES)),
+        Err(err) => Err(ShaderError::new(ShaderStage::Link, err.to_string(), fs, LUMINANCE_HEADER_LINES)),
+    }
 }
 
 pub fn get_screen_rect<C>(ctx: &mut C) -> Tess
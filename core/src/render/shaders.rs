@@ -0,0 +1,44 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const VERTEX_FILE: &str = "vertex.glsl";
+pub const FRAGMENT_FILE: &str = "fragment.glsl";
+pub const COMPUTE_FILE: &str = "compute.glsl";
+
+//Sources of all the shaders the viewer uses.
+//Normally these are embedded in the binary, but for hot reloading they get read from disk instead.
+pub struct ShaderSources {
+    pub vertex: String,
+    pub fragment: String,
+    //Only the distance function library, see scene::codegen
+    pub compute: String,
+}
+
+impl ShaderSources {
+    pub fn embedded() -> ShaderSources {
+        ShaderSources {
+            vertex: include_str!("../vertex.glsl").to_string(),
+            fragment: include_str!("../fragment.glsl").to_string(),
+            compute: include_str!("../compute.glsl").to_string(),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(dir: P) -> io::Result<ShaderSources> {
+        let dir = dir.as_ref();
+        Ok(ShaderSources {
+            vertex: fs::read_to_string(dir.join(VERTEX_FILE))?,
+            fragment: fs::read_to_string(dir.join(FRAGMENT_FILE))?,
+            compute: fs::read_to_string(dir.join(COMPUTE_FILE))?,
+        })
+    }
+
+    //The directory the embedded shaders came from, handy when running from the repo
+    pub fn source_dir() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("src")
+    }
+
+    pub fn paths(dir: &Path) -> Vec<PathBuf> {
+        vec![dir.join(VERTEX_FILE), dir.join(FRAGMENT_FILE), dir.join(COMPUTE_FILE)]
+    }
+}
@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::fs;
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use super::{Scene, Node};
+use super::heightmap::HeightField;
+use crate::mesh::{self, sdf::SignMethod};
+use crate::volume::Volume;
+
+//Everything scene nodes load from other files: baked meshes by file and settings, heightmaps by file.
+//Editing the rest of the scene doesn't load or bake any of it again.
+//Failures are remembered too, otherwise a missing file would get retried every frame.
+//They're only kept as long as the file stays the same though, so creating or fixing it gets picked up.
+pub struct AssetCache {
+    meshes: HashMap<(PathBuf, u32, SignMethod), Entry<Volume>>,
+    heightmaps: HashMap<PathBuf, Entry<HeightField>>,
+}
+
+enum Entry<T> {
+    Loaded(Arc<T>),
+    //When the file was last modified at the time it failed, None if it didn't exist
+    Failed(Option<SystemTime>),
+}
+
+//Relative paths in a scene are relative to the directory of the scene file, not the working directory
+pub fn scene_dir(scene_path: &str) -> PathBuf {
+    Path::new(scene_path).parent().map(Path::to_path_buf).unwrap_or_default()
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+//The cached value, or whatever loading it again gives if it's missing or the file changed since it failed.
+//The flag says whether it just got loaded.
+fn load<K, T, F>(cache: &mut HashMap<K, Entry<T>>, key: K, path: &Path, errors: &mut Vec<String>, load: F) -> (Option<Arc<T>>, bool)
+where
+    K: Eq + Hash,
+    F: FnOnce() -> Result<T, String>,
+{
+    let modified = match cache.get(&key) {
+        Some(Entry::Loaded(value)) => return (Some(value.clone()), false),
+        Some(Entry::Failed(failed)) => {
+            let modified = modified(path);
+            if *failed == modified {
+                return (None, false);
+            }
+            modified
+        },
+        None => modified(path),
+    };
+
+    match load() {
+        Ok(value) => {
+            let value = Arc::new(value);
+            cache.insert(key, Entry::Loaded(value.clone()));
+            (Some(value), true)
+        },
+        Err(err) => {
+            errors.push(err);
+            cache.insert(key, Entry::Failed(modified));
+            (None, false)
+        },
+    }
+}
+
+//What a call to AssetCache::resolve did
+#[derive(Debug, Default)]
+pub struct Resolved {
+    //Files that failed to load since the last time they changed
+    pub errors: Vec<String>,
+    //Top level nodes that got a mesh or heightmap loaded. Nodes don't compare their loaded data,
+    //so these look unchanged to the DirtyTracker and have to be marked by hand.
+    pub loaded: Vec<usize>,
+}
+
+impl AssetCache {
+    pub fn new() -> AssetCache {
+        AssetCache {
+            meshes: HashMap::new(),
+            heightmaps: HashMap::new(),
+        }
+    }
+
+    //Hands every node in the scene its data, loading whatever isn't cached yet.
+    //Relative paths get looked up in `dir`, see scene_dir.
+    pub fn resolve(&mut self, scene: &mut Scene, dir: &Path) -> Resolved {
+        let mut resolved = Resolved::default();
+        for (index, node) in scene.nodes.iter_mut().enumerate() {
+            if self.resolve_node(node, dir, &mut resolved.errors) {
+                resolved.loaded.push(index);
+            }
+        }
+        resolved
+    }
+
+    //Whether anything in the node got loaded
+    fn resolve_node(&mut self, node: &mut Node, dir: &Path, errors: &mut Vec<String>) -> bool {
+        match node {
+            Node::Mesh { mesh: mesh_volume, .. } => {
+                let path = dir.join(&mesh_volume.path);
+                let key = (path.clone(), mesh_volume.resolution, mesh_volume.sign);
+                let (volume, loaded) = load(&mut self.meshes, key, &path, errors, || {
+                    mesh::import::load(&path)
+                        .map(|m| mesh::sdf::bake_mesh(&m, mesh_volume.resolution as usize, mesh_volume.sign))
+                        .map_err(|err| format!("Failed to load mesh {}: {}", path.display(), err))
+                });
+                mesh_volume.volume = volume;
+                loaded
+            },
+            Node::Heightmap { heightmap, .. } => {
+                let path = dir.join(&heightmap.path);
+                let (field, loaded) = load(&mut self.heightmaps, path.clone(), &path, errors, || {
+                    HeightField::load(&path).map_err(|err| format!("Failed to load heightmap {}: {}", path.display(), err))
+                });
+                heightmap.field = field;
+                loaded
+            },
+            Node::Transform { child, .. } | Node::Modifier { child, .. } => self.resolve_node(child, dir, errors),
+            //Every child gets resolved, not just the ones up to the first load
+            Node::Operation { children, .. } => children.iter_mut().fold(false, |loaded, child| self.resolve_node(child, dir, errors) | loaded),
+            Node::Primitive { .. } => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::MeshVolume;
+
+    const TETRAHEDRON: &str = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 0 1\nf 1 3 2\nf 1 2 4\nf 1 4 3\nf 2 3 4\n";
+
+    fn mesh_scene(path: &str) -> Scene {
+        let mut mesh = MeshVolume::new(path);
+        mesh.resolution = 8;
+        let mut scene = Scene::new();
+        scene.add(Node::mesh(mesh, 0));
+        scene
+    }
+
+    fn mesh_volume(scene: &Scene) -> &MeshVolume {
+        match &scene.nodes[0] {
+            Node::Mesh { mesh, .. } => mesh,
+            _ => unreachable!(),
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn relative_to_scene_file() {
+        let dir = temp_dir("sdf_preview_assets_relative");
+        fs::write(dir.join("tetrahedron.obj"), TETRAHEDRON).unwrap();
+        assert_eq!(scene_dir(dir.join("scene.ron").to_str().unwrap()), dir);
+        assert_eq!(scene_dir("scene.ron"), PathBuf::new());
+
+        let mut scene = mesh_scene("tetrahedron.obj");
+        let mut cache = AssetCache::new();
+        let resolved = cache.resolve(&mut scene, &dir);
+        assert!(resolved.errors.is_empty());
+        assert_eq!(resolved.loaded, vec![0]);
+        assert!(mesh_volume(&scene).volume.is_some());
+
+        //Cached from now on
+        assert!(cache.resolve(&mut scene, &dir).loaded.is_empty());
+    }
+
+    #[test]
+    fn failures_get_retried_once_the_file_changes() {
+        let dir = temp_dir("sdf_preview_assets_retry");
+        let mut scene = mesh_scene("tetrahedron.obj");
+        let mut cache = AssetCache::new();
+
+        assert_eq!(cache.resolve(&mut scene, &dir).errors.len(), 1);
+        //Still missing, so it's neither loaded nor reported again
+        let resolved = cache.resolve(&mut scene, &dir);
+        assert!(resolved.errors.is_empty() && resolved.loaded.is_empty());
+        assert!(mesh_volume(&scene).volume.is_none());
+
+        fs::write(dir.join("tetrahedron.obj"), TETRAHEDRON).unwrap();
+        let resolved = cache.resolve(&mut scene, &dir);
+        assert!(resolved.errors.is_empty());
+        assert_eq!(resolved.loaded, vec![0]);
+        assert!(mesh_volume(&scene).volume.is_some());
+    }
+}
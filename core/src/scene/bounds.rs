@@ -0,0 +1,431 @@
+use cgmath::*;
+
+use super::{Scene, Node, Primitive, Operation, Blend, Modifier, Transform};
+
+//Axis aligned box in world space. Boxes with min > max are empty,
+//infinite coordinates are fine for things like planes and repetition.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+
+impl Aabb {
+    pub fn new(min: Vector3<f32>, max: Vector3<f32>) -> Aabb {
+        Aabb { min, max }
+    }
+
+    pub fn empty() -> Aabb {
+        Aabb::new(Vector3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY), Vector3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY))
+    }
+
+    pub fn everything() -> Aabb {
+        Aabb::new(Vector3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY), Vector3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY))
+    }
+
+    //Centred on the origin
+    pub fn centred(half_extents: Vector3<f32>) -> Aabb {
+        Aabb::new(-half_extents, half_extents)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb::new(
+            Vector3::new(self.min.x.min(other.min.x), self.min.y.min(other.min.y), self.min.z.min(other.min.z)),
+            Vector3::new(self.max.x.max(other.max.x), self.max.y.max(other.max.y), self.max.z.max(other.max.z)),
+        )
+    }
+
+    pub fn intersection(&self, other: &Aabb) -> Aabb {
+        Aabb::new(
+            Vector3::new(self.min.x.max(other.min.x), self.min.y.max(other.min.y), self.min.z.max(other.min.z)),
+            Vector3::new(self.max.x.min(other.max.x), self.max.y.min(other.max.y), self.max.z.min(other.max.z)),
+        )
+    }
+
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        !self.intersection(other).is_empty()
+    }
+
+    //Grows every side by `amount`, empty boxes stay empty
+    pub fn expand(&self, amount: f32) -> Aabb {
+        if self.is_empty() {
+            return *self;
+        }
+        let amount = Vector3::new(amount, amount, amount);
+        Aabb::new(self.min - amount, self.max + amount)
+    }
+
+    pub fn corners(&self) -> [Vector3<f32>; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            Vector3::new(a.x, a.y, a.z), Vector3::new(b.x, a.y, a.z), Vector3::new(a.x, b.y, a.z), Vector3::new(b.x, b.y, a.z),
+            Vector3::new(a.x, a.y, b.z), Vector3::new(b.x, a.y, b.z), Vector3::new(a.x, b.y, b.z), Vector3::new(b.x, b.y, b.z),
+        ]
+    }
+
+    //Box around the transformed box, from the local space of the transform into its parent
+    pub fn transformed(&self, transform: &Transform) -> Aabb {
+        if self.is_empty() {
+            return *self;
+        }
+        if !is_finite(self.min) || !is_finite(self.max) {
+            return Aabb::everything();
+        }
+        self.corners().iter().fold(Aabb::empty(), |bounds, corner| {
+            let p = transform.position + transform.rotation.rotate_vector(corner * transform.scale);
+            bounds.union(&Aabb::new(p, p))
+        })
+    }
+
+    //Furthest any point of the box gets from the origin
+    fn radius(&self) -> f32 {
+        self.corners().iter().map(|c| c.magnitude()).fold(0.0, f32::max)
+    }
+}
+
+fn is_finite(v: Vector3<f32>) -> bool {
+    v.x.is_finite() && v.y.is_finite() && v.z.is_finite()
+}
+
+impl Primitive {
+    //Box around the primitive in its local space
+    pub fn bounds(&self) -> Aabb {
+        match *self {
+            Primitive::Sphere { radius } => Aabb::centred(Vector3::new(radius, radius, radius)),
+            Primitive::Plane { .. } => Aabb::everything(),
+            Primitive::Box { half_extents } | Primitive::RoundBox { half_extents, .. } => Aabb::centred(half_extents),
+            Primitive::Torus { major_radius, minor_radius } => {
+                let r = major_radius + minor_radius;
+                Aabb::centred(Vector3::new(r, minor_radius, r))
+            },
+            Primitive::Capsule { half_height, radius } => Aabb::centred(Vector3::new(radius, half_height + radius, radius)),
+            Primitive::Cylinder { half_height, radius } => Aabb::centred(Vector3::new(radius, half_height, radius)),
+            Primitive::Cone { half_height, bottom_radius, top_radius } => {
+                let r = bottom_radius.max(top_radius);
+                Aabb::centred(Vector3::new(r, half_height, r))
+            },
+            //The distance bound of the ellipsoid falls well short of the real distance off the axes,
+            //it only catches up with the distance to the box once that is twice the longest radius
+            Primitive::Ellipsoid { radii } => {
+                let r = radii.x.max(radii.y).max(radii.z) * 2.0;
+                Aabb::centred(Vector3::new(r, r, r))
+            },
+            //`radius` is the distance to the flat sides, the corners stick out further
+            Primitive::HexPrism { radius, half_length } => {
+                let corner = radius * 2.0 / 3.0f32.sqrt();
+                Aabb::centred(Vector3::new(corner, corner, half_length))
+            },
+            Primitive::Link { half_length, major_radius, minor_radius } => {
+                let r = major_radius + minor_radius;
+                Aabb::centred(Vector3::new(r, half_length + r, minor_radius))
+            },
+        }
+    }
+}
+
+impl Modifier {
+    //Box around what the modifier makes of a child with the given bounds
+    pub fn bounds(&self, child: &Aabb) -> Aabb {
+        if child.is_empty() {
+            return *child;
+        }
+        match *self {
+            Modifier::Repeat { period } => {
+                let axis = |min: f32, max: f32, period: f32| if period == 0.0 { (min, max) } else { (f32::NEG_INFINITY, f32::INFINITY) };
+                let (x, y, z) = (axis(child.min.x, child.max.x, period.x), axis(child.min.y, child.max.y, period.y), axis(child.min.z, child.max.z, period.z));
+                Aabb::new(Vector3::new(x.0, y.0, z.0), Vector3::new(x.1, y.1, z.1))
+            },
+            Modifier::RepeatLimited { period, count } => {
+                let reach = abs(period.mul_element_wise(count));
+                Aabb::new(child.min - reach, child.max + reach)
+            },
+            Modifier::Mirror { x, y, z } => {
+                let axis = |min: f32, max: f32, mirrored: bool| {
+                    let m = min.abs().max(max.abs());
+                    if mirrored { (-m, m) } else { (min, max) }
+                };
+                let (x, y, z) = (axis(child.min.x, child.max.x, x), axis(child.min.y, child.max.y, y), axis(child.min.z, child.max.z, z));
+                Aabb::new(Vector3::new(x.0, y.0, z.0), Vector3::new(x.1, y.1, z.1))
+            },
+            //Twisting spins slices around the y axis, so the child can end up anywhere around it
+            Modifier::Twist { .. } => {
+                let r = child.corners().iter().map(|c| Vector2::new(c.x, c.z).magnitude()).fold(0.0, f32::max);
+                Aabb::new(Vector3::new(-r, child.min.y, -r), Vector3::new(r, child.max.y, r))
+            },
+            Modifier::Bend { .. } => {
+                let r = child.radius();
+                Aabb::new(Vector3::new(-r, -r, child.min.z), Vector3::new(r, r, child.max.z))
+            },
+            Modifier::Elongate { amount } => {
+                let amount = abs(amount);
+                Aabb::new(child.min - amount, child.max + amount)
+            },
+            Modifier::Round { radius } => child.expand(radius.max(0.0)),
+            Modifier::Onion { thickness } => child.expand(thickness.abs()),
+        }
+    }
+}
+
+fn abs(v: Vector3<f32>) -> Vector3<f32> {
+    Vector3::new(v.x.abs(), v.y.abs(), v.z.abs())
+}
+
+impl Blend {
+    //How far past the hard version of the operation the blend can add material
+    pub fn reach(&self) -> f32 {
+        match *self {
+            Blend::Hard => 0.0,
+            Blend::Smooth { radius } | Blend::Exponential { radius } | Blend::Chamfer { radius } | Blend::Stairs { radius, .. } => radius.abs(),
+        }
+    }
+}
+
+impl Node {
+    //Box in the parent space that holds everything the node puts into the scene.
+    //Outside of it the node's distance is at least the distance to the box, give or take its Lipschitz bound.
+    pub fn bounds(&self) -> Aabb {
+        match self {
+            Node::Primitive { primitive, .. } => primitive.bounds(),
+            Node::Mesh { mesh, .. } => match &mesh.volume {
+                Some(volume) => Aabb::new(volume.origin, volume.origin + volume.extent),
+                None => Aabb::empty(),
+            },
+            Node::Heightmap { heightmap, .. } => match &heightmap.field {
+                Some(_) => Aabb::new(
+                    Vector3::new(-heightmap.size.x * 0.5, 0.0, -heightmap.size.y * 0.5),
+                    Vector3::new(heightmap.size.x * 0.5, heightmap.height, heightmap.size.y * 0.5),
+                ),
+                None => Aabb::empty(),
+            },
+            Node::Transform { transform, child } => child.bounds().transformed(transform),
+            Node::Modifier { modifier, child } => modifier.bounds(&child.bounds()),
+            Node::Operation { operation, blend, children } => {
+                let mut children = children.iter().map(|c| c.bounds());
+                let first = match children.next() {
+                    Some(first) => first,
+                    None => return Aabb::empty(),
+                };
+                let bounds = match operation {
+                    Operation::Union => children.fold(first, |a, b| a.union(&b)),
+                    //Cutting things out never adds anything outside the first child
+                    Operation::Subtraction => first,
+                    Operation::Intersection => children.fold(first, |a, b| a.intersection(&b)),
+                };
+                bounds.expand(blend.reach())
+            },
+        }
+    }
+}
+
+//Remembers the top level nodes of the scene as they were baked, so the next bake
+//can be limited to the parts of the world that changed since then
+pub struct DirtyTracker {
+    baked: Option<Vec<Node>>,
+    //Regions that changed without the nodes looking any different, see mark
+    marked: Vec<(Aabb, f32)>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Dirty {
+    Nothing,
+    Everything,
+    //Bounds of the nodes that got added or removed, an edited node counts as both.
+    //Each comes with the Lipschitz bound of its node, which says how much to grow the box by.
+    Regions(Vec<(Aabb, f32)>),
+}
+
+impl DirtyTracker {
+    pub fn new() -> DirtyTracker {
+        DirtyTracker {
+            baked: None,
+            marked: Vec::new(),
+        }
+    }
+
+    //The volume got filled with something that isn't the scene, or by a different bake shader
+    pub fn invalidate(&mut self) {
+        self.baked = None;
+        self.marked.clear();
+    }
+
+    //Has the next update include the node, for when its loaded mesh or heightmap changed
+    pub fn mark(&mut self, node: &Node) {
+        self.marked.push((node.bounds(), node.lipschitz()));
+    }
+
+    //What changed since the last call, which is then taken as baked.
+    //Top level nodes are a union, so their order doesn't matter.
+    pub fn update(&mut self, scene: &Scene) -> Dirty {
+        let marked = std::mem::take(&mut self.marked);
+        let baked = match self.baked.replace(scene.nodes.clone()) {
+            Some(baked) => baked,
+            None => return Dirty::Everything,
+        };
+
+        let mut unmatched: Vec<Option<&Node>> = baked.iter().map(Some).collect();
+        let mut regions = marked;
+        for node in &scene.nodes {
+            match unmatched.iter_mut().find(|n| **n == Some(node)) {
+                Some(matched) => *matched = None,
+                None => regions.push((node.bounds(), node.lipschitz())),
+            }
+        }
+        for node in unmatched.into_iter().flatten() {
+            regions.push((node.bounds(), node.lipschitz()));
+        }
+
+        regions.retain(|(bounds, _)| !bounds.is_empty());
+        if regions.is_empty() {
+            Dirty::Nothing
+        } else {
+            Dirty::Regions(regions)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::scene::MeshVolume;
+    use crate::volume::Volume;
+
+    fn contains(bounds: &Aabb, p: Vector3<f32>) -> bool {
+        p.x >= bounds.min.x && p.y >= bounds.min.y && p.z >= bounds.min.z && p.x <= bounds.max.x && p.y <= bounds.max.y && p.z <= bounds.max.z
+    }
+
+    //Samples a grid around the bounds, every point on or inside the surface has to be in them
+    fn check_bounds(node: &Node) {
+        let bounds = node.bounds();
+        assert!(!bounds.is_empty());
+        //Infinite sides get cut off, a little past the finite ones
+        let clamp = |v: f32| v.clamp(-100.0, 100.0);
+        let min = Vector3::new(clamp(bounds.min.x), clamp(bounds.min.y), clamp(bounds.min.z)) - Vector3::new(8.0, 8.0, 8.0);
+        let max = Vector3::new(clamp(bounds.max.x), clamp(bounds.max.y), clamp(bounds.max.z)) + Vector3::new(8.0, 8.0, 8.0);
+
+        let steps = 40;
+        for z in 0..=steps {
+            for y in 0..=steps {
+                for x in 0..=steps {
+                    let t = Vector3::new(x as f32, y as f32, z as f32) / steps as f32;
+                    let p = min + (max - min).mul_element_wise(t);
+                    let dist = node.sample(p).dist;
+                    assert!(dist > 0.0 || contains(&bounds, p), "{:?} has a distance of {} at {:?}, outside of {:?}", node, dist, p, bounds);
+                }
+            }
+        }
+    }
+
+    //Off centre and a different size along every axis, so mixed up axes show
+    fn child() -> Node {
+        Node::primitive(Primitive::Box { half_extents: Vector3::new(8.0, 4.0, 6.0) }, 0).translated(Vector3::new(3.0, 2.0, -1.0))
+    }
+
+    #[test]
+    fn primitives_are_inside_their_bounds() {
+        for primitive in Primitive::all() {
+            check_bounds(&Node::primitive(primitive, 0));
+        }
+    }
+
+    #[test]
+    fn modifiers_are_inside_their_bounds() {
+        let strong = [Modifier::Twist { rate: 0.2, extent: 16.0 }, Modifier::Bend { rate: 0.1, extent: 16.0 }];
+        for modifier in Modifier::all().into_iter().chain(strong.iter().copied()) {
+            check_bounds(&Node::modifier(modifier, child()));
+        }
+    }
+
+    #[test]
+    fn operations_are_inside_their_bounds() {
+        let other = Node::primitive(Primitive::Sphere { radius: 6.0 }, 0).translated(Vector3::new(10.0, 0.0, 0.0));
+        for operation in Operation::all().iter() {
+            for blend in Blend::all().iter() {
+                check_bounds(&Node::blended(*operation, *blend, vec![child(), other.clone()]));
+            }
+        }
+    }
+
+    #[test]
+    fn transformed_bounds() {
+        let rotation = Quaternion::from_axis_angle(Vector3::new(1.0, 1.0, 0.0).normalize(), Deg(40.0));
+        check_bounds(&Node::transform(Transform::new(Vector3::new(5.0, -3.0, 2.0), rotation, 1.5), child()));
+    }
+
+    fn tracked(nodes: Vec<Node>) -> (DirtyTracker, Scene) {
+        let mut scene = Scene::new();
+        scene.nodes = nodes;
+        let mut tracker = DirtyTracker::new();
+        assert_eq!(tracker.update(&scene), Dirty::Everything);
+        (tracker, scene)
+    }
+
+    fn sphere(x: f32) -> Node {
+        Node::primitive(Primitive::Sphere { radius: 4.0 }, 0).translated(Vector3::new(x, 0.0, 0.0))
+    }
+
+    fn region(node: &Node) -> (Aabb, f32) {
+        (node.bounds(), node.lipschitz())
+    }
+
+    #[test]
+    fn unchanged_scene_is_clean() {
+        let (mut tracker, scene) = tracked(vec![sphere(0.0), sphere(20.0)]);
+        assert_eq!(tracker.update(&scene), Dirty::Nothing);
+        assert_eq!(tracker.update(&scene), Dirty::Nothing);
+    }
+
+    #[test]
+    fn added_and_removed_nodes_are_dirty() {
+        let (mut tracker, mut scene) = tracked(vec![sphere(0.0)]);
+        scene.add(sphere(20.0));
+        assert_eq!(tracker.update(&scene), Dirty::Regions(vec![region(&sphere(20.0))]));
+
+        scene.nodes.remove(0);
+        assert_eq!(tracker.update(&scene), Dirty::Regions(vec![region(&sphere(0.0))]));
+    }
+
+    #[test]
+    fn edited_nodes_are_dirty_where_they_were_and_are() {
+        let (mut tracker, mut scene) = tracked(vec![sphere(0.0), sphere(20.0)]);
+        scene.nodes[1] = sphere(40.0);
+        assert_eq!(tracker.update(&scene), Dirty::Regions(vec![region(&sphere(40.0)), region(&sphere(20.0))]));
+    }
+
+    #[test]
+    fn reordered_nodes_are_clean() {
+        let (mut tracker, mut scene) = tracked(vec![sphere(0.0), sphere(20.0), sphere(40.0)]);
+        scene.nodes.reverse();
+        assert_eq!(tracker.update(&scene), Dirty::Nothing);
+    }
+
+    #[test]
+    fn invalidate_dirties_everything() {
+        let (mut tracker, scene) = tracked(vec![sphere(0.0)]);
+        tracker.invalidate();
+        assert_eq!(tracker.update(&scene), Dirty::Everything);
+    }
+
+    #[test]
+    fn marked_nodes_get_rebaked() {
+        let mut scene = Scene::new();
+        scene.add(Node::mesh(MeshVolume::new("mesh.obj"), 0));
+        let mut tracker = DirtyTracker::new();
+        assert_eq!(tracker.update(&scene), Dirty::Everything);
+
+        //The mesh showing up doesn't make the node compare any different
+        if let Node::Mesh { mesh, .. } = &mut scene.nodes[0] {
+            mesh.volume = Some(Arc::new(Volume::new([4, 4, 4], Vector3::new(1.0, 2.0, 3.0), Vector3::new(4.0, 4.0, 4.0))));
+        }
+        assert_eq!(tracker.update(&scene), Dirty::Nothing);
+
+        tracker.mark(&scene.nodes[0]);
+        let bounds = Aabb::new(Vector3::new(1.0, 2.0, 3.0), Vector3::new(5.0, 6.0, 7.0));
+        assert_eq!(tracker.update(&scene), Dirty::Regions(vec![(bounds, scene.nodes[0].lipschitz())]));
+        assert_eq!(tracker.update(&scene), Dirty::Nothing);
+    }
+}
@@ -0,0 +1,400 @@
+use std::fmt::Write;
+
+use cgmath::*;
+
+use super::{Scene, Node, Primitive, Operation, Blend, Modifier, Transform};
+use super::eval::EMPTY_DISTANCE;
+use crate::volume::bricks::{BRICK_SIZE, BRICK_SAMPLES};
+use crate::volume::config::VolumeConfig;
+
+pub struct ShaderOptions {
+    pub volume: VolumeConfig,
+    //Image unit of the brick atlas
+    pub binding: u32,
+    //Shader storage binding of the list of bricks to bake
+    pub brick_binding: u32,
+}
+
+impl ShaderOptions {
+    pub fn default() -> ShaderOptions {
+        ShaderOptions {
+            volume: VolumeConfig::default(),
+            binding: 0,
+            brick_binding: 1,
+        }
+    }
+}
+
+//Generates the full bake compute shader for a scene.
+//`library` is the source of compute.glsl, which contains the distance functions and operators.
+//The output only depends on the inputs, so it can be compared against stored snapshots.
+pub fn generate_compute_shader(scene: &Scene, library: &str, options: &ShaderOptions) -> String {
+    let mut src = String::new();
+
+    writeln!(src, "#version 450").unwrap();
+    writeln!(src).unwrap();
+    //One work group per brick, with an invocation for every sample. BRICK_SAMPLES^3 is 729,
+    //below the 1024 invocations every driver has to support.
+    writeln!(src, "layout(local_size_x = {0}, local_size_y = {0}, local_size_z = {0}) in;", BRICK_SAMPLES).unwrap();
+    writeln!(src, "layout({}, binding = {}) uniform image3D img_output;", options.volume.format.glsl_name(), options.binding).unwrap();
+    writeln!(src, "layout(std430, binding = {}) readonly buffer Bricks {{ ivec4 bricks[]; }};", options.brick_binding).unwrap();
+    writeln!(src).unwrap();
+    src.push_str(&generate_volume_defines(&options.volume));
+    writeln!(src).unwrap();
+    src.push_str(library);
+    if !library.ends_with('\n') {
+        src.push('\n');
+    }
+    writeln!(src).unwrap();
+    src.push_str(&generate_map(scene));
+    writeln!(src).unwrap();
+    src.push_str(MAIN);
+
+    src
+}
+
+//The render shader only needs the volume layout, luminance adds the #version line in front of this
+pub fn generate_fragment_shader(source: &str, options: &ShaderOptions) -> String {
+    let mut src = generate_volume_defines(&options.volume);
+    writeln!(src).unwrap();
+    src.push_str(source);
+    src
+}
+
+//Layout of the baked volume, shared by the bake and the render shader
+pub fn generate_volume_defines(volume: &VolumeConfig) -> String {
+    let mut src = String::new();
+    writeln!(src, "#define VOLUME_ORIGIN {}", vec3(volume.origin)).unwrap();
+    writeln!(src, "#define VOLUME_EXTENT {}", vec3(volume.extent)).unwrap();
+    writeln!(src, "#define VOXEL_SPACING {}", vec3(volume.spacing())).unwrap();
+    writeln!(src, "#define VOXEL_SIZE {}", float(volume.voxel_size())).unwrap();
+    writeln!(src, "#define BRICK_SIZE {}", BRICK_SIZE).unwrap();
+    writeln!(src, "#define BRICK_SAMPLES {}", BRICK_SAMPLES).unwrap();
+    src
+}
+
+//Generates `vec2 map(vec3 p)`, returning vec2(distance, material id)
+pub fn generate_map(scene: &Scene) -> String {
+    let mut gen = Generator {
+        code: String::new(),
+        next_id: 0,
+        meshes: 0,
+        heightmaps: 0,
+    };
+
+    let result = gen.union(&scene.nodes, "p");
+
+    let mut src = String::new();
+    //One sampler per mesh and heightmap node, numbered like Scene::mesh_volumes and Scene::height_fields
+    for i in 0..gen.meshes {
+        writeln!(src, "uniform sampler3D {}{};", MESH_UNIFORM, i).unwrap();
+    }
+    for i in 0..gen.heightmaps {
+        writeln!(src, "uniform sampler2D {}{};", HEIGHTMAP_UNIFORM, i).unwrap();
+    }
+    if gen.meshes > 0 || gen.heightmaps > 0 {
+        writeln!(src).unwrap();
+    }
+    writeln!(src, "vec2 map(vec3 p) {{").unwrap();
+    src.push_str(&gen.code);
+    writeln!(src, "    return {};", result).unwrap();
+    writeln!(src, "}}").unwrap();
+    src
+}
+
+//Every work group bakes the brick at brick_offset + its id from the list, which holds
+//the brick coordinates in xyz and the atlas slot in w. Slots are numbered x first, like in BrickMap.
+//Distances get stored in multiples of VOXEL_SIZE, which keeps them small enough for half floats.
+const MAIN: &str = "uniform int brick_offset;
+
+void main() {
+    ivec4 brick = bricks[brick_offset + int(gl_WorkGroupID.x)];
+    ivec3 local_coords = ivec3(gl_LocalInvocationID.xyz);
+    vec3 world_pos = VOLUME_ORIGIN + vec3(brick.xyz * BRICK_SIZE + local_coords) * VOXEL_SPACING;
+
+    ivec3 slots = imageSize(img_output) / BRICK_SAMPLES;
+    ivec3 slot = ivec3(brick.w % slots.x, (brick.w / slots.x) % slots.y, brick.w / (slots.x * slots.y));
+    ivec3 pixel_coords = slot * BRICK_SAMPLES + local_coords;
+
+    vec2 pixel_data = map(world_pos);
+    pixel_data.x /= VOXEL_SIZE;
+    vec4 pixel = vec4(pixel_data, 0.0, 1.0);
+
+    imageStore(img_output, pixel_coords, pixel);
+}
+";
+
+pub const MESH_UNIFORM: &str = "mesh_volume";
+pub const HEIGHTMAP_UNIFORM: &str = "heightmap";
+
+struct Generator {
+    code: String,
+    next_id: u32,
+    meshes: u32,
+    heightmaps: u32,
+}
+
+impl Generator {
+    fn var(&mut self, prefix: &str) -> String {
+        let name = format!("{}{}", prefix, self.next_id);
+        self.next_id += 1;
+        name
+    }
+
+    fn line(&mut self, line: String) {
+        self.code.push_str("    ");
+        self.code.push_str(&line);
+        self.code.push('\n');
+    }
+
+    fn union(&mut self, nodes: &[Node], p: &str) -> String {
+        self.fold(Operation::Union, Blend::Hard, nodes, p)
+    }
+
+    fn fold(&mut self, operation: Operation, blend: Blend, nodes: &[Node], p: &str) -> String {
+        if nodes.is_empty() {
+            let d = self.var("d");
+            self.line(format!("vec2 {} = vec2({}, 0.0);", d, float(EMPTY_DISTANCE)));
+            return d;
+        }
+
+        let d = self.node(&nodes[0], p);
+        for node in &nodes[1..] {
+            let other = self.node(node, p);
+            self.line(format!("{} = {};", d, operation_call(operation, blend, &d, &other)));
+        }
+        d
+    }
+
+    fn node(&mut self, node: &Node, p: &str) -> String {
+        match node {
+            Node::Primitive { primitive, material } => {
+                let d = self.var("d");
+                self.line(format!("vec2 {} = vec2({}, {});", d, primitive_call(primitive, p), float(*material as f32)));
+                d
+            },
+            Node::Mesh { mesh, material } => {
+                let d = self.var("d");
+                let dist = match &mesh.volume {
+                    Some(volume) => {
+                        let size = Vector3::new(volume.size[0] as f32, volume.size[1] as f32, volume.size[2] as f32);
+                        format!("sdVolume({}{}, {}, {}, {}, {})", MESH_UNIFORM, self.meshes, p, vec3(volume.origin), vec3(volume.spacing()), vec3(size))
+                    },
+                    None => float(EMPTY_DISTANCE),
+                };
+                self.meshes += 1;
+                self.line(format!("vec2 {} = vec2({}, {});", d, dist, float(*material as f32)));
+                d
+            },
+            Node::Heightmap { heightmap, material } => {
+                let d = self.var("d");
+                let dist = match &heightmap.field {
+                    Some(field) => format!("sdHeightmap({}{}, {}, vec2({}, {}), {}, {})", HEIGHTMAP_UNIFORM, self.heightmaps, p,
+                        float(heightmap.size.x), float(heightmap.size.y), float(heightmap.height), float(heightmap.lipschitz(field))),
+                    None => float(EMPTY_DISTANCE),
+                };
+                self.heightmaps += 1;
+                self.line(format!("vec2 {} = vec2({}, {});", d, dist, float(*material as f32)));
+                d
+            },
+            Node::Transform { transform, child } => {
+                let local = self.var("p");
+                self.line(format!("vec3 {} = {};", local, transform_point(transform, p)));
+                let d = self.node(child, &local);
+                if transform.scale != 1.0 {
+                    self.line(format!("{}.x *= {};", d, float(transform.scale)));
+                }
+                d
+            },
+            Node::Modifier { modifier, child } => {
+                let local = self.var("p");
+                self.line(format!("vec3 {} = {};", local, modifier_warp(modifier, p)));
+                let d = self.node(child, &local);
+                if let Some(correction) = modifier_correct(modifier, &format!("{}.x", d), p) {
+                    self.line(format!("{}.x = {};", d, correction));
+                }
+                d
+            },
+            Node::Operation { operation, blend, children } => self.fold(*operation, *blend, children, p),
+        }
+    }
+}
+
+fn transform_point(transform: &Transform, p: &str) -> String {
+    let rot = Matrix3::from(transform.rotation.invert());
+    let mut expr = format!("({} - {})", p, vec3(transform.position));
+    if transform.rotation != Quaternion::one() {
+        expr = format!("{} * {}", mat3(rot), expr);
+    }
+    if transform.scale != 1.0 {
+        expr = format!("({}) / {}", expr, float(transform.scale));
+    }
+    expr
+}
+
+fn primitive_call(primitive: &Primitive, p: &str) -> String {
+    match primitive {
+        Primitive::Sphere { radius } => format!("sdSphere({}, {})", p, float(*radius)),
+        Primitive::Plane { normal, offset } => format!("sdPlane({}, {}, {})", p, vec3(*normal), float(*offset)),
+        Primitive::Box { half_extents } => format!("sdBox({}, {})", p, vec3(*half_extents)),
+        Primitive::RoundBox { half_extents, radius } => format!("sdRoundBox({}, {}, {})", p, vec3(*half_extents), float(*radius)),
+        Primitive::Torus { major_radius, minor_radius } => format!("sdTorus({}, vec2({}, {}))", p, float(*major_radius), float(*minor_radius)),
+        Primitive::Capsule { half_height, radius } => format!("sdCapsule({}, {}, {})", p, float(*half_height), float(*radius)),
+        Primitive::Cylinder { half_height, radius } => format!("sdCylinder({}, {}, {})", p, float(*half_height), float(*radius)),
+        Primitive::Cone { half_height, bottom_radius, top_radius } => format!("sdCappedCone({}, {}, {}, {})", p, float(*half_height), float(*bottom_radius), float(*top_radius)),
+        Primitive::Ellipsoid { radii } => format!("sdEllipsoid({}, {})", p, vec3(*radii)),
+        Primitive::HexPrism { radius, half_length } => format!("sdHexPrism({}, vec2({}, {}))", p, float(*radius), float(*half_length)),
+        Primitive::Link { half_length, major_radius, minor_radius } => format!("sdLink({}, {}, {}, {})", p, float(*half_length), float(*major_radius), float(*minor_radius)),
+    }
+}
+
+//Same as Modifier::warp
+fn modifier_warp(modifier: &Modifier, p: &str) -> String {
+    match *modifier {
+        Modifier::Repeat { period } => format!("opRepeat({}, {})", p, vec3(repeat_period(period))),
+        Modifier::RepeatLimited { period, count } => format!("opRepeatLimited({}, {}, {})", p, vec3(repeat_period(period)), vec3(count)),
+        Modifier::Mirror { x, y, z } => {
+            let mask = Vector3::new(x as u32 as f32, y as u32 as f32, z as u32 as f32);
+            format!("mix({}, abs({}), {})", p, p, vec3(mask))
+        },
+        Modifier::Twist { rate, .. } => format!("opTwist({}, {})", p, float(rate)),
+        Modifier::Bend { rate, .. } => format!("opBend({}, {})", p, float(rate)),
+        Modifier::Elongate { amount } => format!("{} - clamp({}, -{}, {})", p, p, vec3(amount), vec3(amount)),
+        Modifier::Round { .. } | Modifier::Onion { .. } => p.to_string(),
+    }
+}
+
+//Same as Modifier::correct, returns None if the distance stays untouched
+fn modifier_correct(modifier: &Modifier, dist: &str, p: &str) -> Option<String> {
+    match *modifier {
+        Modifier::Twist { .. } | Modifier::Bend { .. } => Some(format!("{} / {}", dist, float(modifier.lipschitz()))),
+        Modifier::Elongate { amount } => Some(format!("{} + opElongateInterior({}, {})", dist, p, vec3(amount))),
+        Modifier::Round { radius } => Some(format!("{} - {}", dist, float(radius))),
+        Modifier::Onion { thickness } => Some(format!("abs({}) - {}", dist, float(thickness))),
+        _ => None,
+    }
+}
+
+//An axis with a period of 0 isn't repeated, which a period too big to ever wrap takes care of in GLSL
+fn repeat_period(period: Vector3<f32>) -> Vector3<f32> {
+    let fix = |x: f32| if x == 0.0 { EMPTY_DISTANCE } else { x };
+    Vector3::new(fix(period.x), fix(period.y), fix(period.z))
+}
+
+fn operation_call(operation: Operation, blend: Blend, a: &str, b: &str) -> String {
+    let hard = match operation {
+        Operation::Union => format!("opUnion({}, {})", a, b),
+        Operation::Subtraction => format!("opSubtraction({}, {})", a, b),
+        Operation::Intersection => format!("opIntersection({}, {})", a, b),
+    };
+
+    let (function, params) = match blend {
+        Blend::Hard => return hard,
+        Blend::Smooth { radius } => ("fSmoothUnion", float(radius)),
+        Blend::Exponential { radius } => ("fExpUnion", float(radius)),
+        Blend::Chamfer { radius } => ("fChamferUnion", float(radius)),
+        Blend::Stairs { radius, steps } => ("fStairsUnion", format!("{}, {}", float(radius), float(steps as f32))),
+    };
+
+    //Same identities as Operation::apply_blended
+    let dist = match operation {
+        Operation::Union => format!("{}({}.x, {}.x, {})", function, a, b, params),
+        Operation::Subtraction => format!("-{}(-{}.x, {}.x, {})", function, a, b, params),
+        Operation::Intersection => format!("-{}(-{}.x, -{}.x, {})", function, a, b, params),
+    };
+
+    format!("vec2({}, {}.y)", dist, hard)
+}
+
+//Debug formatting always prints a decimal point or exponent, which keeps GLSL from treating it as an int
+fn float(f: f32) -> String {
+    format!("{:?}", f)
+}
+
+fn vec3(v: Vector3<f32>) -> String {
+    format!("vec3({}, {}, {})", float(v.x), float(v.y), float(v.z))
+}
+
+//GLSL matrices are column major, just like cgmath
+fn mat3(m: Matrix3<f32>) -> String {
+    format!("mat3({}, {}, {})", vec3(m.x), vec3(m.y), vec3(m.z))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nested_scene() -> Scene {
+        let mut scene = Scene::new();
+        scene.add(Node::blended(Operation::Subtraction, Blend::Smooth { radius: 2.0 }, vec![
+            Node::primitive(Primitive::Box { half_extents: Vector3::new(4.0, 4.0, 4.0) }, 1),
+            Node::modifier(Modifier::Round { radius: 0.5 }, Node::primitive(Primitive::Sphere { radius: 5.0 }, 2)).translated(Vector3::new(2.0, 0.0, 0.0)),
+        ]));
+        scene
+    }
+
+    #[test]
+    fn default_scene_map() {
+        let expected = "\
+vec2 map(vec3 p) {
+    vec3 p0 = (p - vec3(128.0, 32.0, 128.0));
+    vec2 d1 = vec2(sdSphere(p0, 16.0), 0.0);
+    vec3 p2 = (p - vec3(128.0, 32.0, 32.0));
+    vec2 d3 = vec2(sdSphere(p2, 16.0), 0.0);
+    d1 = opUnion(d1, d3);
+    vec2 d4 = vec2(sdPlane(p, vec3(0.0, 1.0, 0.0), -1.0), 0.0);
+    d1 = opUnion(d1, d4);
+    return d1;
+}
+";
+        assert_eq!(generate_map(&Scene::default()), expected);
+    }
+
+    #[test]
+    fn nested_scene_map() {
+        let expected = "\
+vec2 map(vec3 p) {
+    vec2 d0 = vec2(sdBox(p, vec3(4.0, 4.0, 4.0)), 1.0);
+    vec3 p1 = (p - vec3(2.0, 0.0, 0.0));
+    vec3 p2 = p1;
+    vec2 d3 = vec2(sdSphere(p2, 5.0), 2.0);
+    d3.x = d3.x - 0.5;
+    d0 = vec2(-fSmoothUnion(-d0.x, d3.x, 2.0), opSubtraction(d0, d3).y);
+    return d0;
+}
+";
+        assert_eq!(generate_map(&nested_scene()), expected);
+    }
+
+    #[test]
+    fn default_scene_compute_shader() {
+        let expected = String::from("\
+#version 450
+
+layout(local_size_x = 9, local_size_y = 9, local_size_z = 9) in;
+layout(rg32f, binding = 0) uniform image3D img_output;
+layout(std430, binding = 1) readonly buffer Bricks { ivec4 bricks[]; };
+
+#define VOLUME_ORIGIN vec3(0.0, 0.0, 0.0)
+#define VOLUME_EXTENT vec3(512.0, 512.0, 512.0)
+#define VOXEL_SPACING vec3(1.0, 1.0, 1.0)
+#define VOXEL_SIZE 1.0
+#define BRICK_SIZE 8
+#define BRICK_SAMPLES 9
+
+//library
+
+") + &generate_map(&Scene::default()) + "\n" + MAIN;
+        assert_eq!(generate_compute_shader(&Scene::default(), "//library", &ShaderOptions::default()), expected);
+    }
+
+    #[test]
+    fn same_scene_same_source() {
+        let library = include_str!("../compute.glsl");
+        let options = ShaderOptions::default();
+        for scene in &[Scene::default(), nested_scene()] {
+            let first = generate_compute_shader(scene, library, &options);
+            assert_eq!(first, generate_compute_shader(&scene.clone(), library, &options));
+        }
+    }
+}
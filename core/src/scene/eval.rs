@@ -0,0 +1,219 @@
+use cgmath::*;
+
+use super::{Scene, Node, Operation, Blend};
+
+//Distance returned when there is nothing in the scene, same as the generated shader
+pub const EMPTY_DISTANCE: f32 = 1e10;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sample {
+    pub dist: f32,
+    pub material: u32,
+}
+
+impl Sample {
+    pub fn new(dist: f32, material: u32) -> Sample {
+        Sample {
+            dist: dist,
+            material: material,
+        }
+    }
+
+    pub fn empty() -> Sample {
+        Sample::new(EMPTY_DISTANCE, 0)
+    }
+}
+
+impl Node {
+    pub fn sample(&self, p: Vector3<f32>) -> Sample {
+        match self {
+            Node::Primitive { primitive, material } => Sample::new(primitive.distance(p), *material),
+            Node::Mesh { mesh, material } => Sample::new(mesh.distance(p), *material),
+            Node::Heightmap { heightmap, material } => Sample::new(heightmap.distance(p), *material),
+            Node::Transform { transform, child } => {
+                let mut sample = child.sample(transform.to_local(p));
+                sample.dist *= transform.scale;
+                sample
+            },
+            Node::Modifier { modifier, child } => {
+                let mut sample = child.sample(modifier.warp(p));
+                sample.dist = modifier.correct(sample.dist, p);
+                sample
+            },
+            Node::Operation { operation, blend, children } => fold(*operation, *blend, children, p),
+        }
+    }
+}
+
+impl Node {
+    //Upper bound on the Lipschitz constant of the subtree before any correction.
+    //Anything above 1 means the raw field would overstep when sphere tracing,
+    //which is why the modifiers that cause it divide their distance by it.
+    pub fn lipschitz(&self) -> f32 {
+        match self {
+            //Heightmaps already divide by their slope
+            Node::Primitive { .. } | Node::Mesh { .. } | Node::Heightmap { .. } => 1.0,
+            Node::Transform { child, .. } => child.lipschitz(),
+            Node::Modifier { modifier, child } => modifier.lipschitz() * child.lipschitz(),
+            Node::Operation { children, .. } => children.iter().map(|c| c.lipschitz()).fold(1.0, f32::max),
+        }
+    }
+}
+
+fn fold(operation: Operation, blend: Blend, nodes: &[Node], p: Vector3<f32>) -> Sample {
+    let mut iter = nodes.iter();
+    let first = match iter.next() {
+        Some(node) => node.sample(p),
+        None => return Sample::empty(),
+    };
+    iter.fold(first, |acc, node| operation.apply_blended(blend, acc, node.sample(p)))
+}
+
+//Pure CPU evaluation of the scene, with the same semantics as the generated map() in the bake shader.
+//Positions are in world space, which is the same as texel space of the volume.
+impl Scene {
+    pub fn sample(&self, p: Vector3<f32>) -> Sample {
+        fold(Operation::Union, Blend::Hard, &self.nodes, p)
+    }
+
+    pub fn distance(&self, p: Vector3<f32>) -> f32 {
+        self.sample(p).dist
+    }
+
+    pub fn lipschitz(&self) -> f32 {
+        self.nodes.iter().map(|n| n.lipschitz()).fold(1.0, f32::max)
+    }
+
+    //Tetrahedron technique, same as calcNormal in fragment.glsl
+    pub fn normal(&self, p: Vector3<f32>) -> Vector3<f32> {
+        let e = 0.1;
+        let xyy = Vector3::new(e, -e, -e);
+        let yyx = Vector3::new(-e, -e, e);
+        let yxy = Vector3::new(-e, e, -e);
+        let xxx = Vector3::new(e, e, e);
+
+        let n = xyy * self.distance(p + xyy)
+              + yyx * self.distance(p + yyx)
+              + yxy * self.distance(p + yxy)
+              + xxx * self.distance(p + xxx);
+
+        if n.magnitude2() > 0.0 {
+            n.normalize()
+        } else {
+            Vector3::unit_y()
+        }
+    }
+
+    //Sphere traces the scene, same as castRay in fragment.glsl.
+    //Returns the distance along the ray to the hit, useful for picking.
+    pub fn raycast(&self, origin: Vector3<f32>, direction: Vector3<f32>, tmax: f32) -> Option<f32> {
+        let direction = direction.normalize();
+        let mut t = 0.02;
+
+        for _ in 0..1024 {
+            if t >= tmax {
+                return None;
+            }
+            let dist = self.distance(origin + direction * t);
+            if dist.abs() < 0.001 * t {
+                return Some(t);
+            }
+            t += dist;
+        }
+
+        None
+    }
+
+    //Collision query for a sphere against the scene surface
+    pub fn overlaps_sphere(&self, center: Vector3<f32>, radius: f32) -> bool {
+        self.distance(center) < radius
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::{Primitive, Modifier, Transform};
+
+    //Deterministic points spread over a cube of the given half size
+    fn points(half_size: f32) -> Vec<Vector3<f32>> {
+        let mut state = 12345u32;
+        let mut next = || {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 8) as f32 / (1 << 24) as f32 * 2.0 - 1.0
+        };
+        (0..500).map(|_| Vector3::new(next(), next(), next()) * half_size).collect()
+    }
+
+    fn scene(node: Node) -> Scene {
+        let mut scene = Scene::new();
+        scene.add(node);
+        scene
+    }
+
+    #[test]
+    fn sphere_distance() {
+        let scene = scene(Node::primitive(Primitive::Sphere { radius: 5.0 }, 0));
+        for p in points(20.0) {
+            assert!((scene.distance(p) - (p.magnitude() - 5.0)).abs() < 1e-4, "{:?}", p);
+        }
+    }
+
+    #[test]
+    fn box_distance() {
+        let scene = scene(Node::primitive(Primitive::Box { half_extents: Vector3::new(4.0, 4.0, 4.0) }, 0));
+        let cases = [
+            (Vector3::new(10.0, 0.0, 0.0), 6.0),
+            (Vector3::new(8.0, 7.0, 0.0), 5.0),
+            (Vector3::new(7.0, 8.0, 9.0), 50.0f32.sqrt()),
+            (Vector3::new(1.0, 0.0, 0.0), -3.0),
+            (Vector3::new(0.0, 0.0, 0.0), -4.0),
+        ];
+        for (p, expected) in cases.iter() {
+            assert!((scene.distance(*p) - expected).abs() < 1e-4, "{:?}", p);
+        }
+    }
+
+    #[test]
+    fn transformed_sphere_distance() {
+        let centre = Vector3::new(10.0, -3.0, 6.0);
+        let transform = Transform::new(centre, Quaternion::from_angle_y(Deg(30.0)), 2.0);
+        let scene = scene(Node::transform(transform, Node::primitive(Primitive::Sphere { radius: 5.0 }, 0)));
+        for p in points(30.0) {
+            assert!((scene.distance(p) - ((p - centre).magnitude() - 10.0)).abs() < 1e-3, "{:?}", p);
+        }
+    }
+
+    //The field under a warp, before the modifier divides by its Lipschitz bound
+    fn raw_distance(modifier: &Modifier, child: &Node, p: Vector3<f32>) -> f32 {
+        child.sample(modifier.warp(p)).dist
+    }
+
+    fn assert_lipschitz_bounded(modifier: Modifier, child: Node, points: &[Vector3<f32>]) {
+        let bound = scene(Node::modifier(modifier, child.clone())).lipschitz();
+        assert!(bound > 1.0);
+        let step = 0.01;
+        for p in points {
+            for axis in &[Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()] {
+                let change = (raw_distance(&modifier, &child, p + axis * step) - raw_distance(&modifier, &child, *p)).abs() / step;
+                assert!(change <= bound * 1.01, "{:?} changes by {} with a bound of {}", p, change, bound);
+            }
+        }
+    }
+
+    #[test]
+    fn twist_lipschitz() {
+        //Within `extent` of the y axis
+        let points: Vec<_> = points(8.0).into_iter().filter(|p| Vector2::new(p.x, p.z).magnitude() < 7.9).collect();
+        let child = Node::primitive(Primitive::Box { half_extents: Vector3::new(4.0, 8.0, 2.0) }, 0);
+        assert_lipschitz_bounded(Modifier::Twist { rate: 0.2, extent: 8.0 }, child, &points);
+    }
+
+    #[test]
+    fn bend_lipschitz() {
+        //Within `extent` of the origin
+        let points: Vec<_> = points(16.0).into_iter().filter(|p| p.magnitude() < 15.9).collect();
+        let child = Node::primitive(Primitive::Box { half_extents: Vector3::new(12.0, 2.0, 2.0) }, 0);
+        assert_lipschitz_bounded(Modifier::Bend { rate: 0.05, extent: 16.0 }, child, &points);
+    }
+}
@@ -0,0 +1,194 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{Serialize, Deserialize};
+
+use super::{Scene, SceneCamera, Material, Light, Node};
+
+//Bump this whenever the format changes in a way older versions can't read,
+//and add a migration step to `upgrade` so older files keep loading.
+//
+//Version history:
+//1 - initial format
+pub const CURRENT_VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum SceneFileError {
+    Io(io::Error),
+    Parse(String),
+    Serialize(String),
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for SceneFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            SceneFileError::Io(err) => write!(f, "Failed to access scene file: {}", err),
+            SceneFileError::Parse(err) => write!(f, "Failed to parse scene file: {}", err),
+            SceneFileError::Serialize(err) => write!(f, "Failed to write scene: {}", err),
+            SceneFileError::UnsupportedVersion(version) => write!(f, "Scene file version {} is newer than the supported version {}", version, CURRENT_VERSION),
+        }
+    }
+}
+
+impl From<io::Error> for SceneFileError {
+    fn from(err: io::Error) -> SceneFileError {
+        SceneFileError::Io(err)
+    }
+}
+
+//What actually ends up on disk. Everything that got added after version 1
+//has to be #[serde(default)] so older files still parse.
+#[derive(Serialize, Deserialize)]
+struct SceneFile {
+    version: u32,
+    #[serde(default = "SceneCamera::default")]
+    camera: SceneCamera,
+    #[serde(default = "default_materials")]
+    materials: Vec<Material>,
+    #[serde(default = "default_lights")]
+    lights: Vec<Light>,
+    nodes: Vec<Node>,
+}
+
+fn default_materials() -> Vec<Material> {
+    vec![Material::default()]
+}
+
+fn default_lights() -> Vec<Light> {
+    vec![Light::default()]
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Scene, SceneFileError> {
+    let src = fs::read_to_string(path)?;
+    from_str(&src)
+}
+
+pub fn save<P: AsRef<Path>>(scene: &Scene, path: P) -> Result<(), SceneFileError> {
+    let src = to_string(scene)?;
+    fs::write(path, src)?;
+    Ok(())
+}
+
+pub fn from_str(src: &str) -> Result<Scene, SceneFileError> {
+    let file: SceneFile = ron::de::from_str(src).map_err(|e| SceneFileError::Parse(e.to_string()))?;
+    if file.version > CURRENT_VERSION {
+        return Err(SceneFileError::UnsupportedVersion(file.version));
+    }
+    let file = upgrade(file);
+
+    Ok(Scene {
+        camera: file.camera,
+        materials: file.materials,
+        lights: file.lights,
+        nodes: file.nodes,
+    })
+}
+
+//The output is pretty printed with a fixed layout, so saving the same scene
+//twice gives identical files and small edits give small diffs.
+pub fn to_string(scene: &Scene) -> Result<String, SceneFileError> {
+    let file = SceneFile {
+        version: CURRENT_VERSION,
+        camera: scene.camera,
+        materials: scene.materials.clone(),
+        lights: scene.lights.clone(),
+        nodes: scene.nodes.clone(),
+    };
+
+    let config = ron::ser::PrettyConfig::new()
+        .with_indentor("    ".to_string())
+        .with_new_line("\n".to_string())
+        .with_separate_tuple_members(true)
+        .with_enumerate_arrays(false);
+
+    let mut src = ron::ser::to_string_pretty(&file, config).map_err(|e| SceneFileError::Serialize(e.to_string()))?;
+    src.push('\n');
+    Ok(src)
+}
+
+//Brings an older file up to the current version, one version at a time.
+//Nothing to migrate yet, future steps go here as `if file.version == 1 { ...; file.version = 2; }`
+fn upgrade(mut file: SceneFile) -> SceneFile {
+    file.version = CURRENT_VERSION;
+    file
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::*;
+
+    use super::*;
+    use crate::scene::{Primitive, Modifier, Operation, Blend, Transform, MeshVolume, Heightmap};
+
+    //Every kind of node, primitive, modifier, operation and blend at least once
+    fn every_variant() -> Scene {
+        let mut scene = Scene::new();
+        for (i, primitive) in Primitive::all().into_iter().enumerate() {
+            scene.add(Node::primitive(primitive, i as u32).translated(Vector3::new(i as f32 * 8.0, 0.0, 0.0)));
+        }
+        scene.add(Node::mesh(MeshVolume::new("meshes/bunny.obj"), 1));
+        scene.add(Node::heightmap(Heightmap::new("terrain/hills.png"), 2));
+        for modifier in Modifier::all() {
+            scene.add(Node::modifier(modifier, Node::primitive(Primitive::Sphere { radius: 3.5 }, 0)));
+        }
+        for operation in Operation::all().iter() {
+            for blend in Blend::all().iter() {
+                scene.add(Node::blended(*operation, *blend, vec![
+                    Node::primitive(Primitive::Box { half_extents: Vector3::new(1.0, 2.0, 3.0) }, 0),
+                    Node::primitive(Primitive::Sphere { radius: 2.25 }, 1),
+                ]));
+            }
+        }
+        let transform = Transform::new(Vector3::new(1.0, -2.0, 3.5), Quaternion::from_angle_y(Deg(45.0)), 0.5);
+        scene.add(Node::transform(transform, Node::primitive(Primitive::Sphere { radius: 1.0 }, 0)));
+        scene.camera.position = Vector3::new(-4.0, 12.0, 7.5);
+        scene.camera.yaw = 33.0;
+        scene
+    }
+
+    fn assert_round_trip(scene: &Scene) {
+        let saved = to_string(scene).unwrap();
+        let loaded = from_str(&saved).unwrap();
+        assert_eq!(&loaded, scene);
+        assert_eq!(to_string(&loaded).unwrap(), saved);
+    }
+
+    #[test]
+    fn default_scene_round_trip() {
+        assert_round_trip(&Scene::default());
+    }
+
+    #[test]
+    fn every_variant_round_trip() {
+        assert_round_trip(&every_variant());
+    }
+
+    #[test]
+    fn current_version_loads_unchanged() {
+        let src = to_string(&Scene::default()).unwrap();
+        assert!(src.contains(&format!("version: {}", CURRENT_VERSION)));
+        let file: SceneFile = ron::de::from_str(&src).unwrap();
+        let nodes = file.nodes.clone();
+        let upgraded = upgrade(file);
+        assert_eq!(upgraded.version, CURRENT_VERSION);
+        assert_eq!(upgraded.nodes, nodes);
+    }
+
+    #[test]
+    fn missing_fields_get_defaults() {
+        let scene = from_str("(version: 1, nodes: [])").unwrap();
+        assert_eq!(scene, Scene::new());
+    }
+
+    #[test]
+    fn newer_version_is_rejected() {
+        let src = format!("(version: {}, nodes: [])", CURRENT_VERSION + 1);
+        match from_str(&src) {
+            Err(SceneFileError::UnsupportedVersion(version)) => assert_eq!(version, CURRENT_VERSION + 1),
+            other => panic!("Expected an unsupported version, got {:?}", other.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use cgmath::*;
+use serde::{Serialize, Deserialize};
+
+use super::eval::EMPTY_DISTANCE;
+
+//Heights from a grayscale image, as they are in the file. PNGs end up between 0 and 1,
+//EXRs can have anything. Texel (x, z) is values[x + z * width], the top row of the image is -Z.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeightField {
+    pub width: usize,
+    pub depth: usize,
+    pub values: Vec<f32>,
+    //Biggest height difference between neighbouring texels along X and Z.
+    //Bilinear filtering never gets steeper than that, which bounds the slope of the terrain.
+    pub max_step: [f32; 2],
+}
+
+impl HeightField {
+    pub fn new(width: usize, depth: usize, values: Vec<f32>) -> HeightField {
+        let mut max_step = [0.0f32; 2];
+        for z in 0..depth {
+            for x in 0..width {
+                let h = values[x + z * width];
+                if x + 1 < width {
+                    max_step[0] = max_step[0].max((values[x + 1 + z * width] - h).abs());
+                }
+                if z + 1 < depth {
+                    max_step[1] = max_step[1].max((values[x + (z + 1) * width] - h).abs());
+                }
+            }
+        }
+
+        HeightField {
+            width: width,
+            depth: depth,
+            values: values,
+            max_step: max_step,
+        }
+    }
+
+    //Colour images are turned into luminance first
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<HeightField, String> {
+        let image = image::open(path.as_ref()).map_err(|err| err.to_string())?.to_luma32f();
+        let (width, depth) = image.dimensions();
+        Ok(HeightField::new(width as usize, depth as usize, image.into_raw()))
+    }
+
+    //Bilinear between texel centres at (u, v) in 0..1, clamped at the edges like the texture on the GPU
+    pub fn sample(&self, u: f32, v: f32) -> f32 {
+        let x = (u * self.width as f32 - 0.5).max(0.0).min((self.width - 1) as f32);
+        let z = (v * self.depth as f32 - 0.5).max(0.0).min((self.depth - 1) as f32);
+        let (x0, z0) = (x.floor() as usize, z.floor() as usize);
+        let (x1, z1) = ((x0 + 1).min(self.width - 1), (z0 + 1).min(self.depth - 1));
+        let (fx, fz) = (x - x0 as f32, z - z0 as f32);
+
+        let h = |x: usize, z: usize| self.values[x + z * self.width];
+        let near = h(x0, z0) + (h(x1, z0) - h(x0, z0)) * fx;
+        let far = h(x0, z1) + (h(x1, z1) - h(x0, z1)) * fx;
+        near + (far - near) * fz
+    }
+}
+
+//Terrain from a heightmap file. It covers `size` on X and Z centred on the origin,
+//and goes from 0 up to `height` where the image is white.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Heightmap {
+    //Relative to the directory of the scene file
+    pub path: String,
+    pub size: Vector2<f32>,
+    pub height: f32,
+    //Filled in by AssetCache::resolve
+    #[serde(skip)]
+    pub field: Option<Arc<HeightField>>,
+}
+
+impl Heightmap {
+    pub fn new(path: &str) -> Heightmap {
+        Heightmap {
+            path: path.to_string(),
+            size: Vector2::new(256.0, 256.0),
+            height: 32.0,
+            field: None,
+        }
+    }
+
+    //How much faster than a distance `p.y - height` can change, for a field with the given steps per texel.
+    //Dividing by it keeps the estimate conservative on steep terrain.
+    pub fn lipschitz(&self, field: &HeightField) -> f32 {
+        let slope_x = field.max_step[0] * self.height * field.width as f32 / self.size.x;
+        let slope_z = field.max_step[1] * self.height * field.depth as f32 / self.size.y;
+        (1.0 + slope_x * slope_x + slope_z * slope_z).sqrt()
+    }
+
+    //Same as sdHeightmap in compute.glsl. The terrain is cut off at its bounding box,
+    //both estimates are lower bounds so the bigger one is too.
+    pub fn distance(&self, p: Vector3<f32>) -> f32 {
+        let field = match &self.field {
+            Some(field) => field,
+            None => return EMPTY_DISTANCE,
+        };
+
+        let u = (p.x / self.size.x + 0.5).clamp(0.0, 1.0);
+        let v = (p.z / self.size.y + 0.5).clamp(0.0, 1.0);
+        let terrain = (p.y - field.sample(u, v) * self.height) / self.lipschitz(field);
+
+        let half = Vector3::new(self.size.x * 0.5, self.height * 0.5, self.size.y * 0.5);
+        let q = Vector3::new(p.x.abs(), (p.y - half.y).abs(), p.z.abs()) - half;
+        let outside = Vector3::new(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0)).magnitude();
+        let bounds = outside + q.x.max(q.y).max(q.z).min(0.0);
+
+        terrain.max(bounds)
+    }
+}
+
+//Whether the image is loaded yet doesn't make it a different node
+impl PartialEq for Heightmap {
+    fn eq(&self, other: &Heightmap) -> bool {
+        self.path == other.path && self.size == other.size && self.height == other.height
+    }
+}
+
+impl fmt::Debug for Heightmap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("Heightmap")
+            .field("path", &self.path)
+            .field("size", &self.size)
+            .field("height", &self.height)
+            .field("field", &self.field.as_ref().map(|h| (h.width, h.depth)))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Steep and bumpy, so the Lipschitz scaling has to do some work
+    fn terrain() -> Heightmap {
+        let values = vec![
+            0.0, 1.0, 0.2, 0.8,
+            0.9, 0.1, 0.7, 0.3,
+            0.2, 0.6, 0.0, 1.0,
+            0.5, 0.4, 0.9, 0.1,
+        ];
+        let mut heightmap = Heightmap::new("terrain.png");
+        heightmap.size = Vector2::new(8.0, 8.0);
+        heightmap.height = 4.0;
+        heightmap.field = Some(Arc::new(HeightField::new(4, 4, values)));
+        heightmap
+    }
+
+    //Points on the surface of the terrain cut off at its box, top, sides and bottom
+    fn surface(heightmap: &Heightmap, step: f32) -> Vec<Vector3<f32>> {
+        let field = heightmap.field.as_ref().unwrap();
+        let half = heightmap.size * 0.5;
+        let height = |x: f32, z: f32| field.sample(x / heightmap.size.x + 0.5, z / heightmap.size.y + 0.5) * heightmap.height;
+        let steps = |from: f32, to: f32| {
+            let n = ((to - from) / step).ceil() as usize;
+            (0..=n).map(move |i| from + (to - from) * i as f32 / n as f32)
+        };
+
+        let mut points = Vec::new();
+        for x in steps(-half.x, half.x) {
+            for z in steps(-half.y, half.y) {
+                points.push(Vector3::new(x, height(x, z), z));
+                points.push(Vector3::new(x, 0.0, z));
+                let edge = x.abs() == half.x || z.abs() == half.y;
+                if edge {
+                    points.extend(steps(0.0, height(x, z)).map(|y| Vector3::new(x, y, z)));
+                }
+            }
+        }
+        points
+    }
+
+    #[test]
+    fn distance_is_a_lower_bound() {
+        let heightmap = terrain();
+        let field = heightmap.field.as_ref().unwrap();
+        assert!(heightmap.lipschitz(field) > 1.0);
+        let surface = surface(&heightmap, 0.05);
+
+        let mut state = 4321u32;
+        let mut next = || {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 8) as f32 / (1 << 24) as f32 * 2.0 - 1.0
+        };
+        for _ in 0..300 {
+            let p = Vector3::new(next() * 7.0, next() * 4.0 + 2.0, next() * 7.0);
+            //The closest surface sample is at least as far as the surface itself
+            let brute_force = surface.iter().map(|s| (s - p).magnitude()).fold(f32::INFINITY, f32::min);
+            let estimate = heightmap.distance(p);
+            assert!(estimate.abs() <= brute_force, "estimate {} is past the surface {} away at {:?}", estimate, brute_force, p);
+
+            let u = p.x / heightmap.size.x + 0.5;
+            let v = p.z / heightmap.size.y + 0.5;
+            let inside = p.x.abs() < 4.0 && p.z.abs() < 4.0 && p.y > 0.0 && p.y < field.sample(u, v) * heightmap.height;
+            if brute_force > 0.05 {
+                assert_eq!(estimate < 0.0, inside, "wrong side at {:?}", p);
+            }
+        }
+    }
+}
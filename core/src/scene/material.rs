@@ -0,0 +1,49 @@
+use cgmath::*;
+use serde::{Serialize, Deserialize};
+
+//Same layout as the Material struct in fragment.glsl
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Material {
+    pub albedo: Vector3<f32>,
+    pub roughness: f32,
+    pub metalness: f32,
+}
+
+impl Material {
+    pub fn new(albedo: Vector3<f32>, roughness: f32, metalness: f32) -> Material {
+        Material {
+            albedo: albedo,
+            roughness: roughness,
+            metalness: metalness,
+        }
+    }
+
+    pub fn default() -> Material {
+        Material::new(Vector3::new(1.0, 1.0, 1.0), 0.5, 0.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Light {
+    Directional {
+        direction: Vector3<f32>,
+        colour: Vector3<f32>,
+        intensity: f32,
+    },
+    Point {
+        position: Vector3<f32>,
+        colour: Vector3<f32>,
+        intensity: f32,
+    },
+}
+
+impl Light {
+    //Matches LIGHT_DIR and LIGHT_INTENSITY in fragment.glsl
+    pub fn default() -> Light {
+        Light::Directional {
+            direction: Vector3::new(-1.0, -1.0, -1.0).normalize(),
+            colour: Vector3::new(1.0, 1.0, 1.0),
+            intensity: 1.5,
+        }
+    }
+}
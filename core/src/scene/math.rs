@@ -0,0 +1,40 @@
+use cgmath::*;
+
+//Small helpers to mirror the GLSL builtins, so the CPU evaluator matches the shader exactly
+
+pub fn clamp(x: f32, min: f32, max: f32) -> f32 {
+    x.max(min).min(max)
+}
+
+//GLSL sign() returns 0 for 0, unlike f32::signum
+pub fn sign(x: f32) -> f32 {
+    if x > 0.0 {
+        1.0
+    } else if x < 0.0 {
+        -1.0
+    } else {
+        0.0
+    }
+}
+
+//GLSL mod() floors, unlike the % operator which truncates
+pub fn glsl_mod(x: f32, y: f32) -> f32 {
+    x - y * (x / y).floor()
+}
+
+//GLSL round() may go either way on .5, so both sides use floor(x + 0.5) instead
+pub fn round_half_up(x: f32) -> f32 {
+    (x + 0.5).floor()
+}
+
+pub fn abs3(v: Vector3<f32>) -> Vector3<f32> {
+    Vector3::new(v.x.abs(), v.y.abs(), v.z.abs())
+}
+
+pub fn max3(v: Vector3<f32>, m: f32) -> Vector3<f32> {
+    Vector3::new(v.x.max(m), v.y.max(m), v.z.max(m))
+}
+
+pub fn max2(v: Vector2<f32>, m: f32) -> Vector2<f32> {
+    Vector2::new(v.x.max(m), v.y.max(m))
+}
@@ -0,0 +1,64 @@
+use std::fmt;
+use std::sync::Arc;
+
+use cgmath::*;
+use serde::{Serialize, Deserialize};
+
+use super::eval::EMPTY_DISTANCE;
+use crate::mesh::sdf::SignMethod;
+use crate::volume::Volume;
+
+//A mesh file, baked to a distance volume in its own coordinates.
+//Only the reference ends up in the scene file, the volume gets baked again after loading.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MeshVolume {
+    //Relative to the directory of the scene file
+    pub path: String,
+    //Voxels along the longest side of the mesh
+    pub resolution: u32,
+    pub sign: SignMethod,
+    //Filled in by AssetCache::resolve
+    #[serde(skip)]
+    pub volume: Option<Arc<Volume>>,
+}
+
+impl MeshVolume {
+    pub fn new(path: &str) -> MeshVolume {
+        MeshVolume {
+            path: path.to_string(),
+            resolution: 64,
+            sign: SignMethod::WindingNumber,
+            volume: None,
+        }
+    }
+
+    //Same as sdVolume in compute.glsl. Until the mesh is baked there's nothing there.
+    pub fn distance(&self, p: Vector3<f32>) -> f32 {
+        match &self.volume {
+            Some(volume) => volume.sample_unbounded(p),
+            None => EMPTY_DISTANCE,
+        }
+    }
+
+    pub(super) fn key(&self) -> (String, u32, SignMethod) {
+        (self.path.clone(), self.resolution, self.sign)
+    }
+}
+
+//Whether the volume is baked yet doesn't make it a different node
+impl PartialEq for MeshVolume {
+    fn eq(&self, other: &MeshVolume) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl fmt::Debug for MeshVolume {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("MeshVolume")
+            .field("path", &self.path)
+            .field("resolution", &self.resolution)
+            .field("sign", &self.sign)
+            .field("volume", &self.volume.as_ref().map(|v| v.size))
+            .finish()
+    }
+}
@@ -0,0 +1,255 @@
+use std::sync::Arc;
+
+use cgmath::*;
+use serde::{Serialize, Deserialize};
+
+use crate::volume::Volume;
+
+pub mod math;
+pub mod primitive;
+pub mod operation;
+pub mod modifier;
+pub mod material;
+pub mod codegen;
+pub mod file;
+pub mod eval;
+pub mod mesh_volume;
+pub mod heightmap;
+pub mod assets;
+pub mod bounds;
+
+pub use primitive::Primitive;
+pub use operation::{Operation, Blend};
+pub use modifier::Modifier;
+pub use material::{Material, Light};
+pub use mesh_volume::MeshVolume;
+pub use heightmap::{Heightmap, HeightField};
+pub use assets::AssetCache;
+pub use bounds::{Aabb, Dirty, DirtyTracker};
+
+//The scene is the single source of truth for whatever ends up in the baked volume.
+//Top level nodes are implicitly combined with a union.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Scene {
+    pub camera: SceneCamera,
+    //Indexed by the material id of primitives
+    pub materials: Vec<Material>,
+    pub lights: Vec<Light>,
+    pub nodes: Vec<Node>,
+}
+
+//Where the viewer starts out, angles in degrees just like the camera controls in main.rs
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SceneCamera {
+    pub position: Vector3<f32>,
+    pub pitch: f32,
+    pub yaw: f32,
+    pub fovy: f32,
+}
+
+impl SceneCamera {
+    pub fn default() -> SceneCamera {
+        SceneCamera {
+            position: Vector3::new(128.0, 32.0, 80.0),
+            pitch: 0.0,
+            yaw: 0.0,
+            fovy: 60.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Node {
+    Primitive {
+        primitive: Primitive,
+        material: u32,
+    },
+    //A baked mesh, in the coordinates of the mesh file
+    Mesh {
+        mesh: MeshVolume,
+        material: u32,
+    },
+    //Terrain from an image, centred on the origin
+    Heightmap {
+        heightmap: Heightmap,
+        material: u32,
+    },
+    Transform {
+        transform: Transform,
+        child: Box<Node>,
+    },
+    Modifier {
+        modifier: Modifier,
+        child: Box<Node>,
+    },
+    //Children are folded from left to right, so for subtraction every
+    //child after the first one gets cut out of the first child.
+    Operation {
+        operation: Operation,
+        blend: Blend,
+        children: Vec<Node>,
+    },
+}
+
+impl Node {
+    pub fn primitive(primitive: Primitive, material: u32) -> Node {
+        Node::Primitive {
+            primitive: primitive,
+            material: material,
+        }
+    }
+
+    pub fn mesh(mesh: MeshVolume, material: u32) -> Node {
+        Node::Mesh {
+            mesh: mesh,
+            material: material,
+        }
+    }
+
+    pub fn heightmap(heightmap: Heightmap, material: u32) -> Node {
+        Node::Heightmap {
+            heightmap: heightmap,
+            material: material,
+        }
+    }
+
+    pub fn transform(transform: Transform, child: Node) -> Node {
+        Node::Transform {
+            transform: transform,
+            child: Box::new(child),
+        }
+    }
+
+    pub fn modifier(modifier: Modifier, child: Node) -> Node {
+        Node::Modifier {
+            modifier: modifier,
+            child: Box::new(child),
+        }
+    }
+
+    pub fn operation(operation: Operation, children: Vec<Node>) -> Node {
+        Node::blended(operation, Blend::Hard, children)
+    }
+
+    pub fn blended(operation: Operation, blend: Blend, children: Vec<Node>) -> Node {
+        Node::Operation {
+            operation: operation,
+            blend: blend,
+            children: children,
+        }
+    }
+
+    //Shorthand for wrapping a node in a transform that only moves it
+    pub fn translated(self, position: Vector3<f32>) -> Node {
+        Node::transform(Transform::from_position(position), self)
+    }
+}
+
+//Rigid transform with a uniform scale. Non-uniform scaling is left out on purpose,
+//as it would break the distance bound of whatever is underneath it.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Transform {
+    pub position: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: f32,
+}
+
+impl Transform {
+    pub fn new(position: Vector3<f32>, rotation: Quaternion<f32>, scale: f32) -> Transform {
+        Transform {
+            position: position,
+            rotation: rotation,
+            scale: scale,
+        }
+    }
+
+    pub fn identity() -> Transform {
+        Transform::new(Vector3::zero(), Quaternion::one(), 1.0)
+    }
+
+    pub fn from_position(position: Vector3<f32>) -> Transform {
+        Transform::new(position, Quaternion::one(), 1.0)
+    }
+
+    //Moves a point from the parent space into the local space of this transform
+    pub fn to_local(&self, p: Vector3<f32>) -> Vector3<f32> {
+        self.rotation.invert().rotate_vector(p - self.position) / self.scale
+    }
+}
+
+impl Scene {
+    pub fn new() -> Scene {
+        Scene {
+            camera: SceneCamera::default(),
+            materials: vec![Material::default()],
+            lights: vec![Light::default()],
+            nodes: Vec::new(),
+        }
+    }
+
+    //The scene that used to be hardcoded in compute.glsl
+    pub fn default() -> Scene {
+        Scene {
+            camera: SceneCamera::default(),
+            materials: vec![Material::default()],
+            lights: vec![Light::default()],
+            nodes: vec![
+                Node::primitive(Primitive::Sphere { radius: 16.0 }, 0).translated(Vector3::new(128.0, 32.0, 128.0)),
+                Node::primitive(Primitive::Sphere { radius: 16.0 }, 0).translated(Vector3::new(128.0, 32.0, 32.0)),
+                Node::primitive(Primitive::Plane { normal: Vector3::unit_y(), offset: -1.0 }, 0),
+            ],
+        }
+    }
+
+    pub fn add(&mut self, node: Node) {
+        self.nodes.push(node);
+    }
+
+    pub fn primitive_count(&self) -> usize {
+        fn count(node: &Node) -> usize {
+            match node {
+                Node::Primitive { .. } | Node::Mesh { .. } | Node::Heightmap { .. } => 1,
+                Node::Transform { child, .. } | Node::Modifier { child, .. } => count(child),
+                Node::Operation { children, .. } => children.iter().map(count).sum(),
+            }
+        }
+
+        self.nodes.iter().map(count).sum()
+    }
+
+    //The volumes of every mesh node, depth first in the same order the bake shader numbers them
+    pub fn mesh_volumes(&self) -> Vec<Option<Arc<Volume>>> {
+        fn collect(node: &Node, volumes: &mut Vec<Option<Arc<Volume>>>) {
+            match node {
+                Node::Mesh { mesh, .. } => volumes.push(mesh.volume.clone()),
+                Node::Transform { child, .. } | Node::Modifier { child, .. } => collect(child, volumes),
+                Node::Operation { children, .. } => children.iter().for_each(|c| collect(c, volumes)),
+                Node::Primitive { .. } | Node::Heightmap { .. } => {},
+            }
+        }
+
+        let mut volumes = Vec::new();
+        for node in &self.nodes {
+            collect(node, &mut volumes);
+        }
+        volumes
+    }
+
+    //Same for the height fields of heightmap nodes
+    pub fn height_fields(&self) -> Vec<Option<Arc<HeightField>>> {
+        fn collect(node: &Node, fields: &mut Vec<Option<Arc<HeightField>>>) {
+            match node {
+                Node::Heightmap { heightmap, .. } => fields.push(heightmap.field.clone()),
+                Node::Transform { child, .. } | Node::Modifier { child, .. } => collect(child, fields),
+                Node::Operation { children, .. } => children.iter().for_each(|c| collect(c, fields)),
+                Node::Primitive { .. } | Node::Mesh { .. } => {},
+            }
+        }
+
+        let mut fields = Vec::new();
+        for node in &self.nodes {
+            collect(node, &mut fields);
+        }
+        fields
+    }
+}
@@ -0,0 +1,145 @@
+use cgmath::*;
+use serde::{Serialize, Deserialize};
+
+use super::math::*;
+
+//Space warping operators that wrap a single child node.
+//http://iquilezles.org/www/articles/distfunctions/distfunctions.htm
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Modifier {
+    //Infinite repetition, an axis with a period of 0 is not repeated.
+    //Only stays a correct distance if the child fits inside a single cell.
+    Repeat {
+        period: Vector3<f32>,
+    },
+    //Repeats `count` times in both directions along each axis
+    RepeatLimited {
+        period: Vector3<f32>,
+        count: Vector3<f32>,
+    },
+    Mirror {
+        x: bool,
+        y: bool,
+        z: bool,
+    },
+    //Twists around the y axis, `rate` in radians per unit.
+    //`extent` is the furthest the child reaches from the axis, used to bound the distortion.
+    Twist {
+        rate: f32,
+        extent: f32,
+    },
+    //Bends the xy plane along the x axis, `rate` in radians per unit.
+    //`extent` is the furthest the child reaches from the origin, used to bound the distortion.
+    Bend {
+        rate: f32,
+        extent: f32,
+    },
+    Elongate {
+        amount: Vector3<f32>,
+    },
+    Round {
+        radius: f32,
+    },
+    //Turns the child into a shell
+    Onion {
+        thickness: f32,
+    },
+}
+
+impl Modifier {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Modifier::Repeat { .. } => "Repeat",
+            Modifier::RepeatLimited { .. } => "Repeat limited",
+            Modifier::Mirror { .. } => "Mirror",
+            Modifier::Twist { .. } => "Twist",
+            Modifier::Bend { .. } => "Bend",
+            Modifier::Elongate { .. } => "Elongate",
+            Modifier::Round { .. } => "Round",
+            Modifier::Onion { .. } => "Onion",
+        }
+    }
+
+    pub fn all() -> Vec<Modifier> {
+        vec![
+            Modifier::Repeat { period: Vector3::new(64.0, 0.0, 64.0) },
+            Modifier::RepeatLimited { period: Vector3::new(48.0, 0.0, 48.0), count: Vector3::new(2.0, 0.0, 2.0) },
+            Modifier::Mirror { x: true, y: false, z: false },
+            Modifier::Twist { rate: 0.05, extent: 16.0 },
+            Modifier::Bend { rate: 0.02, extent: 32.0 },
+            Modifier::Elongate { amount: Vector3::new(8.0, 0.0, 0.0) },
+            Modifier::Round { radius: 2.0 },
+            Modifier::Onion { thickness: 1.0 },
+        ]
+    }
+
+    //Lipschitz constant of the warp, so how much faster than 1 the warped field can change.
+    //The distance gets divided by this, which keeps the result safe to sphere trace.
+    //Twisting around y moves points at right angles to y, bending along x moves them along x as well,
+    //so there the stretch adds up instead.
+    pub fn lipschitz(&self) -> f32 {
+        match *self {
+            Modifier::Twist { rate, extent } => (1.0 + (rate * extent) * (rate * extent)).sqrt(),
+            Modifier::Bend { rate, extent } => 1.0 + (rate * extent).abs(),
+            _ => 1.0,
+        }
+    }
+}
+
+//CPU versions of the domain operators in compute.glsl
+impl Modifier {
+    //Moves a point into the space the child gets evaluated in
+    pub fn warp(&self, p: Vector3<f32>) -> Vector3<f32> {
+        match *self {
+            Modifier::Repeat { period } => {
+                Vector3::new(repeat(p.x, period.x), repeat(p.y, period.y), repeat(p.z, period.z))
+            },
+            Modifier::RepeatLimited { period, count } => {
+                Vector3::new(repeat_limited(p.x, period.x, count.x), repeat_limited(p.y, period.y, count.y), repeat_limited(p.z, period.z, count.z))
+            },
+            Modifier::Mirror { x, y, z } => {
+                Vector3::new(if x { p.x.abs() } else { p.x }, if y { p.y.abs() } else { p.y }, if z { p.z.abs() } else { p.z })
+            },
+            Modifier::Twist { rate, .. } => {
+                let (s, c) = (rate * p.y).sin_cos();
+                Vector3::new(c * p.x - s * p.z, p.y, s * p.x + c * p.z)
+            },
+            Modifier::Bend { rate, .. } => {
+                let (s, c) = (rate * p.x).sin_cos();
+                Vector3::new(c * p.x - s * p.y, s * p.x + c * p.y, p.z)
+            },
+            Modifier::Elongate { amount } => {
+                p - Vector3::new(clamp(p.x, -amount.x, amount.x), clamp(p.y, -amount.y, amount.y), clamp(p.z, -amount.z, amount.z))
+            },
+            Modifier::Round { .. } | Modifier::Onion { .. } => p,
+        }
+    }
+
+    //Fixes up the distance the child returned for the point `p` that was passed to warp
+    pub fn correct(&self, dist: f32, p: Vector3<f32>) -> f32 {
+        match *self {
+            Modifier::Twist { .. } | Modifier::Bend { .. } => dist / self.lipschitz(),
+            Modifier::Elongate { amount } => {
+                let q = abs3(p) - amount;
+                dist + q.x.max(q.y.max(q.z)).min(0.0)
+            },
+            Modifier::Round { radius } => dist - radius,
+            Modifier::Onion { thickness } => dist.abs() - thickness,
+            _ => dist,
+        }
+    }
+}
+
+fn repeat(x: f32, period: f32) -> f32 {
+    if period == 0.0 {
+        return x;
+    }
+    x - period * round_half_up(x / period)
+}
+
+fn repeat_limited(x: f32, period: f32, count: f32) -> f32 {
+    if period == 0.0 {
+        return x;
+    }
+    x - period * clamp(round_half_up(x / period), -count, count)
+}
@@ -0,0 +1,171 @@
+use serde::{Serialize, Deserialize};
+
+use super::eval::Sample;
+use super::math::glsl_mod;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Operation {
+    Union,
+    Subtraction,
+    Intersection,
+}
+
+impl Operation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Operation::Union => "Union",
+            Operation::Subtraction => "Subtraction",
+            Operation::Intersection => "Intersection",
+        }
+    }
+
+    pub fn all() -> [Operation; 3] {
+        [Operation::Union, Operation::Subtraction, Operation::Intersection]
+    }
+}
+
+//How the edge between two children of an operation is shaped.
+//The material always comes from the hard version of the operation.
+//Formulas from http://iquilezles.org/www/articles/smin/smin.htm and http://mercury.sexy/hg_sdf/
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Blend {
+    Hard,
+    //Polynomial smooth min
+    Smooth {
+        radius: f32,
+    },
+    Exponential {
+        radius: f32,
+    },
+    Chamfer {
+        radius: f32,
+    },
+    Stairs {
+        radius: f32,
+        steps: u32,
+    },
+}
+
+impl Blend {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Blend::Hard => "Hard",
+            Blend::Smooth { .. } => "Smooth",
+            Blend::Exponential { .. } => "Exponential",
+            Blend::Chamfer { .. } => "Chamfer",
+            Blend::Stairs { .. } => "Stairs",
+        }
+    }
+
+    pub fn all() -> [Blend; 5] {
+        [
+            Blend::Hard,
+            Blend::Smooth { radius: 4.0 },
+            Blend::Exponential { radius: 4.0 },
+            Blend::Chamfer { radius: 4.0 },
+            Blend::Stairs { radius: 4.0, steps: 4 },
+        ]
+    }
+}
+
+//CPU versions of the operators in compute.glsl
+impl Operation {
+    pub fn apply(&self, a: Sample, b: Sample) -> Sample {
+        match self {
+            Operation::Union => if a.dist < b.dist { a } else { b },
+            Operation::Subtraction => Sample::new(a.dist.max(-b.dist), a.material),
+            Operation::Intersection => if a.dist > b.dist { a } else { b },
+        }
+    }
+
+    pub fn apply_blended(&self, blend: Blend, a: Sample, b: Sample) -> Sample {
+        let hard = self.apply(a, b);
+        let (a, b) = (a.dist, b.dist);
+
+        let dist = match blend {
+            Blend::Hard => return hard,
+            Blend::Smooth { radius } => match self {
+                Operation::Union => smooth_union(a, b, radius),
+                Operation::Subtraction => -smooth_union(-a, b, radius),
+                Operation::Intersection => -smooth_union(-a, -b, radius),
+            },
+            Blend::Exponential { radius } => match self {
+                Operation::Union => exp_union(a, b, radius),
+                Operation::Subtraction => -exp_union(-a, b, radius),
+                Operation::Intersection => -exp_union(-a, -b, radius),
+            },
+            Blend::Chamfer { radius } => match self {
+                Operation::Union => chamfer_union(a, b, radius),
+                Operation::Subtraction => -chamfer_union(-a, b, radius),
+                Operation::Intersection => -chamfer_union(-a, -b, radius),
+            },
+            Blend::Stairs { radius, steps } => match self {
+                Operation::Union => stairs_union(a, b, radius, steps as f32),
+                Operation::Subtraction => -stairs_union(-a, b, radius, steps as f32),
+                Operation::Intersection => -stairs_union(-a, -b, radius, steps as f32),
+            },
+        };
+
+        Sample::new(dist, hard.material)
+    }
+}
+
+//Every blend is written as a union, subtraction and intersection follow from
+//max(a, b) = -min(-a, -b), exactly like in compute.glsl.
+
+//A radius of 0 or less would divide by zero, so those fall back to the hard union.
+//The exponential and stairs blends do the same.
+fn smooth_union(a: f32, b: f32, k: f32) -> f32 {
+    if k <= 0.0 {
+        return a.min(b);
+    }
+    let h = (0.5 + 0.5 * (b - a) / k).max(0.0).min(1.0);
+    b + (a - b) * h - k * h * (1.0 - h)
+}
+
+//Shifted by the minimum so exp2 can't overflow on large distances
+fn exp_union(a: f32, b: f32, k: f32) -> f32 {
+    if k <= 0.0 {
+        return a.min(b);
+    }
+    let m = a.min(b);
+    let res = (-(a - m) / k).exp2() + (-(b - m) / k).exp2();
+    m - k * res.log2()
+}
+
+//Nothing to divide by, so any radius works and there is no fallback
+fn chamfer_union(a: f32, b: f32, r: f32) -> f32 {
+    a.min(b).min((a - r + b) * 0.5f32.sqrt())
+}
+
+//At least one step, whatever the scene says. A radius of 0 would make the steps 0 wide.
+fn stairs_union(a: f32, b: f32, r: f32, n: f32) -> f32 {
+    if r <= 0.0 {
+        return a.min(b);
+    }
+    let s = r / n.max(1.0);
+    let u = b - r;
+    a.min(b).min(0.5 * (u + a + (glsl_mod(u - a + s, 2.0 * s) - s).abs()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn degenerate_blends_fall_back_to_hard() {
+        let (a, b) = (Sample::new(1.5, 1), Sample::new(-0.5, 2));
+        let blends = [
+            Blend::Smooth { radius: 0.0 },
+            Blend::Exponential { radius: -1.0 },
+            Blend::Stairs { radius: 0.0, steps: 4 },
+        ];
+        for operation in Operation::all().iter() {
+            for blend in blends.iter() {
+                assert_eq!(operation.apply_blended(*blend, a, b), operation.apply(a, b), "{:?} {:?}", operation, blend);
+            }
+            let stairs = operation.apply_blended(Blend::Stairs { radius: 2.0, steps: 0 }, a, b);
+            assert!(stairs.dist.is_finite(), "{:?}", operation);
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use cgmath::*;
+use serde::{Serialize, Deserialize};
+
+use super::math::*;
+
+//All primitives are centered around the origin of their local space.
+//Use a transform node to place them in the world.
+//Mostly taken from http://iquilezles.org/www/articles/distfunctions/distfunctions.htm
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Primitive {
+    Sphere {
+        radius: f32,
+    },
+    //Infinite plane, distance is dot(p, normal) + offset
+    Plane {
+        normal: Vector3<f32>,
+        offset: f32,
+    },
+    Box {
+        half_extents: Vector3<f32>,
+    },
+    RoundBox {
+        half_extents: Vector3<f32>,
+        radius: f32,
+    },
+    //Lies in the xz plane
+    Torus {
+        major_radius: f32,
+        minor_radius: f32,
+    },
+    //Capsule, cylinder and cone all run along the y axis
+    Capsule {
+        half_height: f32,
+        radius: f32,
+    },
+    Cylinder {
+        half_height: f32,
+        radius: f32,
+    },
+    Cone {
+        half_height: f32,
+        bottom_radius: f32,
+        top_radius: f32,
+    },
+    //Not an exact distance, but a bound
+    Ellipsoid {
+        radii: Vector3<f32>,
+    },
+    //Hexagon in the xy plane, extruded along z
+    HexPrism {
+        radius: f32,
+        half_length: f32,
+    },
+    //Chain link, stretched along y
+    Link {
+        half_length: f32,
+        major_radius: f32,
+        minor_radius: f32,
+    },
+}
+
+impl Primitive {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Primitive::Sphere { .. } => "Sphere",
+            Primitive::Plane { .. } => "Plane",
+            Primitive::Box { .. } => "Box",
+            Primitive::RoundBox { .. } => "Round box",
+            Primitive::Torus { .. } => "Torus",
+            Primitive::Capsule { .. } => "Capsule",
+            Primitive::Cylinder { .. } => "Cylinder",
+            Primitive::Cone { .. } => "Cone",
+            Primitive::Ellipsoid { .. } => "Ellipsoid",
+            Primitive::HexPrism { .. } => "Hex prism",
+            Primitive::Link { .. } => "Link",
+        }
+    }
+
+    //One of every primitive with some sensible parameters, used to populate the UI
+    pub fn all() -> Vec<Primitive> {
+        vec![
+            Primitive::Sphere { radius: 16.0 },
+            Primitive::Plane { normal: Vector3::unit_y(), offset: 0.0 },
+            Primitive::Box { half_extents: Vector3::new(16.0, 16.0, 16.0) },
+            Primitive::RoundBox { half_extents: Vector3::new(16.0, 16.0, 16.0), radius: 4.0 },
+            Primitive::Torus { major_radius: 16.0, minor_radius: 4.0 },
+            Primitive::Capsule { half_height: 16.0, radius: 8.0 },
+            Primitive::Cylinder { half_height: 16.0, radius: 8.0 },
+            Primitive::Cone { half_height: 16.0, bottom_radius: 12.0, top_radius: 0.0 },
+            Primitive::Ellipsoid { radii: Vector3::new(16.0, 8.0, 12.0) },
+            Primitive::HexPrism { radius: 12.0, half_length: 16.0 },
+            Primitive::Link { half_length: 8.0, major_radius: 8.0, minor_radius: 3.0 },
+        ]
+    }
+}
+
+//CPU versions of the distance functions in compute.glsl, these have to match exactly
+impl Primitive {
+    pub fn distance(&self, p: Vector3<f32>) -> f32 {
+        match *self {
+            Primitive::Sphere { radius } => p.magnitude() - radius,
+            Primitive::Plane { normal, offset } => p.dot(normal) + offset,
+            Primitive::Box { half_extents } => sd_box(p, half_extents),
+            Primitive::RoundBox { half_extents, radius } => {
+                sd_box(p, half_extents - Vector3::new(radius, radius, radius)) - radius
+            },
+            Primitive::Torus { major_radius, minor_radius } => {
+                let q = Vector2::new(Vector2::new(p.x, p.z).magnitude() - major_radius, p.y);
+                q.magnitude() - minor_radius
+            },
+            Primitive::Capsule { half_height, radius } => {
+                let q = Vector3::new(p.x, p.y - clamp(p.y, -half_height, half_height), p.z);
+                q.magnitude() - radius
+            },
+            Primitive::Cylinder { half_height, radius } => {
+                let d = Vector2::new(Vector2::new(p.x, p.z).magnitude() - radius, p.y.abs() - half_height);
+                d.x.max(d.y).min(0.0) + max2(d, 0.0).magnitude()
+            },
+            Primitive::Cone { half_height, bottom_radius, top_radius } => {
+                let q = Vector2::new(Vector2::new(p.x, p.z).magnitude(), p.y);
+                let k1 = Vector2::new(top_radius, half_height);
+                let k2 = Vector2::new(top_radius - bottom_radius, 2.0 * half_height);
+                let ca = Vector2::new(q.x - q.x.min(if q.y < 0.0 { bottom_radius } else { top_radius }), q.y.abs() - half_height);
+                let cb = q - k1 + k2 * clamp((k1 - q).dot(k2) / k2.magnitude2(), 0.0, 1.0);
+                let s = if cb.x < 0.0 && ca.y < 0.0 { -1.0 } else { 1.0 };
+                s * ca.magnitude2().min(cb.magnitude2()).sqrt()
+            },
+            Primitive::Ellipsoid { radii } => {
+                let k0 = p.div_element_wise(radii).magnitude();
+                let k1 = p.div_element_wise(radii.mul_element_wise(radii)).magnitude();
+                if k1 == 0.0 {
+                    return -radii.x.min(radii.y.min(radii.z));
+                }
+                k0 * (k0 - 1.0) / k1
+            },
+            Primitive::HexPrism { radius, half_length } => {
+                let k = Vector3::new(-0.8660254, 0.5, 0.57735);
+                let mut p = abs3(p);
+                let kxy = Vector2::new(k.x, k.y);
+                let pxy = Vector2::new(p.x, p.y) - kxy * (2.0 * kxy.dot(Vector2::new(p.x, p.y)).min(0.0));
+                p.x = pxy.x;
+                p.y = pxy.y;
+                let d = Vector2::new(
+                    (Vector2::new(p.x, p.y) - Vector2::new(clamp(p.x, -k.z * radius, k.z * radius), radius)).magnitude() * sign(p.y - radius),
+                    p.z - half_length
+                );
+                d.x.max(d.y).min(0.0) + max2(d, 0.0).magnitude()
+            },
+            Primitive::Link { half_length, major_radius, minor_radius } => {
+                let q = Vector3::new(p.x, (p.y.abs() - half_length).max(0.0), p.z);
+                Vector2::new(Vector2::new(q.x, q.y).magnitude() - major_radius, q.z).magnitude() - minor_radius
+            },
+        }
+    }
+}
+
+fn sd_box(p: Vector3<f32>, b: Vector3<f32>) -> f32 {
+    let q = abs3(p) - b;
+    max3(q, 0.0).magnitude() + q.x.max(q.y.max(q.z)).min(0.0)
+}
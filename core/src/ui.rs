@@ -0,0 +1,698 @@
+use cgmath::*;
+use imgui::*;
+
+use crate::scene::{self, Scene, Node, Primitive, Operation, Blend, Modifier, Transform, MeshVolume, Heightmap};
+use crate::render::ShaderError;
+use crate::render::bake::BakeJob;
+use crate::mesh::{self, Mesher};
+use crate::mesh::export::MeshFormat;
+use crate::mesh::sdf::SignMethod;
+use crate::volume::{self, Volume};
+use crate::volume::config::{VolumeConfig, TexelFormat};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EditorResponse {
+    Unchanged,
+    //Something in the scene was edited, it should be re-baked
+    Edited,
+    //A whole new scene was loaded from disk, which includes the camera
+    Loaded,
+}
+
+pub struct SceneEditor {
+    pub add_index: usize,
+    pub operation_index: usize,
+    pub modifier_index: usize,
+    pub path: ImString,
+    pub mesh_path: ImString,
+    pub heightmap_path: ImString,
+    pub status: Option<String>,
+}
+
+impl SceneEditor {
+    pub fn new(path: &str) -> SceneEditor {
+        let mut im_path = ImString::with_capacity(256);
+        im_path.push_str(path);
+
+        let mut mesh_path = ImString::with_capacity(256);
+        mesh_path.push_str("mesh.obj");
+        let mut heightmap_path = ImString::with_capacity(256);
+        heightmap_path.push_str("terrain.png");
+
+        SceneEditor {
+            add_index: 0,
+            operation_index: 0,
+            modifier_index: 0,
+            path: im_path,
+            mesh_path: mesh_path,
+            heightmap_path: heightmap_path,
+            status: None,
+        }
+    }
+
+    pub fn build(&mut self, ui: &Ui, scene: &mut Scene) -> EditorResponse {
+        let mut changed = false;
+        let mut loaded = false;
+        let add_index = &mut self.add_index;
+        let operation_index = &mut self.operation_index;
+        let modifier_index = &mut self.modifier_index;
+        let path = &mut self.path;
+        let mesh_path = &mut self.mesh_path;
+        let heightmap_path = &mut self.heightmap_path;
+        let status = &mut self.status;
+
+        Window::new(im_str!("Scene"))
+            .position([10.0, 140.0], Condition::Appearing)
+            .size([320.0, 480.0], Condition::Appearing)
+            .collapsible(true)
+            .build(ui, || {
+                ui.input_text(im_str!("File"), path).build();
+                if ui.button(im_str!("Save"), [0.0, 0.0]) {
+                    *status = match scene::file::save(scene, path.to_str()) {
+                        Ok(()) => Some(format!("Saved to {}", path.to_str())),
+                        Err(err) => Some(err.to_string()),
+                    };
+                }
+                ui.same_line(0.0);
+                if ui.button(im_str!("Load"), [0.0, 0.0]) {
+                    match scene::file::load(path.to_str()) {
+                        Ok(new_scene) => {
+                            *scene = new_scene;
+                            *status = Some(format!("Loaded {}", path.to_str()));
+                            loaded = true;
+                        },
+                        Err(err) => *status = Some(err.to_string()),
+                    }
+                }
+                if let Some(status) = status {
+                    ui.text_wrapped(&ImString::new(status.as_str()));
+                }
+                ui.separator();
+
+                let primitives = Primitive::all();
+                let names: Vec<ImString> = primitives.iter().map(|p| ImString::new(p.name())).collect();
+                let name_refs: Vec<&ImStr> = names.iter().map(|n| n.as_ref()).collect();
+
+                ComboBox::new(im_str!("##add_primitive")).build_simple_string(ui, add_index, &name_refs);
+                ui.same_line(0.0);
+                if ui.button(im_str!("Add"), [0.0, 0.0]) {
+                    let node = Node::primitive(primitives[*add_index], 0).translated(Vector3::new(128.0, 32.0, 64.0));
+                    scene.add(node);
+                    changed = true;
+                }
+
+                //The mesh gets baked once the scene is handed back, see AssetCache
+                ui.input_text(im_str!("##mesh_path"), mesh_path).build();
+                ui.same_line(0.0);
+                if ui.button(im_str!("Add mesh"), [0.0, 0.0]) {
+                    let node = Node::mesh(MeshVolume::new(mesh_path.to_str()), 0).translated(Vector3::new(128.0, 32.0, 64.0));
+                    scene.add(node);
+                    changed = true;
+                }
+                ui.input_text(im_str!("##heightmap_path"), heightmap_path).build();
+                ui.same_line(0.0);
+                if ui.button(im_str!("Add heightmap"), [0.0, 0.0]) {
+                    let node = Node::heightmap(Heightmap::new(heightmap_path.to_str()), 0).translated(Vector3::new(128.0, 0.0, 128.0));
+                    scene.add(node);
+                    changed = true;
+                }
+
+                //Combines the two most recently added nodes, so bigger shapes can be built up step by step
+                let operations = Operation::all();
+                let operation_names: Vec<ImString> = operations.iter().map(|o| ImString::new(o.name())).collect();
+                let operation_refs: Vec<&ImStr> = operation_names.iter().map(|n| n.as_ref()).collect();
+                ComboBox::new(im_str!("##combine_operation")).build_simple_string(ui, operation_index, &operation_refs);
+                ui.same_line(0.0);
+                if ui.button(im_str!("Combine last two"), [0.0, 0.0]) && scene.nodes.len() >= 2 {
+                    let b = scene.nodes.pop().unwrap();
+                    let a = scene.nodes.pop().unwrap();
+                    scene.add(Node::operation(operations[*operation_index], vec![a, b]));
+                    changed = true;
+                }
+
+                let modifiers = Modifier::all();
+                let modifier_names: Vec<ImString> = modifiers.iter().map(|m| ImString::new(m.name())).collect();
+                let modifier_refs: Vec<&ImStr> = modifier_names.iter().map(|n| n.as_ref()).collect();
+                ComboBox::new(im_str!("##wrap_modifier")).build_simple_string(ui, modifier_index, &modifier_refs);
+                ui.same_line(0.0);
+                if ui.button(im_str!("Wrap last"), [0.0, 0.0]) {
+                    if let Some(node) = scene.nodes.pop() {
+                        scene.add(Node::modifier(modifiers[*modifier_index], node));
+                        changed = true;
+                    }
+                }
+                ui.separator();
+
+                let mut remove = None;
+                for (i, node) in scene.nodes.iter_mut().enumerate() {
+                    let id = ui.push_id(i as i32);
+                    changed |= edit_node(ui, node);
+                    if ui.small_button(im_str!("Remove")) {
+                        remove = Some(i);
+                    }
+                    id.pop(ui);
+                }
+                if let Some(i) = remove {
+                    scene.nodes.remove(i);
+                    changed = true;
+                }
+            });
+
+        if loaded {
+            EditorResponse::Loaded
+        } else if changed {
+            EditorResponse::Edited
+        } else {
+            EditorResponse::Unchanged
+        }
+    }
+}
+
+pub struct ExportWindow {
+    pub path: ImString,
+    pub format_index: usize,
+    pub mesher_index: usize,
+    pub resolution: i32,
+    pub volume_path: ImString,
+    pub volume_source_index: usize,
+    pub volume_resolution: i32,
+    pub status: Option<String>,
+}
+
+const VOLUME_SOURCES: [&str; 2] = ["GPU read-back", "CPU bake"];
+
+impl ExportWindow {
+    pub fn new() -> ExportWindow {
+        let mut path = ImString::with_capacity(256);
+        path.push_str("scene.obj");
+        let mut volume_path = ImString::with_capacity(256);
+        volume_path.push_str("scene.raw");
+
+        ExportWindow {
+            path: path,
+            format_index: 0,
+            mesher_index: 0,
+            resolution: 128,
+            volume_path: volume_path,
+            volume_source_index: 0,
+            volume_resolution: 128,
+            status: None,
+        }
+    }
+
+    //Meshing happens right away on the CPU, so big resolutions will freeze the viewer for a bit.
+    //Reading the volume back needs the GL context, so that only gets requested here,
+    //returns true if the caller should read back the texture and hand it to save_volume
+    pub fn build(&mut self, ui: &Ui, scene: &Scene, config: &VolumeConfig) -> bool {
+        let path = &mut self.path;
+        let format_index = &mut self.format_index;
+        let mesher_index = &mut self.mesher_index;
+        let resolution = &mut self.resolution;
+        let volume_path = &mut self.volume_path;
+        let volume_source_index = &mut self.volume_source_index;
+        let volume_resolution = &mut self.volume_resolution;
+        let status = &mut self.status;
+        let mut read_back = false;
+
+        Window::new(im_str!("Export"))
+            .position([10.0, 630.0], Condition::Appearing)
+            .size([320.0, 260.0], Condition::Appearing)
+            .collapsible(true)
+            .build(ui, || {
+                ui.input_text(im_str!("File##export"), path).build();
+
+                let formats = MeshFormat::all();
+                let names: Vec<ImString> = formats.iter().map(|f| ImString::new(f.name())).collect();
+                let name_refs: Vec<&ImStr> = names.iter().map(|n| n.as_ref()).collect();
+                ComboBox::new(im_str!("Format")).build_simple_string(ui, format_index, &name_refs);
+
+                let meshers = Mesher::all();
+                let names: Vec<ImString> = meshers.iter().map(|m| ImString::new(m.name())).collect();
+                let name_refs: Vec<&ImStr> = names.iter().map(|n| n.as_ref()).collect();
+                ComboBox::new(im_str!("Mesher")).build_simple_string(ui, mesher_index, &name_refs);
+
+                Drag::new(im_str!("Resolution")).range(8..=512).build(ui, resolution);
+
+                if ui.button(im_str!("Export mesh"), [0.0, 0.0]) {
+                    let format = formats[*format_index];
+                    let mesh = mesh::mesh_scene(scene, *resolution as usize, config.origin, config.extent, meshers[*mesher_index]);
+                    *status = match mesh::export::save(&mesh, &scene.materials, path.to_str(), format) {
+                        Ok(()) => Some(format!("Wrote {} triangles to {}", mesh.triangle_count(), path.to_str())),
+                        Err(err) => Some(format!("Failed to export mesh: {}", err)),
+                    };
+                }
+
+                ui.separator();
+                ui.input_text(im_str!("Volume file"), volume_path).build();
+                let source_names: Vec<ImString> = VOLUME_SOURCES.iter().map(|s| ImString::new(*s)).collect();
+                let source_refs: Vec<&ImStr> = source_names.iter().map(|n| n.as_ref()).collect();
+                ComboBox::new(im_str!("Source")).build_simple_string(ui, volume_source_index, &source_refs);
+                //The read-back always copies the whole texture
+                if *volume_source_index == 1 {
+                    Drag::new(im_str!("Resolution##volume")).range(8..=512).build(ui, volume_resolution);
+                }
+
+                if ui.button(im_str!("Export volume"), [0.0, 0.0]) {
+                    if *volume_source_index == 0 {
+                        read_back = true;
+                    } else {
+                        let res = *volume_resolution as usize;
+                        let volume = Volume::from_scene(scene, [res, res, res], config.origin, config.extent);
+                        *status = Some(volume_status(&volume, volume_path.to_str()));
+                    }
+                }
+
+                if let Some(status) = status {
+                    ui.text_wrapped(&ImString::new(status.as_str()));
+                }
+            });
+
+        read_back
+    }
+
+    pub fn save_volume(&mut self, volume: &Volume) {
+        self.status = Some(volume_status(volume, self.volume_path.to_str()));
+    }
+}
+
+fn volume_status(volume: &Volume, path: &str) -> String {
+    match volume::file::save(volume, path) {
+        Ok(()) => format!("Wrote {}x{}x{} volume to {}", volume.size[0], volume.size[1], volume.size[2], path),
+        Err(err) => format!("Failed to export volume: {}", err),
+    }
+}
+
+pub struct ImportWindow {
+    pub path: ImString,
+    pub fit: bool,
+    pub status: Option<String>,
+}
+
+impl ImportWindow {
+    pub fn new() -> ImportWindow {
+        let mut path = ImString::with_capacity(256);
+        path.push_str("scene.raw");
+
+        ImportWindow {
+            path: path,
+            fit: true,
+            status: None,
+        }
+    }
+
+    //Returns the loaded volume, already resampled to the config so it can go straight into the atlas.
+    //Without fitting the volume keeps its own coordinates, which is what volumes exported from here want
+    pub fn build(&mut self, ui: &Ui, config: &VolumeConfig) -> Option<Volume> {
+        let path = &mut self.path;
+        let fit = &mut self.fit;
+        let status = &mut self.status;
+        let mut loaded = None;
+
+        Window::new(im_str!("Import"))
+            .position([340.0, 630.0], Condition::Appearing)
+            .size([320.0, 130.0], Condition::Appearing)
+            .collapsible(true)
+            .build(ui, || {
+                ui.input_text(im_str!("File##import"), path).build();
+                ui.checkbox(im_str!("Fit to scene"), fit);
+
+                if ui.button(im_str!("Load volume"), [0.0, 0.0]) {
+                    *status = match volume::file::load(path.to_str()) {
+                        Ok(volume) => {
+                            let volume = if *fit { volume.fit(config.origin, config.extent, 0.05) } else { volume };
+                            let message = format!("Loaded {}x{}x{} volume, editing the scene bakes over it", volume.size[0], volume.size[1], volume.size[2]);
+                            loaded = Some(volume.resample(config.resolution, config.origin, config.extent));
+                            Some(message)
+                        },
+                        Err(err) => Some(format!("{}", err)),
+                    };
+                }
+
+                if let Some(status) = status {
+                    ui.text_wrapped(&ImString::new(status.as_str()));
+                }
+            });
+
+        loaded
+    }
+}
+
+//Edits a copy of the volume config, nothing changes until it gets applied
+pub struct VolumeWindow {
+    pub config: VolumeConfig,
+}
+
+impl VolumeWindow {
+    pub fn new(config: &VolumeConfig) -> VolumeWindow {
+        VolumeWindow {
+            config: *config,
+        }
+    }
+
+    //Returns the new config once it's applied, the caller has to recreate the textures and shaders and re-bake
+    pub fn build(&mut self, ui: &Ui, current: &VolumeConfig) -> Option<VolumeConfig> {
+        let config = &mut self.config;
+        let mut applied = None;
+
+        Window::new(im_str!("Volume"))
+            .position([670.0, 630.0], Condition::Appearing)
+            .size([320.0, 200.0], Condition::Appearing)
+            .collapsible(true)
+            .build(ui, || {
+                let mut resolution = [config.resolution[0] as i32, config.resolution[1] as i32, config.resolution[2] as i32];
+                if Drag::new(im_str!("Resolution")).range(8..=2048).speed(8.0).build_array(ui, &mut resolution) {
+                    config.resolution = [resolution[0].max(8) as usize, resolution[1].max(8) as usize, resolution[2].max(8) as usize];
+                }
+                drag_vec3(ui, im_str!("Origin"), &mut config.origin, 1.0);
+                if drag_vec3(ui, im_str!("Extent"), &mut config.extent, 1.0) {
+                    config.extent = Vector3::new(config.extent.x.max(1.0), config.extent.y.max(1.0), config.extent.z.max(1.0));
+                }
+
+                let formats = TexelFormat::all();
+                let mut index = formats.iter().position(|f| *f == config.format).unwrap_or(0);
+                let names: Vec<ImString> = formats.iter().map(|f| ImString::new(f.name())).collect();
+                let name_refs: Vec<&ImStr> = names.iter().map(|n| n.as_ref()).collect();
+                if ComboBox::new(im_str!("Format")).build_simple_string(ui, &mut index, &name_refs) {
+                    config.format = formats[index];
+                }
+                if !config.format.has_material() {
+                    ui.text_disabled("No materials in this format");
+                }
+
+                let spacing = config.spacing();
+                ui.text(format!("Voxel size: {:.3} x {:.3} x {:.3}", spacing.x, spacing.y, spacing.z));
+                ui.text(format!("Atlas: {:.1} MB", config.atlas_bytes() as f32 / (1024.0 * 1024.0)));
+
+                if config != current {
+                    if ui.button(im_str!("Apply and re-bake"), [0.0, 0.0]) {
+                        applied = Some(*config);
+                    }
+                    ui.same_line(0.0);
+                    if ui.button(im_str!("Revert"), [0.0, 0.0]) {
+                        *config = *current;
+                    }
+                }
+            });
+
+        applied
+    }
+}
+
+//Only shows up while a bake is running, the volume fills in behind it. Sits right below the shader errors.
+pub fn bake_progress_window(ui: &Ui, job: &BakeJob) {
+    Window::new(im_str!("Baking"))
+        .position([340.0, 320.0], Condition::Appearing)
+        .size([240.0, 60.0], Condition::Appearing)
+        .collapsible(true)
+        .build(ui, || {
+            let overlay = ImString::new(format!("{} / {} bricks", job.baked(), job.len()));
+            ProgressBar::new(job.progress()).overlay_text(&overlay).build(ui);
+        });
+}
+
+//Lists every parsed error with a bit of the source around it, the offending line in red
+pub fn shader_error_window(ui: &Ui, errors: &[&ShaderError]) {
+    Window::new(im_str!("Shader errors"))
+        .position([340.0, 10.0], Condition::Appearing)
+        .size([600.0, 300.0], Condition::Appearing)
+        .collapsible(true)
+        .build(ui, || {
+            for (i, err) in errors.iter().enumerate() {
+                let id = ui.push_id(i as i32);
+                ui.text(format!("Failed {}", err.stage));
+
+                //Not every driver log could be parsed, show it as is in that case
+                if err.entries.is_empty() {
+                    ui.text_wrapped(&ImString::new(err.log.as_str()));
+                }
+
+                for entry in &err.entries {
+                    ui.separator();
+                    ui.text_wrapped(&ImString::new(format!("{}:{}: {}", entry.file, entry.line, entry.message)));
+                    for line in entry.line.saturating_sub(2)..=entry.line + 2 {
+                        if let Some(src) = err.source_line(line) {
+                            let text = format!("{:>5} | {}", line, src);
+                            if line == entry.line {
+                                ui.text_colored([1.0, 0.35, 0.35, 1.0], text);
+                            } else {
+                                ui.text_disabled(text);
+                            }
+                        }
+                    }
+                }
+
+                ui.separator();
+                id.pop(ui);
+            }
+        });
+}
+
+fn edit_node(ui: &Ui, node: &mut Node) -> bool {
+    let mut changed = false;
+
+    let label = ImString::new(node_label(node));
+    TreeNode::new(&label).default_open(true).build(ui, || {
+        match node {
+            Node::Primitive { primitive, material } => {
+                changed |= edit_primitive(ui, primitive);
+                let mut mat = *material as i32;
+                if Drag::new(im_str!("Material")).range(0..=255).build(ui, &mut mat) {
+                    *material = mat as u32;
+                    changed = true;
+                }
+            },
+            Node::Mesh { mesh, material } => {
+                changed |= edit_mesh(ui, mesh);
+                let mut mat = *material as i32;
+                if Drag::new(im_str!("Material")).range(0..=255).build(ui, &mut mat) {
+                    *material = mat as u32;
+                    changed = true;
+                }
+            },
+            Node::Heightmap { heightmap, material } => {
+                changed |= edit_heightmap(ui, heightmap);
+                let mut mat = *material as i32;
+                if Drag::new(im_str!("Material")).range(0..=255).build(ui, &mut mat) {
+                    *material = mat as u32;
+                    changed = true;
+                }
+            },
+            Node::Transform { transform, child } => {
+                changed |= edit_transform(ui, transform);
+                changed |= edit_node(ui, child);
+            },
+            Node::Modifier { modifier, child } => {
+                changed |= edit_modifier(ui, modifier);
+                changed |= edit_node(ui, child);
+            },
+            Node::Operation { operation, blend, children } => {
+                changed |= edit_operation(ui, operation, blend);
+                for (i, child) in children.iter_mut().enumerate() {
+                    let id = ui.push_id(i as i32);
+                    changed |= edit_node(ui, child);
+                    id.pop(ui);
+                }
+            },
+        }
+    });
+
+    changed
+}
+
+fn node_label(node: &Node) -> String {
+    match node {
+        Node::Primitive { primitive, .. } => primitive.name().to_string(),
+        Node::Mesh { mesh, .. } => format!("Mesh ({})", file_name(&mesh.path)),
+        Node::Heightmap { heightmap, .. } => format!("Heightmap ({})", file_name(&heightmap.path)),
+        Node::Transform { child, .. } => format!("Transform ({})", node_label(child)),
+        Node::Modifier { modifier, child } => format!("{} ({})", modifier.name(), node_label(child)),
+        Node::Operation { operation, blend: Blend::Hard, .. } => operation.name().to_string(),
+        Node::Operation { operation, blend, .. } => format!("{} ({})", operation.name(), blend.name()),
+    }
+}
+
+//Only takes a new path on enter, otherwise every keystroke would try to load a file
+fn edit_mesh(ui: &Ui, mesh: &mut MeshVolume) -> bool {
+    let mut changed = false;
+
+    let mut path = ImString::with_capacity(256);
+    path.push_str(&mesh.path);
+    if ui.input_text(im_str!("Path"), &mut path).enter_returns_true(true).build() {
+        mesh.path = path.to_str().to_string();
+        changed = true;
+    }
+
+    let mut resolution = mesh.resolution as i32;
+    if Drag::new(im_str!("Resolution")).range(16..=256).build(ui, &mut resolution) {
+        mesh.resolution = resolution as u32;
+        changed = true;
+    }
+
+    let signs = SignMethod::all();
+    let mut index = signs.iter().position(|s| *s == mesh.sign).unwrap_or(0);
+    let names: Vec<ImString> = signs.iter().map(|s| ImString::new(s.name())).collect();
+    let name_refs: Vec<&ImStr> = names.iter().map(|n| n.as_ref()).collect();
+    if ComboBox::new(im_str!("Sign")).build_simple_string(ui, &mut index, &name_refs) {
+        mesh.sign = signs[index];
+        changed = true;
+    }
+
+    changed
+}
+
+fn file_name(path: &str) -> String {
+    std::path::Path::new(path).file_name().map_or_else(|| path.to_string(), |n| n.to_string_lossy().into_owned())
+}
+
+fn edit_heightmap(ui: &Ui, heightmap: &mut Heightmap) -> bool {
+    let mut changed = false;
+
+    let mut path = ImString::with_capacity(256);
+    path.push_str(&heightmap.path);
+    if ui.input_text(im_str!("Path"), &mut path).enter_returns_true(true).build() {
+        heightmap.path = path.to_str().to_string();
+        changed = true;
+    }
+
+    let mut size: [f32; 2] = heightmap.size.into();
+    if Drag::new(im_str!("Size")).range(1.0..=1024.0).speed(0.5).build_array(ui, &mut size) {
+        heightmap.size = size.into();
+        changed = true;
+    }
+    changed |= Drag::new(im_str!("Height")).range(0.0..=512.0).speed(0.1).build(ui, &mut heightmap.height);
+
+    changed
+}
+
+fn edit_transform(ui: &Ui, transform: &mut Transform) -> bool {
+    let mut changed = drag_vec3(ui, im_str!("Position"), &mut transform.position, 0.25);
+
+    let euler = Euler::from(transform.rotation);
+    let mut angles = Vector3::new(Deg::from(euler.x).0, Deg::from(euler.y).0, Deg::from(euler.z).0);
+    if drag_vec3(ui, im_str!("Rotation"), &mut angles, 0.5) {
+        transform.rotation = Quaternion::from(Euler::new(Deg(angles.x), Deg(angles.y), Deg(angles.z)));
+        changed = true;
+    }
+
+    changed |= Drag::new(im_str!("Scale")).range(0.01..=100.0).speed(0.01).build(ui, &mut transform.scale);
+    changed
+}
+
+fn edit_operation(ui: &Ui, operation: &mut Operation, blend: &mut Blend) -> bool {
+    let mut changed = false;
+
+    let operations = Operation::all();
+    let mut index = operations.iter().position(|o| o == operation).unwrap_or(0);
+    let names: Vec<ImString> = operations.iter().map(|o| ImString::new(o.name())).collect();
+    let name_refs: Vec<&ImStr> = names.iter().map(|n| n.as_ref()).collect();
+    if ComboBox::new(im_str!("Operation")).build_simple_string(ui, &mut index, &name_refs) {
+        *operation = operations[index];
+        changed = true;
+    }
+
+    //Switching blends keeps the radius around, so it doesn't jump back to the default
+    let blends = Blend::all();
+    let mut index = blends.iter().position(|b| b.name() == blend.name()).unwrap_or(0);
+    let names: Vec<ImString> = blends.iter().map(|b| ImString::new(b.name())).collect();
+    let name_refs: Vec<&ImStr> = names.iter().map(|n| n.as_ref()).collect();
+    if ComboBox::new(im_str!("Blend")).build_simple_string(ui, &mut index, &name_refs) {
+        let radius = blend_radius(blend);
+        *blend = blends[index];
+        if let Some(old_radius) = radius {
+            match blend {
+                Blend::Hard => {},
+                Blend::Smooth { radius } | Blend::Exponential { radius } | Blend::Chamfer { radius } | Blend::Stairs { radius, .. } => *radius = old_radius,
+            }
+        }
+        changed = true;
+    }
+
+    match blend {
+        Blend::Hard => {},
+        Blend::Smooth { radius } | Blend::Exponential { radius } | Blend::Chamfer { radius } => {
+            changed |= Drag::new(im_str!("Blend radius")).range(0.01..=128.0).speed(0.05).build(ui, radius);
+        },
+        Blend::Stairs { radius, steps } => {
+            changed |= Drag::new(im_str!("Blend radius")).range(0.01..=128.0).speed(0.05).build(ui, radius);
+            changed |= Drag::new(im_str!("Steps")).range(1..=32).build(ui, steps);
+        },
+    }
+
+    changed
+}
+
+fn blend_radius(blend: &Blend) -> Option<f32> {
+    match *blend {
+        Blend::Hard => None,
+        Blend::Smooth { radius } | Blend::Exponential { radius } | Blend::Chamfer { radius } | Blend::Stairs { radius, .. } => Some(radius),
+    }
+}
+
+fn edit_modifier(ui: &Ui, modifier: &mut Modifier) -> bool {
+    match modifier {
+        Modifier::Repeat { period } => drag_vec3(ui, im_str!("Period"), period, 0.1),
+        Modifier::RepeatLimited { period, count } => {
+            drag_vec3(ui, im_str!("Period"), period, 0.1) | drag_vec3(ui, im_str!("Count"), count, 0.05)
+        },
+        Modifier::Mirror { x, y, z } => {
+            ui.checkbox(im_str!("X"), x) | ui.checkbox(im_str!("Y"), y) | ui.checkbox(im_str!("Z"), z)
+        },
+        Modifier::Twist { rate, extent } | Modifier::Bend { rate, extent } => {
+            Drag::new(im_str!("Rate")).speed(0.001).build(ui, rate) | drag_f32(ui, im_str!("Extent"), extent)
+        },
+        Modifier::Elongate { amount } => drag_vec3(ui, im_str!("Amount"), amount, 0.1),
+        Modifier::Round { radius } => drag_f32(ui, im_str!("Radius"), radius),
+        Modifier::Onion { thickness } => drag_f32(ui, im_str!("Thickness"), thickness),
+    }
+}
+
+fn edit_primitive(ui: &Ui, primitive: &mut Primitive) -> bool {
+    match primitive {
+        Primitive::Sphere { radius } => drag_f32(ui, im_str!("Radius"), radius),
+        Primitive::Plane { normal, offset } => {
+            let mut changed = drag_vec3(ui, im_str!("Normal"), normal, 0.01);
+            if changed && normal.magnitude2() > 0.0 {
+                *normal = normal.normalize();
+            }
+            changed |= drag_f32(ui, im_str!("Offset"), offset);
+            changed
+        },
+        Primitive::Box { half_extents } => drag_vec3(ui, im_str!("Half extents"), half_extents, 0.1),
+        Primitive::RoundBox { half_extents, radius } => {
+            drag_vec3(ui, im_str!("Half extents"), half_extents, 0.1) | drag_f32(ui, im_str!("Radius"), radius)
+        },
+        Primitive::Torus { major_radius, minor_radius } => {
+            drag_f32(ui, im_str!("Major radius"), major_radius) | drag_f32(ui, im_str!("Minor radius"), minor_radius)
+        },
+        Primitive::Capsule { half_height, radius } | Primitive::Cylinder { half_height, radius } => {
+            drag_f32(ui, im_str!("Half height"), half_height) | drag_f32(ui, im_str!("Radius"), radius)
+        },
+        Primitive::Cone { half_height, bottom_radius, top_radius } => {
+            drag_f32(ui, im_str!("Half height"), half_height)
+                | drag_f32(ui, im_str!("Bottom radius"), bottom_radius)
+                | drag_f32(ui, im_str!("Top radius"), top_radius)
+        },
+        Primitive::Ellipsoid { radii } => drag_vec3(ui, im_str!("Radii"), radii, 0.1),
+        Primitive::HexPrism { radius, half_length } => {
+            drag_f32(ui, im_str!("Radius"), radius) | drag_f32(ui, im_str!("Half length"), half_length)
+        },
+        Primitive::Link { half_length, major_radius, minor_radius } => {
+            drag_f32(ui, im_str!("Half length"), half_length)
+                | drag_f32(ui, im_str!("Major radius"), major_radius)
+                | drag_f32(ui, im_str!("Minor radius"), minor_radius)
+        },
+    }
+}
+
+fn drag_f32(ui: &Ui, label: &ImStr, value: &mut f32) -> bool {
+    Drag::new(label).speed(0.1).build(ui, value)
+}
+
+fn drag_vec3(ui: &Ui, label: &ImStr, value: &mut Vector3<f32>, speed: f32) -> bool {
+    let mut array: [f32; 3] = (*value).into();
+    if Drag::new(label).speed(speed).build_array(ui, &mut array) {
+        *value = array.into();
+        return true;
+    }
+    false
+}
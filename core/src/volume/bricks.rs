@@ -0,0 +1,449 @@
+use cgmath::*;
+
+use super::Volume;
+use crate::scene::eval::EMPTY_DISTANCE;
+
+//Cells along each side of a brick
+pub const BRICK_SIZE: usize = 8;
+//Samples along each side of a stored brick. Neighbouring bricks share their border samples,
+//so filtering inside a brick never needs anything from the next one.
+pub const BRICK_SAMPLES: usize = BRICK_SIZE + 1;
+//How close the surface has to come to a brick before it gets stored, in voxels.
+//Normals and soft shadows sample a little around the surface, that has to stay inside stored bricks.
+const MARGIN: f32 = 2.0;
+//Bricks along each side of the blocks that get culled as a whole before looking at single bricks
+const BLOCK_BRICKS: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Brick {
+    //Nothing stored, every point in the brick is at least this far from the surface, negative inside
+    Empty(f32),
+    //Samples are in the atlas at this slot
+    Stored(u32),
+}
+
+//What changed in an update
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BrickUpdate {
+    pub stored: usize,
+    //Bricks that got a slot in this update, these have nothing baked yet
+    pub allocated: Vec<usize>,
+    pub released: usize,
+    //Bricks near the surface that didn't fit in the atlas, those show up as holes
+    pub overflow: usize,
+}
+
+//Which bricks of the volume are near the surface and where in the atlas they live.
+//Only these get baked and stored, the rest is described by a single distance bound per brick.
+//Brick (x, y, z) covers voxels x * BRICK_SIZE up to and including (x + 1) * BRICK_SIZE. Everything in here
+//is in voxels, positions as well as distances, see VolumeConfig. Slots are numbered x fastest through the atlas.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BrickMap {
+    //Bricks along each axis of the volume
+    pub grid: [usize; 3],
+    //Slots along each axis of the atlas
+    pub atlas: [usize; 3],
+    bricks: Vec<Brick>,
+    //The brick each slot holds
+    slots: Vec<Option<usize>>,
+    free: Vec<u32>,
+}
+
+impl BrickMap {
+    pub fn new(grid: [usize; 3], atlas: [usize; 3]) -> BrickMap {
+        let capacity = atlas[0] * atlas[1] * atlas[2];
+        BrickMap {
+            grid,
+            atlas,
+            bricks: vec![Brick::Empty(EMPTY_DISTANCE); grid[0] * grid[1] * grid[2]],
+            slots: vec![None; capacity],
+            //Popped from the back, so the atlas fills up from slot 0
+            free: (0..capacity as u32).rev().collect(),
+        }
+    }
+
+    //Enough bricks to cover `size` voxels
+    pub fn grid_for(size: [usize; 3]) -> [usize; 3] {
+        let bricks = |s: usize| s.div_ceil(BRICK_SIZE);
+        [bricks(size[0]), bricks(size[1]), bricks(size[2])]
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn stored_count(&self) -> usize {
+        self.capacity() - self.free.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.bricks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bricks.is_empty()
+    }
+
+    pub fn index(&self, x: usize, y: usize, z: usize) -> usize {
+        x + self.grid[0] * (y + self.grid[1] * z)
+    }
+
+    pub fn coords(&self, index: usize) -> [usize; 3] {
+        [index % self.grid[0], (index / self.grid[0]) % self.grid[1], index / (self.grid[0] * self.grid[1])]
+    }
+
+    pub fn get(&self, index: usize) -> Brick {
+        self.bricks[index]
+    }
+
+    //Voxels covered by the grid
+    pub fn voxels(&self) -> [usize; 3] {
+        [self.grid[0] * BRICK_SIZE, self.grid[1] * BRICK_SIZE, self.grid[2] * BRICK_SIZE]
+    }
+
+    //Texels of the atlas texture
+    pub fn atlas_size(&self) -> [usize; 3] {
+        [self.atlas[0] * BRICK_SAMPLES, self.atlas[1] * BRICK_SAMPLES, self.atlas[2] * BRICK_SAMPLES]
+    }
+
+    //First texel of a slot in the atlas
+    pub fn slot_origin(&self, slot: u32) -> [usize; 3] {
+        let slot = slot as usize;
+        let x = slot % self.atlas[0];
+        let y = (slot / self.atlas[0]) % self.atlas[1];
+        let z = slot / (self.atlas[0] * self.atlas[1]);
+        [x * BRICK_SAMPLES, y * BRICK_SAMPLES, z * BRICK_SAMPLES]
+    }
+
+    //Voxel position of the first sample of a brick
+    pub fn brick_origin(&self, index: usize) -> Vector3<f32> {
+        let [x, y, z] = self.coords(index);
+        Vector3::new(x as f32, y as f32, z as f32) * BRICK_SIZE as f32
+    }
+
+    fn brick_centre(&self, index: usize) -> Vector3<f32> {
+        self.brick_origin(index) + Vector3::new(1.0, 1.0, 1.0) * (BRICK_SIZE as f32 * 0.5)
+    }
+
+    //Keeps the slot the brick already has
+    pub fn allocate(&mut self, index: usize) -> Option<u32> {
+        if let Brick::Stored(slot) = self.bricks[index] {
+            return Some(slot);
+        }
+        let slot = self.free.pop()?;
+        self.slots[slot as usize] = Some(index);
+        self.bricks[index] = Brick::Stored(slot);
+        Some(slot)
+    }
+
+    pub fn release(&mut self, index: usize, distance: f32) {
+        if let Brick::Stored(slot) = self.bricks[index] {
+            self.slots[slot as usize] = None;
+            self.free.push(slot);
+        }
+        self.bricks[index] = Brick::Empty(distance);
+    }
+
+    //Drops every brick, which puts the atlas back in order as well
+    pub fn clear(&mut self) {
+        for brick in self.bricks.iter_mut() {
+            *brick = Brick::Empty(EMPTY_DISTANCE);
+        }
+        for slot in self.slots.iter_mut() {
+            *slot = None;
+        }
+        self.free = (0..self.capacity() as u32).rev().collect();
+    }
+
+    //Decides which bricks are needed from a conservative distance estimate, like Scene::distance.
+    //Bricks keep their slot if they stay, so only new ones strictly need to be baked.
+    pub fn update<F: Fn(Vector3<f32>) -> f32>(&mut self, distance: F) -> BrickUpdate {
+        let half_diagonal = BRICK_SIZE as f32 * 0.5 * 3.0f32.sqrt();
+        let mut wanted: Vec<Option<f32>> = vec![None; self.len()];
+
+        //Whole blocks far from the surface can skip evaluating every brick, the bound of a brick
+        //is what's left of the block distance after getting from the block centre to the far corner of the brick
+        let block_size = (BLOCK_BRICKS * BRICK_SIZE) as f32;
+        let block_half_diagonal = block_size * 0.5 * 3.0f32.sqrt();
+        let blocks = |g: usize| g.div_ceil(BLOCK_BRICKS);
+        for bz in 0..blocks(self.grid[2]) {
+            for by in 0..blocks(self.grid[1]) {
+                for bx in 0..blocks(self.grid[0]) {
+                    let centre = (Vector3::new(bx as f32, by as f32, bz as f32) + Vector3::new(0.5, 0.5, 0.5)) * block_size;
+                    let block_distance = distance(centre);
+                    let far = block_distance.abs() > block_half_diagonal + MARGIN;
+
+                    let range = |b: usize, g: usize| b * BLOCK_BRICKS..((b + 1) * BLOCK_BRICKS).min(g);
+                    for z in range(bz, self.grid[2]) {
+                        for y in range(by, self.grid[1]) {
+                            for x in range(bx, self.grid[0]) {
+                                let index = self.index(x, y, z);
+                                let brick_centre = self.brick_centre(index);
+                                let (d, from) = if far {
+                                    (block_distance.abs() - (brick_centre - centre).magnitude(), block_distance)
+                                } else {
+                                    let d = distance(brick_centre);
+                                    (d.abs(), d)
+                                };
+                                if d > half_diagonal + MARGIN {
+                                    let bound = d - half_diagonal;
+                                    wanted[index] = Some(if from < 0.0 { -bound } else { bound });
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        //Release first, so the slots are free for the new bricks
+        let mut update = BrickUpdate::default();
+        for (index, wanted) in wanted.iter().enumerate() {
+            if let Some(bound) = wanted {
+                if let Brick::Stored(_) = self.bricks[index] {
+                    update.released += 1;
+                }
+                self.release(index, *bound);
+            }
+        }
+        for (index, wanted) in wanted.iter().enumerate() {
+            if wanted.is_some() {
+                continue;
+            }
+            let was_stored = matches!(self.bricks[index], Brick::Stored(_));
+            match self.allocate(index) {
+                Some(_) => {
+                    update.stored += 1;
+                    if !was_stored {
+                        update.allocated.push(index);
+                    }
+                },
+                //Nothing known about it. The ray marcher skips empty bricks as a whole, so this renders as a hole,
+                //which is why the caller gets told about it through overflow.
+                None => {
+                    self.bricks[index] = Brick::Empty(0.0);
+                    update.overflow += 1;
+                },
+            }
+        }
+        update
+    }
+
+    //Stored bricks as (x, y, z, slot), the layout the bake shader reads them in
+    pub fn stored(&self) -> Vec<[i32; 4]> {
+        self.bake_list(self.stored_indices())
+    }
+
+    pub fn stored_indices(&self) -> Vec<usize> {
+        self.slots.iter().flatten().copied().collect()
+    }
+
+    //Same layout for only some bricks, empty ones get left out
+    pub fn bake_list<I: IntoIterator<Item = usize>>(&self, bricks: I) -> Vec<[i32; 4]> {
+        bricks.into_iter().filter_map(|index| match self.bricks[index] {
+            Brick::Stored(slot) => {
+                let [x, y, z] = self.coords(index);
+                Some([x as i32, y as i32, z as i32, slot as i32])
+            },
+            Brick::Empty(_) => None,
+        }).collect()
+    }
+
+    //Stored bricks with any sample inside the box from `min` to `max`, in voxels
+    pub fn stored_within(&self, min: Vector3<f32>, max: Vector3<f32>) -> Vec<usize> {
+        let range = |min: f32, max: f32, grid: usize| {
+            let first = (min / BRICK_SIZE as f32 - 1.0).ceil().max(0.0);
+            let last = (max / BRICK_SIZE as f32).floor().min(grid as f32 - 1.0);
+            //Casting NaN or infinity is well defined, but an empty range is easier to read
+            if first > last { 0..0 } else { first as usize..last as usize + 1 }
+        };
+        let mut bricks = Vec::new();
+        for z in range(min.z, max.z, self.grid[2]) {
+            for y in range(min.y, max.y, self.grid[1]) {
+                for x in range(min.x, max.x, self.grid[0]) {
+                    let index = self.index(x, y, z);
+                    if let Brick::Stored(_) = self.bricks[index] {
+                        bricks.push(index);
+                    }
+                }
+            }
+        }
+        bricks
+    }
+
+    //How far from the surface stored bricks can have samples, in voxels. Changes further away than this
+    //from every sample of a brick can't change what's baked into it.
+    pub fn reach() -> f32 {
+        BRICK_SIZE as f32 * 3.0f32.sqrt() + MARGIN
+    }
+
+    //Two floats per brick for the indirection texture: the slot, or -1 with the distance bound
+    pub fn indirection(&self) -> Vec<f32> {
+        let mut data = Vec::with_capacity(self.len() * 2);
+        for brick in &self.bricks {
+            match brick {
+                Brick::Stored(slot) => data.extend_from_slice(&[*slot as f32, 0.0]),
+                Brick::Empty(bound) => data.extend_from_slice(&[-1.0, *bound]),
+            }
+        }
+        data
+    }
+
+    //Puts a dense volume back together from the atlas, `sample` gets the texel coordinates
+    //of the atlas and returns what's stored there. Empty bricks get their bound, times `scale`
+    //to bring it to the same units as the samples. Voxels are placed like VolumeConfig::position does.
+    pub fn to_volume<F: Fn([usize; 3]) -> (f32, u32)>(&self, origin: Vector3<f32>, spacing: Vector3<f32>, scale: f32, sample: F) -> Volume {
+        let size = self.voxels();
+        let extent = Vector3::new(size[0] as f32, size[1] as f32, size[2] as f32).mul_element_wise(spacing);
+        let mut volume = Volume::new(size, origin, extent);
+        for z in 0..size[2] {
+            for y in 0..size[1] {
+                for x in 0..size[0] {
+                    let brick = self.index(x / BRICK_SIZE, y / BRICK_SIZE, z / BRICK_SIZE);
+                    let i = volume.index(x, y, z);
+                    match self.bricks[brick] {
+                        Brick::Stored(slot) => {
+                            let o = self.slot_origin(slot);
+                            let (dist, material) = sample([o[0] + x % BRICK_SIZE, o[1] + y % BRICK_SIZE, o[2] + z % BRICK_SIZE]);
+                            volume.distances[i] = dist;
+                            volume.materials[i] = material;
+                        },
+                        Brick::Empty(bound) => volume.distances[i] = bound * scale,
+                    }
+                }
+            }
+        }
+        volume
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //A sphere in the middle of a 64 voxel volume, in voxels
+    fn sphere(p: Vector3<f32>) -> f32 {
+        (p - Vector3::new(32.0, 32.0, 32.0)).magnitude() - 12.0
+    }
+
+    #[test]
+    fn only_bricks_near_the_surface_get_stored() {
+        let mut map = BrickMap::new([8, 8, 8], [8, 8, 8]);
+        let update = map.update(sphere);
+        assert!(update.stored > 0);
+        assert_eq!(update.stored, map.stored_count());
+        assert_eq!(update.allocated.len(), update.stored);
+        assert_eq!(update.overflow, 0);
+
+        let half_diagonal = BRICK_SIZE as f32 * 0.5 * 3.0f32.sqrt();
+        for index in 0..map.len() {
+            let centre = map.brick_centre(index);
+            match map.get(index) {
+                Brick::Stored(_) => assert!(sphere(centre).abs() <= half_diagonal + MARGIN),
+                //The bound has to hold for every voxel of the brick, with the sign of the side it's on
+                Brick::Empty(bound) => {
+                    let origin = map.brick_origin(index);
+                    for z in 0..BRICK_SAMPLES {
+                        for y in 0..BRICK_SAMPLES {
+                            for x in 0..BRICK_SAMPLES {
+                                let d = sphere(origin + Vector3::new(x as f32, y as f32, z as f32));
+                                //The bound is exact at the far corners, up to rounding
+                                assert!(d.abs() >= bound.abs() - 1e-4 && d.signum() == bound.signum(), "brick {} has bound {} but a distance of {}", index, bound, d);
+                            }
+                        }
+                    }
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn released_slots_get_reused() {
+        let mut map = BrickMap::new([4, 4, 4], [2, 1, 1]);
+        let a = map.allocate(0).unwrap();
+        let b = map.allocate(1).unwrap();
+        assert_ne!(a, b);
+        assert_eq!(map.allocate(0), Some(a));
+        assert_eq!(map.allocate(2), None);
+
+        map.release(0, 5.0);
+        assert_eq!(map.get(0), Brick::Empty(5.0));
+        assert_eq!(map.allocate(2), Some(a));
+        assert_eq!(map.stored_indices(), vec![2, 1]);
+
+        //Bricks that are still near the surface after an update keep their slot
+        let mut map = BrickMap::new([8, 8, 8], [8, 8, 8]);
+        map.update(sphere);
+        let before: Vec<Brick> = (0..map.len()).map(|i| map.get(i)).collect();
+        let update = map.update(|p| sphere(p - Vector3::new(4.0, 0.0, 0.0)));
+        assert!(update.released > 0);
+        let mut kept = 0;
+        for (index, brick) in before.iter().enumerate() {
+            if let (Brick::Stored(old), Brick::Stored(new)) = (*brick, map.get(index)) {
+                assert_eq!(old, new);
+                kept += 1;
+            }
+        }
+        assert_eq!(update.allocated.len(), update.stored - kept);
+    }
+
+    #[test]
+    fn overflow_gets_counted() {
+        let mut full = BrickMap::new([8, 8, 8], [8, 8, 8]);
+        let needed = full.update(sphere).stored;
+
+        let mut map = BrickMap::new([8, 8, 8], [2, 2, 1]);
+        let update = map.update(sphere);
+        assert_eq!(update.stored, 4);
+        assert_eq!(update.overflow, needed - 4);
+        assert_eq!(map.stored_count(), map.capacity());
+        //Bricks that didn't fit make the ray marcher step through instead of skipping the surface
+        let holes = (0..map.len()).filter(|&i| map.get(i) == Brick::Empty(0.0)).count();
+        assert_eq!(holes, update.overflow);
+    }
+
+    #[test]
+    fn to_volume_reproduces_the_samples() {
+        let mut map = BrickMap::new([8, 8, 8], [8, 8, 4]);
+        map.update(sphere);
+
+        //What the bake would write into the atlas
+        let size = map.atlas_size();
+        let mut atlas = vec![f32::NAN; size[0] * size[1] * size[2]];
+        for index in map.stored_indices() {
+            let slot = match map.get(index) {
+                Brick::Stored(slot) => slot,
+                Brick::Empty(_) => unreachable!(),
+            };
+            let o = map.slot_origin(slot);
+            let origin = map.brick_origin(index);
+            for z in 0..BRICK_SAMPLES {
+                for y in 0..BRICK_SAMPLES {
+                    for x in 0..BRICK_SAMPLES {
+                        let texel = (o[0] + x) + size[0] * ((o[1] + y) + size[1] * (o[2] + z));
+                        atlas[texel] = sphere(origin + Vector3::new(x as f32, y as f32, z as f32));
+                    }
+                }
+            }
+        }
+
+        let spacing = Vector3::new(0.5, 0.5, 0.5);
+        let volume = map.to_volume(Vector3::zero(), spacing, 0.5, |[x, y, z]| (atlas[x + size[0] * (y + size[1] * z)] * 0.5, 3));
+        assert_eq!(volume.size, map.voxels());
+        for z in 0..volume.size[2] {
+            for y in 0..volume.size[1] {
+                for x in 0..volume.size[0] {
+                    let expected = sphere(Vector3::new(x as f32, y as f32, z as f32)) * 0.5;
+                    let found = volume.get(x, y, z);
+                    match map.get(map.index(x / BRICK_SIZE, y / BRICK_SIZE, z / BRICK_SIZE)) {
+                        Brick::Stored(_) => {
+                            assert_eq!(found, expected);
+                            assert_eq!(volume.materials[volume.index(x, y, z)], 3);
+                        },
+                        Brick::Empty(_) => assert!(found.abs() <= expected.abs() + 1e-4 && found.signum() == expected.signum()),
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use cgmath::*;
+
+use super::bricks::{BrickMap, BRICK_SAMPLES};
+
+//Formats the atlas can be stored in. The single channel ones only keep the distance,
+//so every material comes out as 0.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TexelFormat {
+    R16F,
+    RG16F,
+    R32F,
+    RG32F,
+}
+
+impl TexelFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TexelFormat::R16F => "R16F",
+            TexelFormat::RG16F => "RG16F",
+            TexelFormat::R32F => "R32F",
+            TexelFormat::RG32F => "RG32F",
+        }
+    }
+
+    pub fn all() -> [TexelFormat; 4] {
+        [TexelFormat::R16F, TexelFormat::RG16F, TexelFormat::R32F, TexelFormat::RG32F]
+    }
+
+    pub fn has_material(&self) -> bool {
+        matches!(self, TexelFormat::RG16F | TexelFormat::RG32F)
+    }
+
+    pub fn bytes_per_texel(&self) -> usize {
+        match self {
+            TexelFormat::R16F => 2,
+            TexelFormat::RG16F | TexelFormat::R32F => 4,
+            TexelFormat::RG32F => 8,
+        }
+    }
+
+    //Format qualifier of the image the bake shader writes to
+    pub fn glsl_name(&self) -> &'static str {
+        match self {
+            TexelFormat::R16F => "r16f",
+            TexelFormat::RG16F => "rg16f",
+            TexelFormat::R32F => "r32f",
+            TexelFormat::RG32F => "rg32f",
+        }
+    }
+}
+
+//Where the baked scene lives and how finely it's sampled. Voxel (x, y, z) sits at origin + (x, y, z) * spacing
+//like in Volume. Everything that depends on the size of the volume gets it from here: the textures,
+//the bricks that get baked and the defines of both shaders.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VolumeConfig {
+    pub resolution: [usize; 3],
+    pub origin: Vector3<f32>,
+    pub extent: Vector3<f32>,
+    pub format: TexelFormat,
+}
+
+impl VolumeConfig {
+    //One voxel per world unit, so world and voxel coordinates line up
+    pub fn default() -> VolumeConfig {
+        VolumeConfig {
+            resolution: [512, 512, 512],
+            origin: Vector3::zero(),
+            extent: Vector3::new(512.0, 512.0, 512.0),
+            format: TexelFormat::RG32F,
+        }
+    }
+
+    pub fn spacing(&self) -> Vector3<f32> {
+        Vector3::new(
+            self.extent.x / self.resolution[0] as f32,
+            self.extent.y / self.resolution[1] as f32,
+            self.extent.z / self.resolution[2] as f32,
+        )
+    }
+
+    //Distances are stored in multiples of this, the biggest spacing. Going one voxel along any axis
+    //never covers more than that, so a distance in these units is still a lower bound in voxels.
+    pub fn voxel_size(&self) -> f32 {
+        let spacing = self.spacing();
+        spacing.x.max(spacing.y).max(spacing.z)
+    }
+
+    //World position of a point in voxel coordinates
+    pub fn position(&self, voxel: Vector3<f32>) -> Vector3<f32> {
+        self.origin + voxel.mul_element_wise(self.spacing())
+    }
+
+    //The other way around, positions outside the volume go below 0 or past the resolution
+    pub fn to_voxel(&self, position: Vector3<f32>) -> Vector3<f32> {
+        (position - self.origin).div_element_wise(self.spacing())
+    }
+
+    //An atlas for an eighth of the bricks, which is plenty for anything that isn't noise.
+    //Each side stays below 2048 texels, the smallest maximum 3D texture size drivers seem to have.
+    pub fn brick_map(&self) -> BrickMap {
+        let grid = BrickMap::grid_for(self.resolution);
+        let max_slots = 2048 / BRICK_SAMPLES;
+        let slots = |g: usize| g.div_ceil(2).clamp(1, max_slots);
+        BrickMap::new(grid, [slots(grid[0]), slots(grid[1]), slots(grid[2])])
+    }
+
+    //Size of the atlas on the GPU, the indirection texture is tiny next to it
+    pub fn atlas_bytes(&self) -> usize {
+        let [w, h, d] = self.brick_map().atlas_size();
+        w * h * d * self.format.bytes_per_texel()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stretched() -> VolumeConfig {
+        VolumeConfig {
+            resolution: [100, 50, 200],
+            origin: Vector3::new(-10.0, 0.0, 5.0),
+            extent: Vector3::new(50.0, 50.0, 50.0),
+            format: TexelFormat::R16F,
+        }
+    }
+
+    #[test]
+    fn spacing_and_voxel_size() {
+        let config = stretched();
+        assert_eq!(config.spacing(), Vector3::new(0.5, 1.0, 0.25));
+        assert_eq!(config.voxel_size(), 1.0);
+        assert_eq!(VolumeConfig::default().voxel_size(), 1.0);
+
+        let voxel = Vector3::new(3.0, 7.5, 40.0);
+        assert_eq!(config.position(voxel), Vector3::new(-8.5, 7.5, 15.0));
+        assert_eq!(config.to_voxel(config.position(voxel)), voxel);
+    }
+
+    #[test]
+    fn atlas_stays_below_the_texture_limit() {
+        let mut config = VolumeConfig::default();
+        config.resolution = [4096, 64, 8];
+        let map = config.brick_map();
+        assert_eq!(map.voxels(), [4096, 64, 8]);
+        for &size in &map.atlas_size() {
+            assert!(size > 0 && size <= 2048);
+        }
+    }
+
+    //The biggest sphere that fits in the volume, its surface is about as much as a scene is going to need
+    #[test]
+    fn default_atlas_fits_a_sphere() {
+        let config = VolumeConfig::default();
+        let mut map = config.brick_map();
+        let centre = config.extent * 0.5;
+        let radius = centre.x - 16.0;
+        let update = map.update(|v| ((config.position(v) - centre).magnitude() - radius) / config.voxel_size());
+        assert!(update.stored > 0);
+        assert_eq!(update.overflow, 0);
+    }
+}
@@ -0,0 +1,69 @@
+use cgmath::*;
+use rayon::prelude::*;
+
+use super::bricks::{BrickMap, BRICK_SAMPLES};
+use super::config::VolumeConfig;
+use crate::scene::Scene;
+
+//The bake shader on the CPU, spread over every core with rayon. A lot slower than the compute shader,
+//but it runs on drivers without compute shaders and without any GL context at all, which is what tests
+//and offline exports need. Samples come out the way the bake shader writes them, see scene::codegen:
+//the distance in multiples of the voxel size and the material, two floats per sample.
+
+//Samples of a single brick, x fastest, laid out like its slot in the atlas
+pub fn bake_brick(scene: &Scene, config: &VolumeConfig, map: &BrickMap, index: usize) -> Vec<f32> {
+    let origin = map.brick_origin(index);
+    let voxel_size = config.voxel_size();
+    let mut texels = Vec::with_capacity(BRICK_SAMPLES * BRICK_SAMPLES * BRICK_SAMPLES * 2);
+    for z in 0..BRICK_SAMPLES {
+        for y in 0..BRICK_SAMPLES {
+            for x in 0..BRICK_SAMPLES {
+                let voxel = origin + Vector3::new(x as f32, y as f32, z as f32);
+                let sample = scene.sample(config.position(voxel));
+                texels.extend_from_slice(&[sample.dist / voxel_size, sample.material as f32]);
+            }
+        }
+    }
+    texels
+}
+
+//Bakes the given bricks in parallel, the results are in the same order
+pub fn bake_bricks(scene: &Scene, config: &VolumeConfig, map: &BrickMap, bricks: &[usize]) -> Vec<Vec<f32>> {
+    bricks.par_iter().map(|&index| bake_brick(scene, config, map, index)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::{Node, Primitive};
+
+    #[test]
+    fn sphere_bricks_match_the_scene() {
+        let mut scene = Scene::new();
+        scene.add(Node::primitive(Primitive::Sphere { radius: 8.0 }, 1));
+        //Uneven spacing, so distances have to come out in multiples of the largest one
+        let config = VolumeConfig {
+            resolution: [64, 64, 64],
+            origin: Vector3::new(-16.0, -16.0, -24.0),
+            extent: Vector3::new(32.0, 32.0, 48.0),
+            ..VolumeConfig::default()
+        };
+        let voxel_size = config.voxel_size();
+        let mut map = config.brick_map();
+        map.update(|v| scene.distance(config.position(v)) / voxel_size);
+
+        let bricks = map.stored_indices();
+        assert!(!bricks.is_empty());
+        for (&index, texels) in bricks.iter().zip(bake_bricks(&scene, &config, &map, &bricks)) {
+            assert_eq!(texels.len(), BRICK_SAMPLES * BRICK_SAMPLES * BRICK_SAMPLES * 2);
+            let origin = map.brick_origin(index);
+            for (i, texel) in texels.chunks_exact(2).enumerate() {
+                let (x, y, z) = (i % BRICK_SAMPLES, (i / BRICK_SAMPLES) % BRICK_SAMPLES, i / (BRICK_SAMPLES * BRICK_SAMPLES));
+                let p = config.position(origin + Vector3::new(x as f32, y as f32, z as f32));
+                let expected = (p.magnitude() - 8.0) / voxel_size;
+                assert!((texel[0] - expected).abs() < 1e-4, "brick {} sample {:?} is {} instead of {}", index, [x, y, z], texel[0], expected);
+                assert_eq!(texel[1], 1.0);
+            }
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use cgmath::*;
+
+use super::Volume;
+
+//Stands in for infinity, squared distances get added to it so it can't be the real thing
+const FAR: f32 = 1e20;
+
+//Turns a grid of occupied cells into a signed distance volume, with the surface halfway
+//between occupied and empty cell centres. Cells are indexed like Volume, x fastest.
+//Empty cells take the material of the closest occupied one, so sampling near the surface
+//picks up the right material from either side.
+pub fn from_occupancy(size: [usize; 3], origin: Vector3<f32>, extent: Vector3<f32>, cells: &[Option<u32>]) -> Volume {
+    let mut volume = Volume::new(size, origin, extent);
+    let spacing = volume.spacing();
+
+    let (outside, nearest) = distance_transform(size, spacing, |i| cells[i].is_some());
+    let (inside, _) = distance_transform(size, spacing, |i| cells[i].is_none());
+
+    //Half a cell towards the other side, so both sides agree on where the surface is
+    let half = spacing.x.min(spacing.y).min(spacing.z) * 0.5;
+    for (i, cell) in cells.iter().enumerate() {
+        match cell {
+            Some(material) => {
+                volume.distances[i] = if inside[i] >= FAR { -FAR } else { half - inside[i].sqrt() };
+                volume.materials[i] = *material;
+            },
+            None => {
+                volume.distances[i] = outside[i].sqrt() - half;
+                volume.materials[i] = nearest[i].and_then(|n| cells[n]).unwrap_or(0);
+            },
+        }
+    }
+
+    //Nothing at all, or nothing but solid, leaves infinities around
+    let limit = extent.magnitude();
+    for d in volume.distances.iter_mut() {
+        *d = d.max(-limit).min(limit);
+    }
+    volume
+}
+
+//Squared distance from every cell to the closest feature cell, along with the index of that cell.
+//Felzenszwalb and Huttenlocher's lower envelope of parabolas, once along every axis.
+pub fn distance_transform<F: Fn(usize) -> bool>(size: [usize; 3], spacing: Vector3<f32>, feature: F) -> (Vec<f32>, Vec<Option<usize>>) {
+    let len = size[0] * size[1] * size[2];
+    let mut dist: Vec<f32> = (0..len).map(|i| if feature(i) { 0.0 } else { FAR }).collect();
+    let mut nearest: Vec<Option<usize>> = (0..len).map(|i| if feature(i) { Some(i) } else { None }).collect();
+
+    let strides = [1, size[0], size[0] * size[1]];
+    for axis in 0..3 {
+        let n = size[axis];
+        let (a, b) = match axis {
+            0 => (1, 2),
+            1 => (0, 2),
+            _ => (0, 1),
+        };
+
+        let mut line = vec![0.0; n];
+        let mut line_nearest = vec![None; n];
+        for j in 0..size[b] {
+            for i in 0..size[a] {
+                let start = i * strides[a] + j * strides[b];
+                for k in 0..n {
+                    line[k] = dist[start + k * strides[axis]];
+                    line_nearest[k] = nearest[start + k * strides[axis]];
+                }
+                let (d, from) = transform_line(&line, spacing[axis]);
+                for k in 0..n {
+                    dist[start + k * strides[axis]] = d[k];
+                    nearest[start + k * strides[axis]] = line_nearest[from[k]];
+                }
+            }
+        }
+    }
+
+    (dist, nearest)
+}
+
+//One dimensional pass, returns the new squared distances and which cell each one came from
+fn transform_line(f: &[f32], spacing: f32) -> (Vec<f32>, Vec<usize>) {
+    let n = f.len();
+    let mut d = vec![FAR; n];
+    let mut from = vec![0; n];
+    //Cells with a finite distance make up the envelope, the rest would only add infinite parabolas
+    let sites: Vec<usize> = (0..n).filter(|&q| f[q] < FAR).collect();
+    if sites.is_empty() {
+        return (d, from);
+    }
+
+    let pos = |q: usize| q as f32 * spacing;
+    //Where the parabolas of sites p and q intersect
+    let intersect = |p: usize, q: usize| ((f[q] + pos(q) * pos(q)) - (f[p] + pos(p) * pos(p))) / (2.0 * pos(q) - 2.0 * pos(p));
+
+    //z[0] is minus infinity, so the first site never gets popped
+    let mut v = vec![sites[0]];
+    let mut z = vec![f32::NEG_INFINITY, f32::INFINITY];
+    for &q in &sites[1..] {
+        let mut s = intersect(v[v.len() - 1], q);
+        while s <= z[v.len() - 1] {
+            v.pop();
+            z.pop();
+            s = intersect(v[v.len() - 1], q);
+        }
+        let last = z.len() - 1;
+        z[last] = s;
+        v.push(q);
+        z.push(f32::INFINITY);
+    }
+
+    let mut k = 0;
+    for q in 0..n {
+        while z[k + 1] < pos(q) {
+            k += 1;
+        }
+        let offset = pos(q) - pos(v[k]);
+        d[q] = offset * offset + f[v[k]];
+        from[q] = v[k];
+    }
+    (d, from)
+}
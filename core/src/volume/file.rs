@@ -0,0 +1,105 @@
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use super::Volume;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VolumeFormat {
+    Raw,
+    SdfGen,
+    Vdb,
+    //Import only
+    MagicaVoxel,
+    //Import only, reconstructed with the default settings
+    PointCloud,
+}
+
+impl VolumeFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            VolumeFormat::Raw => "Raw",
+            VolumeFormat::SdfGen => "SDFGen",
+            VolumeFormat::Vdb => "OpenVDB",
+            VolumeFormat::MagicaVoxel => "MagicaVoxel",
+            VolumeFormat::PointCloud => "Point cloud",
+        }
+    }
+
+    pub fn all() -> [VolumeFormat; 5] {
+        [VolumeFormat::Raw, VolumeFormat::SdfGen, VolumeFormat::Vdb, VolumeFormat::MagicaVoxel, VolumeFormat::PointCloud]
+    }
+
+    //The raw format can be opened through either the data or the header, saving to the header puts the data in a .raw file
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<VolumeFormat> {
+        let ext = path.as_ref().extension()?.to_str()?.to_lowercase();
+        match ext.as_str() {
+            "raw" | "vol" | "json" => Some(VolumeFormat::Raw),
+            "sdf" => Some(VolumeFormat::SdfGen),
+            "vdb" => Some(VolumeFormat::Vdb),
+            "vox" => Some(VolumeFormat::MagicaVoxel),
+            "ply" | "xyz" => Some(VolumeFormat::PointCloud),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for VolumeFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Debug)]
+pub enum VolumeFileError {
+    Io(io::Error),
+    Parse(String),
+    Serialize(String),
+    Size { expected: u64, found: u64 },
+    UnknownFormat(String),
+    ReadOnly(VolumeFormat),
+}
+
+impl fmt::Display for VolumeFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            VolumeFileError::Io(err) => write!(f, "Failed to access volume file: {}", err),
+            VolumeFileError::Parse(err) => write!(f, "Failed to parse volume file: {}", err),
+            VolumeFileError::Serialize(err) => write!(f, "Failed to write volume: {}", err),
+            VolumeFileError::Size { expected, found } => write!(f, "Volume data is {} bytes, expected {}", found, expected),
+            VolumeFileError::UnknownFormat(path) => write!(f, "Don't know how to read {}", path),
+            VolumeFileError::ReadOnly(format) => write!(f, "{} files can only be imported", format),
+        }
+    }
+}
+
+impl From<io::Error> for VolumeFileError {
+    fn from(err: io::Error) -> VolumeFileError {
+        VolumeFileError::Io(err)
+    }
+}
+
+//Picks the format from the extension
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Volume, VolumeFileError> {
+    let path = path.as_ref();
+    match VolumeFormat::from_path(path) {
+        Some(VolumeFormat::Raw) => super::raw::load(path),
+        Some(VolumeFormat::SdfGen) => super::sdfgen::load(path),
+        Some(VolumeFormat::Vdb) => super::vdb::load(path),
+        Some(VolumeFormat::MagicaVoxel) => super::vox::load(path),
+        Some(VolumeFormat::PointCloud) => super::points::load_volume(path),
+        None => Err(VolumeFileError::UnknownFormat(path.display().to_string())),
+    }
+}
+
+//Picks the format from the extension as well, VDB grids are called "surface" like most level sets
+pub fn save<P: AsRef<Path>>(volume: &Volume, path: P) -> Result<(), VolumeFileError> {
+    let path = path.as_ref();
+    match VolumeFormat::from_path(path) {
+        Some(VolumeFormat::Raw) => super::raw::save(volume, path),
+        Some(VolumeFormat::SdfGen) => super::sdfgen::save(volume, path),
+        Some(VolumeFormat::Vdb) => super::vdb::save(volume, path, "surface"),
+        Some(format @ VolumeFormat::MagicaVoxel) | Some(format @ VolumeFormat::PointCloud) => Err(VolumeFileError::ReadOnly(format)),
+        None => Err(VolumeFileError::UnknownFormat(path.display().to_string())),
+    }
+}
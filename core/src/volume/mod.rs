@@ -0,0 +1,161 @@
+use cgmath::*;
+use rayon::prelude::*;
+
+pub mod file;
+pub mod raw;
+pub mod sdfgen;
+pub mod vdb;
+pub mod vox;
+pub mod points;
+pub mod distance;
+pub mod bricks;
+pub mod config;
+pub mod cpu;
+
+use crate::scene::Scene;
+use crate::scene::eval::{Sample, EMPTY_DISTANCE};
+
+//A dense grid of distances on the CPU, laid out like the scene the bake shader fills.
+//Voxel (x, y, z) sits at origin + (x, y, z) * spacing, so with an origin of 0
+//and an extent equal to the size, voxel coordinates and world coordinates line up
+//just like they do in the bake shader.
+//Distances are stored in world units, not divided by SCENE_SCALE.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Volume {
+    pub size: [usize; 3],
+    pub origin: Vector3<f32>,
+    pub extent: Vector3<f32>,
+    pub distances: Vec<f32>,
+    pub materials: Vec<u32>,
+}
+
+impl Volume {
+    pub fn new(size: [usize; 3], origin: Vector3<f32>, extent: Vector3<f32>) -> Volume {
+        let len = size[0] * size[1] * size[2];
+        Volume {
+            size: size,
+            origin: origin,
+            extent: extent,
+            distances: vec![EMPTY_DISTANCE; len],
+            materials: vec![0; len],
+        }
+    }
+
+    //Samples are independent, so they get spread over every core with rayon
+    pub fn from_fn<F: Fn(Vector3<f32>) -> Sample + Sync>(size: [usize; 3], origin: Vector3<f32>, extent: Vector3<f32>, f: F) -> Volume {
+        let mut volume = Volume::new(size, origin, extent);
+        let samples: Vec<Sample> = (0..volume.distances.len()).into_par_iter().map(|i| {
+            let (x, y, z) = (i % size[0], (i / size[0]) % size[1], i / (size[0] * size[1]));
+            f(volume.position(x, y, z))
+        }).collect();
+        for (i, sample) in samples.iter().enumerate() {
+            volume.distances[i] = sample.dist;
+            volume.materials[i] = sample.material;
+        }
+        volume
+    }
+
+    pub fn from_scene(scene: &Scene, size: [usize; 3], origin: Vector3<f32>, extent: Vector3<f32>) -> Volume {
+        Volume::from_fn(size, origin, extent, |p| scene.sample(p))
+    }
+
+    pub fn index(&self, x: usize, y: usize, z: usize) -> usize {
+        x + y * self.size[0] + z * self.size[0] * self.size[1]
+    }
+
+    pub fn get(&self, x: usize, y: usize, z: usize) -> f32 {
+        self.distances[self.index(x, y, z)]
+    }
+
+    pub fn spacing(&self) -> Vector3<f32> {
+        Vector3::new(
+            self.extent.x / self.size[0] as f32,
+            self.extent.y / self.size[1] as f32,
+            self.extent.z / self.size[2] as f32,
+        )
+    }
+
+    pub fn position(&self, x: usize, y: usize, z: usize) -> Vector3<f32> {
+        self.origin + Vector3::new(x as f32, y as f32, z as f32).mul_element_wise(self.spacing())
+    }
+
+    //Position in voxel coordinates, so 0 is the first voxel and size - 1 the last
+    pub fn to_voxel(&self, p: Vector3<f32>) -> Vector3<f32> {
+        (p - self.origin).div_element_wise(self.spacing())
+    }
+
+    //Trilinear interpolation, clamped to the edges just like CLAMP_TO_EDGE on the texture
+    pub fn sample(&self, p: Vector3<f32>) -> f32 {
+        let v = self.to_voxel(p);
+        let clamp = |x: f32, size: usize| x.max(0.0).min((size - 1) as f32);
+        let v = Vector3::new(clamp(v.x, self.size[0]), clamp(v.y, self.size[1]), clamp(v.z, self.size[2]));
+
+        let x0 = v.x.floor() as usize;
+        let y0 = v.y.floor() as usize;
+        let z0 = v.z.floor() as usize;
+        let x1 = (x0 + 1).min(self.size[0] - 1);
+        let y1 = (y0 + 1).min(self.size[1] - 1);
+        let z1 = (z0 + 1).min(self.size[2] - 1);
+        let (fx, fy, fz) = (v.x - x0 as f32, v.y - y0 as f32, v.z - z0 as f32);
+
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let c00 = lerp(self.get(x0, y0, z0), self.get(x1, y0, z0), fx);
+        let c10 = lerp(self.get(x0, y1, z0), self.get(x1, y1, z0), fx);
+        let c01 = lerp(self.get(x0, y0, z1), self.get(x1, y0, z1), fx);
+        let c11 = lerp(self.get(x0, y1, z1), self.get(x1, y1, z1), fx);
+        lerp(lerp(c00, c10, fy), lerp(c01, c11, fy), fz)
+    }
+
+    //Material of the nearest voxel
+    pub fn material(&self, p: Vector3<f32>) -> u32 {
+        let v = self.to_voxel(p);
+        let round = |x: f32, size: usize| (x + 0.5).max(0.0).min((size - 1) as f32) as usize;
+        self.materials[self.index(round(v.x, self.size[0]), round(v.y, self.size[1]), round(v.z, self.size[2]))]
+    }
+
+    //Like sample, but also usable outside the volume. Assumes the surface is inside the bounds,
+    //so the distance to the bounds and the distance at the edge minus the way there are both lower bounds
+    pub fn sample_unbounded(&self, p: Vector3<f32>) -> f32 {
+        let min = self.origin;
+        let max = self.position(self.size[0] - 1, self.size[1] - 1, self.size[2] - 1);
+        let clamped = Vector3::new(p.x.max(min.x).min(max.x), p.y.max(min.y).min(max.y), p.z.max(min.z).min(max.z));
+        let outside = (p - clamped).magnitude();
+        let dist = self.sample(clamped);
+        if outside > 0.0 {
+            outside.max(dist - outside)
+        } else {
+            dist
+        }
+    }
+
+    //Uniformly scales and moves the volume so it fits centred inside the given box,
+    //leaving `margin` (a fraction of the box) free on every side. Distances scale along with it.
+    pub fn fit(&self, origin: Vector3<f32>, extent: Vector3<f32>, margin: f32) -> Volume {
+        let available = extent * (1.0 - 2.0 * margin);
+        let scale = (available.x / self.extent.x).min(available.y / self.extent.y).min(available.z / self.extent.z);
+        let new_extent = self.extent * scale;
+
+        Volume {
+            size: self.size,
+            origin: origin + (extent - new_extent) * 0.5,
+            extent: new_extent,
+            distances: self.distances.iter().map(|d| d * scale).collect(),
+            materials: self.materials.clone(),
+        }
+    }
+
+    //Samples this volume on a new grid, e.g. to match the size of the scene texture
+    pub fn resample(&self, size: [usize; 3], origin: Vector3<f32>, extent: Vector3<f32>) -> Volume {
+        Volume::from_fn(size, origin, extent, |p| Sample::new(self.sample_unbounded(p), self.material(p)))
+    }
+
+    //Central differences over one voxel
+    pub fn gradient(&self, p: Vector3<f32>) -> Vector3<f32> {
+        let s = self.spacing();
+        Vector3::new(
+            (self.sample(p + Vector3::new(s.x, 0.0, 0.0)) - self.sample(p - Vector3::new(s.x, 0.0, 0.0))) / (2.0 * s.x),
+            (self.sample(p + Vector3::new(0.0, s.y, 0.0)) - self.sample(p - Vector3::new(0.0, s.y, 0.0))) / (2.0 * s.y),
+            (self.sample(p + Vector3::new(0.0, 0.0, s.z)) - self.sample(p - Vector3::new(0.0, 0.0, s.z))) / (2.0 * s.z),
+        )
+    }
+}
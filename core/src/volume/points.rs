@@ -0,0 +1,554 @@
+use std::fs;
+use std::path::Path;
+
+use cgmath::*;
+
+use super::Volume;
+use super::distance;
+use super::file::VolumeFileError;
+
+//Voxels along the longest side when reconstructing through volume::file::load
+pub const RESOLUTION: usize = 128;
+//Radius of the smoothing kernel in voxels
+pub const SMOOTHING: f32 = 1.5;
+//Empty voxels around the points, so the surface isn't right at the edge of the volume
+const PADDING: usize = 4;
+
+//Oriented points, normals point out of the surface
+#[derive(Clone, Debug, PartialEq)]
+pub struct PointCloud {
+    pub positions: Vec<Vector3<f32>>,
+    pub normals: Vec<Vector3<f32>>,
+}
+
+impl PointCloud {
+    pub fn new() -> PointCloud {
+        PointCloud {
+            positions: Vec::new(),
+            normals: Vec::new(),
+        }
+    }
+
+    pub fn add_point(&mut self, position: Vector3<f32>, normal: Vector3<f32>) {
+        self.positions.push(position);
+        self.normals.push(if normal.magnitude2() > 0.0 { normal.normalize() } else { normal });
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    pub fn bounds(&self) -> (Vector3<f32>, Vector3<f32>) {
+        let mut min = Vector3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+        let mut max = -min;
+        for p in &self.positions {
+            min = Vector3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+            max = Vector3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+        }
+        (min, max)
+    }
+}
+
+fn parse_error(message: String) -> VolumeFileError {
+    VolumeFileError::Parse(message)
+}
+
+//Picks the parser from the extension
+pub fn load<P: AsRef<Path>>(path: P) -> Result<PointCloud, VolumeFileError> {
+    let path = path.as_ref();
+    let ext = path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase()).unwrap_or_default();
+    let cloud = match ext.as_str() {
+        "ply" => parse_ply(&fs::read(path)?)?,
+        "xyz" => parse_xyz(&fs::read_to_string(path)?)?,
+        _ => return Err(VolumeFileError::UnknownFormat(path.display().to_string())),
+    };
+    if cloud.is_empty() {
+        return Err(parse_error(format!("{} has no points", path.display())));
+    }
+    Ok(cloud)
+}
+
+//Loads and reconstructs with the default settings
+pub fn load_volume<P: AsRef<Path>>(path: P) -> Result<Volume, VolumeFileError> {
+    Ok(reconstruct(&load(path)?, RESOLUTION, SMOOTHING))
+}
+
+//One point per line, position then normal. Commas work as separators as well.
+pub fn parse_xyz(src: &str) -> Result<PointCloud, VolumeFileError> {
+    let mut cloud = PointCloud::new();
+    for (number, line) in src.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let values: Vec<f32> = line.split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|t| !t.is_empty())
+            .map(|t| t.parse())
+            .collect::<Result<_, _>>()
+            .map_err(|_| parse_error(format!("Line {}: invalid number", number + 1)))?;
+        if values.len() < 6 {
+            return Err(parse_error(format!("Line {}: expected a position and a normal, reconstruction needs normals", number + 1)));
+        }
+        cloud.add_point(Vector3::new(values[0], values[1], values[2]), Vector3::new(values[3], values[4], values[5]));
+    }
+    Ok(cloud)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PlyEncoding {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PlyType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl PlyType {
+    fn parse(name: &str) -> Option<PlyType> {
+        match name {
+            "char" | "int8" => Some(PlyType::I8),
+            "uchar" | "uint8" => Some(PlyType::U8),
+            "short" | "int16" => Some(PlyType::I16),
+            "ushort" | "uint16" => Some(PlyType::U16),
+            "int" | "int32" => Some(PlyType::I32),
+            "uint" | "uint32" => Some(PlyType::U32),
+            "float" | "float32" => Some(PlyType::F32),
+            "double" | "float64" => Some(PlyType::F64),
+            _ => None,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            PlyType::I8 | PlyType::U8 => 1,
+            PlyType::I16 | PlyType::U16 => 2,
+            PlyType::I32 | PlyType::U32 | PlyType::F32 => 4,
+            PlyType::F64 => 8,
+        }
+    }
+}
+
+enum PlyProperty {
+    Scalar { name: String, ty: PlyType },
+    //Faces and the like, only read to get past them
+    List { count: PlyType, item: PlyType },
+}
+
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>,
+}
+
+//Reads values one at a time, from text or binary
+struct PlyReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+    encoding: PlyEncoding,
+    tokens: std::str::SplitWhitespace<'a>,
+}
+
+impl<'a> PlyReader<'a> {
+    fn read(&mut self, ty: PlyType) -> Result<f64, VolumeFileError> {
+        if self.encoding == PlyEncoding::Ascii {
+            let token = self.tokens.next().ok_or_else(|| parse_error("Unexpected end of PLY data".to_string()))?;
+            return token.parse().map_err(|_| parse_error(format!("Invalid PLY value '{}'", token)));
+        }
+
+        let size = ty.size();
+        if self.data.len() - self.offset < size {
+            return Err(parse_error("Unexpected end of PLY data".to_string()));
+        }
+        let mut bytes = [0u8; 8];
+        bytes[..size].copy_from_slice(&self.data[self.offset..self.offset + size]);
+        self.offset += size;
+        if self.encoding == PlyEncoding::BigEndian {
+            bytes[..size].reverse();
+        }
+
+        Ok(match ty {
+            PlyType::I8 => bytes[0] as i8 as f64,
+            PlyType::U8 => bytes[0] as f64,
+            PlyType::I16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            PlyType::U16 => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            PlyType::I32 => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            PlyType::U32 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            PlyType::F32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            PlyType::F64 => f64::from_le_bytes(bytes),
+        })
+    }
+}
+
+//The vertex element with x, y, z and nx, ny, nz, in any of the three encodings.
+//Other elements get skipped, whichever order they come in.
+pub fn parse_ply(data: &[u8]) -> Result<PointCloud, VolumeFileError> {
+    const END: &[u8] = b"end_header";
+    let end = data.windows(END.len()).position(|w| w == END).ok_or_else(|| parse_error("Missing PLY header".to_string()))?;
+    //The body starts after the newline that ends the header line
+    let body = data[end..].iter().position(|b| *b == b'\n').map_or(data.len(), |i| end + i + 1);
+    let header = String::from_utf8_lossy(&data[..end]);
+
+    let mut lines = header.lines();
+    if lines.next().map(str::trim) != Some("ply") {
+        return Err(parse_error("Not a PLY file".to_string()));
+    }
+
+    let mut encoding = None;
+    let mut elements: Vec<PlyElement> = Vec::new();
+    for line in lines {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.as_slice() {
+            ["format", format, _] => {
+                encoding = Some(match *format {
+                    "ascii" => PlyEncoding::Ascii,
+                    "binary_little_endian" => PlyEncoding::LittleEndian,
+                    "binary_big_endian" => PlyEncoding::BigEndian,
+                    _ => return Err(parse_error(format!("Unknown PLY format {}", format))),
+                });
+            },
+            ["element", name, count] => {
+                let count = count.parse().map_err(|_| parse_error(format!("Invalid element count '{}'", count)))?;
+                elements.push(PlyElement { name: name.to_string(), count, properties: Vec::new() });
+            },
+            ["property", "list", count, item, _] => {
+                let property = match (PlyType::parse(count), PlyType::parse(item)) {
+                    (Some(count), Some(item)) => PlyProperty::List { count, item },
+                    _ => return Err(parse_error(format!("Unknown PLY type in '{}'", line))),
+                };
+                elements.last_mut().ok_or_else(|| parse_error("Property before any element".to_string()))?.properties.push(property);
+            },
+            ["property", ty, name] => {
+                let ty = PlyType::parse(ty).ok_or_else(|| parse_error(format!("Unknown PLY type '{}'", ty)))?;
+                elements.last_mut().ok_or_else(|| parse_error("Property before any element".to_string()))?.properties.push(PlyProperty::Scalar { name: name.to_string(), ty });
+            },
+            _ => {},
+        }
+    }
+    let encoding = encoding.ok_or_else(|| parse_error("PLY header has no format".to_string()))?;
+
+    let text = if encoding == PlyEncoding::Ascii { std::str::from_utf8(&data[body..]).map_err(|_| parse_error("PLY data isn't text".to_string()))? } else { "" };
+    let mut reader = PlyReader {
+        data: &data[body..],
+        offset: 0,
+        encoding,
+        tokens: text.split_whitespace(),
+    };
+
+    let mut cloud = PointCloud::new();
+    for element in &elements {
+        let is_vertex = element.name == "vertex";
+        let column = |wanted: &str| element.properties.iter().position(|p| matches!(p, PlyProperty::Scalar { name, .. } if name == wanted));
+        let columns = ["x", "y", "z", "nx", "ny", "nz"].iter().map(|n| column(n)).collect::<Option<Vec<usize>>>();
+        let columns = match (is_vertex, columns) {
+            (true, Some(columns)) => Some(columns),
+            (true, None) => return Err(parse_error("PLY vertices need x, y, z and nx, ny, nz, reconstruction needs normals".to_string())),
+            (false, _) => None,
+        };
+
+        let mut values = vec![0.0; element.properties.len()];
+        for _ in 0..element.count {
+            for (value, property) in values.iter_mut().zip(&element.properties) {
+                match property {
+                    PlyProperty::Scalar { ty, .. } => *value = reader.read(*ty)?,
+                    PlyProperty::List { count, item } => {
+                        let count = reader.read(*count)? as usize;
+                        for _ in 0..count {
+                            reader.read(*item)?;
+                        }
+                    },
+                }
+            }
+            if let Some(c) = &columns {
+                let v = |i: usize| values[c[i]] as f32;
+                cloud.add_point(Vector3::new(v(0), v(1), v(2)), Vector3::new(v(3), v(4), v(5)));
+            }
+        }
+    }
+
+    Ok(cloud)
+}
+
+//Point indices bucketed on a grid, as offsets into one sorted list
+struct Buckets {
+    origin: Vector3<f32>,
+    cell: f32,
+    dims: [usize; 3],
+    starts: Vec<u32>,
+    sorted: Vec<u32>,
+}
+
+impl Buckets {
+    //Cell (0, 0, 0) is centred on origin
+    fn new(positions: &[Vector3<f32>], origin: Vector3<f32>, cell: f32, dims: [usize; 3]) -> Buckets {
+        let mut buckets = Buckets {
+            origin,
+            cell,
+            dims,
+            starts: vec![0; dims[0] * dims[1] * dims[2] + 1],
+            sorted: vec![0; positions.len()],
+        };
+
+        let cells: Vec<usize> = positions.iter().map(|p| buckets.cell_of(*p)).collect();
+        for &c in &cells {
+            buckets.starts[c + 1] += 1;
+        }
+        for i in 0..buckets.starts.len() - 1 {
+            buckets.starts[i + 1] += buckets.starts[i];
+        }
+        let mut fill = buckets.starts.clone();
+        for (i, &c) in cells.iter().enumerate() {
+            buckets.sorted[fill[c] as usize] = i as u32;
+            fill[c] += 1;
+        }
+        buckets
+    }
+
+    fn coords(&self, p: Vector3<f32>) -> [usize; 3] {
+        let v = (p - self.origin) / self.cell;
+        let c = |x: f32, n: usize| (x.round().max(0.0) as usize).min(n - 1);
+        [c(v.x, self.dims[0]), c(v.y, self.dims[1]), c(v.z, self.dims[2])]
+    }
+
+    fn index(&self, c: [usize; 3]) -> usize {
+        c[0] + self.dims[0] * (c[1] + self.dims[1] * c[2])
+    }
+
+    fn cell_of(&self, p: Vector3<f32>) -> usize {
+        self.index(self.coords(p))
+    }
+
+    fn points(&self, cell: usize) -> &[u32] {
+        &self.sorted[self.starts[cell] as usize..self.starts[cell + 1] as usize]
+    }
+
+    //Points in the cell around p and its 26 neighbours, everything within one cell size is in there
+    fn around(&self, p: Vector3<f32>) -> impl Iterator<Item = u32> + '_ {
+        let c = self.coords(p);
+        let dims = self.dims;
+        let range = move |i: usize| c[i].saturating_sub(1)..(c[i] + 2).min(dims[i]);
+        range(2).flat_map(move |z| range(1).flat_map(move |y| range(0).map(move |x| self.index([x, y, z]))))
+            .flat_map(move |cell| self.points(cell).iter().copied())
+    }
+}
+
+fn bucket_dims(extent: Vector3<f32>, cell: f32) -> [usize; 3] {
+    let cells = |e: f32| (e / cell).ceil() as usize + 1;
+    [cells(extent.x), cells(extent.y), cells(extent.z)]
+}
+
+//Average distance from a point to its closest neighbour, from a few hundred of them.
+//Neighbours further than a few voxels away don't get found, sparser clouds than that count as that sparse.
+fn average_gap(cloud: &PointCloud, origin: Vector3<f32>, extent: Vector3<f32>, spacing: f32) -> f32 {
+    const SAMPLES: usize = 256;
+    let reach = 4.0 * spacing;
+    let buckets = Buckets::new(&cloud.positions, origin, reach, bucket_dims(extent, reach));
+
+    let mut sum = 0.0;
+    let mut count = 0;
+    for i in (0..cloud.len()).step_by((cloud.len() / SAMPLES).max(1)) {
+        let p = cloud.positions[i];
+        let closest = buckets.around(p)
+            .filter(|&j| j as usize != i)
+            .map(|j| (cloud.positions[j as usize] - p).magnitude())
+            .fold(reach, f32::min);
+        sum += closest;
+        count += 1;
+    }
+    if count > 0 { sum / count as f32 } else { reach }
+}
+
+//Signed distance to the oriented points on a grid around them, with cubic voxels and
+//`resolution` of them along the longest side. Close to the points it's the distance to their
+//tangent planes, averaged with a gaussian, which fills the gaps between samples and evens out
+//scanner noise. The gaussian is `smoothing` voxels or `smoothing` times the average gap between
+//the points wide, whichever is bigger. Further out it's the distance to the closest point,
+//signed by which side of its plane the voxel is on.
+//An empty cloud has no bounds to put the volume in, load rejects those.
+pub fn reconstruct(cloud: &PointCloud, resolution: usize, smoothing: f32) -> Volume {
+    assert!(!cloud.is_empty(), "Can't reconstruct a surface from an empty point cloud");
+    let (min, max) = cloud.bounds();
+    let size = max - min;
+    let longest = size.x.max(size.y).max(size.z).max(1e-6);
+    let inner = resolution.saturating_sub(2 * PADDING).max(1);
+    let spacing = longest / inner as f32;
+
+    let voxels = |s: f32| ((s / spacing).ceil() as usize).max(1) + 2 * PADDING + 1;
+    let dims = [voxels(size.x), voxels(size.y), voxels(size.z)];
+    let extent = Vector3::new(dims[0] as f32, dims[1] as f32, dims[2] as f32) * spacing;
+    let origin = (min + max) * 0.5 - (extent - Vector3::new(spacing, spacing, spacing)) * 0.5;
+    let mut volume = Volume::new(dims, origin, extent);
+
+    let voxel_buckets = Buckets::new(&cloud.positions, origin, spacing, dims);
+    let (_, nearest) = distance::distance_transform(dims, volume.spacing(), |i| !voxel_buckets.points(i).is_empty());
+
+    let sigma = smoothing.max(0.1) * spacing.max(average_gap(cloud, origin, extent, spacing));
+    let band = 2.0 * sigma;
+    let band_buckets = Buckets::new(&cloud.positions, origin, band, bucket_dims(extent, band));
+
+    for z in 0..dims[2] {
+        for y in 0..dims[1] {
+            for x in 0..dims[0] {
+                let p = volume.position(x, y, z);
+                let i = volume.index(x, y, z);
+
+                //Closest point in the closest occupied voxel, good enough away from the surface
+                let closest = nearest[i].and_then(|cell| {
+                    voxel_buckets.points(cell).iter().map(|&j| j as usize).min_by(|&a, &b| {
+                        (cloud.positions[a] - p).magnitude2().partial_cmp(&(cloud.positions[b] - p).magnitude2()).unwrap_or(std::cmp::Ordering::Equal)
+                    })
+                });
+                let far = match closest {
+                    Some(j) => {
+                        let offset = p - cloud.positions[j];
+                        let dist = offset.magnitude();
+                        if offset.dot(cloud.normals[j]) < 0.0 { -dist } else { dist }
+                    },
+                    None => continue,
+                };
+                if far.abs() >= band {
+                    volume.distances[i] = far;
+                    continue;
+                }
+
+                let mut weighted = 0.0;
+                let mut total = 0.0;
+                for j in band_buckets.around(p) {
+                    let offset = p - cloud.positions[j as usize];
+                    let w = (-offset.magnitude2() / (sigma * sigma)).exp();
+                    weighted += w * offset.dot(cloud.normals[j as usize]);
+                    total += w;
+                }
+
+                //Blend over to the point distance towards the edge of the band, where few points are left to average
+                let near = if total > 1e-12 { weighted / total } else { far };
+                let t = ((far.abs() - sigma) / (band - sigma)).clamp(0.0, 1.0);
+                let t = t * t * (3.0 - 2.0 * t);
+                volume.distances[i] = near + (far - near) * t;
+            }
+        }
+    }
+
+    volume
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xyz() {
+        let cloud = parse_xyz("# comment\n1 2 3 0 0 2\n\n4.5,5,6, 1,0,0\n").unwrap();
+        assert_eq!(cloud.positions, vec![Vector3::new(1.0, 2.0, 3.0), Vector3::new(4.5, 5.0, 6.0)]);
+        assert_eq!(cloud.normals, vec![Vector3::unit_z(), Vector3::unit_x()]);
+
+        match parse_xyz("1 2 3 0 0 1\n1 2 3\n") {
+            Err(VolumeFileError::Parse(message)) => assert!(message.starts_with("Line 2") && message.contains("normals"), "{}", message),
+            _ => panic!("points without normals should be rejected"),
+        }
+        assert!(parse_xyz("1 2 x 0 0 1\n").is_err());
+    }
+
+    const PLY_HEADER: &str = "element vertex 2\nproperty float x\nproperty float y\nproperty float z\n\
+        property uchar red\nproperty float nx\nproperty float ny\nproperty float nz\n\
+        element face 1\nproperty list uchar int vertex_indices\nend_header\n";
+
+    fn expected() -> PointCloud {
+        let mut cloud = PointCloud::new();
+        cloud.add_point(Vector3::new(1.0, 2.0, 3.0), Vector3::unit_y());
+        cloud.add_point(Vector3::new(-1.0, 0.5, 0.0), Vector3::unit_z());
+        cloud
+    }
+
+    //Both vertices, then a face the parser has to skip
+    fn binary_ply(format: &str, word: fn(f32) -> [u8; 4], index: fn(i32) -> [u8; 4]) -> Vec<u8> {
+        let mut data = format!("ply\nformat {} 1.0\n{}", format, PLY_HEADER).into_bytes();
+        for (p, n) in expected().positions.iter().zip(expected().normals.iter()) {
+            for v in &[p.x, p.y, p.z] {
+                data.extend_from_slice(&word(*v));
+            }
+            data.push(255);
+            for v in &[n.x, n.y, n.z] {
+                data.extend_from_slice(&word(*v));
+            }
+        }
+        data.push(3);
+        for i in 0..3 {
+            data.extend_from_slice(&index(i));
+        }
+        data
+    }
+
+    #[test]
+    fn ply_ascii() {
+        let src = format!("ply\nformat ascii 1.0\ncomment test\n{}1 2 3 255 0 1 0\n-1 0.5 0 0 0 0 1\n3 0 1 2\n", PLY_HEADER);
+        assert_eq!(parse_ply(src.as_bytes()).unwrap(), expected());
+    }
+
+    #[test]
+    fn ply_binary() {
+        assert_eq!(parse_ply(&binary_ply("binary_little_endian", f32::to_le_bytes, i32::to_le_bytes)).unwrap(), expected());
+        assert_eq!(parse_ply(&binary_ply("binary_big_endian", f32::to_be_bytes, i32::to_be_bytes)).unwrap(), expected());
+    }
+
+    #[test]
+    fn ply_errors() {
+        let no_normals = "ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nproperty float y\nproperty float z\nend_header\n1 2 3\n";
+        match parse_ply(no_normals.as_bytes()) {
+            Err(VolumeFileError::Parse(message)) => assert!(message.contains("normals"), "{}", message),
+            _ => panic!("points without normals should be rejected"),
+        }
+        let truncated = binary_ply("binary_little_endian", f32::to_le_bytes, i32::to_le_bytes);
+        assert!(parse_ply(&truncated[..truncated.len() - 2]).is_err());
+        assert!(parse_ply(b"ply\nformat ascii 1.0\n").is_err());
+        assert!(parse_ply(b"obj\nend_header\n").is_err());
+    }
+
+    //Evenly spread over a sphere, with a Fibonacci spiral
+    fn sphere(radius: f32, count: usize) -> PointCloud {
+        let mut cloud = PointCloud::new();
+        let golden = std::f32::consts::PI * (3.0 - 5.0f32.sqrt());
+        for i in 0..count {
+            let y = 1.0 - 2.0 * (i as f32 + 0.5) / count as f32;
+            let r = (1.0 - y * y).sqrt();
+            let angle = golden * i as f32;
+            let n = Vector3::new(r * angle.cos(), y, r * angle.sin());
+            cloud.add_point(n * radius, n);
+        }
+        cloud
+    }
+
+    #[test]
+    fn reconstructed_sphere() {
+        let radius = 10.0;
+        let volume = reconstruct(&sphere(radius, 2000), 48, SMOOTHING);
+        let voxel = volume.spacing().x;
+        assert!(volume.sample(Vector3::zero()) < 0.0);
+
+        //The zero crossing along every direction is within a voxel of the radius
+        let directions = sphere(1.0, 100);
+        for d in &directions.normals {
+            assert!(volume.sample(d * (radius - voxel)) < 0.0, "inside at {:?}", d);
+            assert!(volume.sample(d * (radius + voxel)) > 0.0, "outside at {:?}", d);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn empty_cloud_is_rejected() {
+        reconstruct(&PointCloud::new(), 48, SMOOTHING);
+    }
+}
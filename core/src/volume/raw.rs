@@ -0,0 +1,201 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use cgmath::*;
+use serde::{Serialize, Deserialize};
+
+use super::Volume;
+use super::file::VolumeFileError;
+
+//Raw volume format: the data file holds all distances as little endian f32,
+//followed by all material ids as little endian u32, x fastest, then y, then z.
+//A small JSON header with the same name next to it describes the layout and where the volume sits,
+//so the data can be pulled into other tools without knowing anything about this one.
+
+pub const FORMAT_NAME: &str = "sdf_preview volume";
+pub const CURRENT_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Channel {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub data_type: String,
+    //In bytes, from the start of the data file
+    pub offset: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RawHeader {
+    pub format: String,
+    pub version: u32,
+    pub size: [usize; 3],
+    pub origin: [f32; 3],
+    pub extent: [f32; 3],
+    pub endianness: String,
+    pub layout: String,
+    pub channels: Vec<Channel>,
+    //File name of the data, relative to the header
+    pub data: String,
+}
+
+//The header goes next to the data, `scene.raw` gets `scene.json`
+pub fn header_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    path.as_ref().with_extension("json")
+}
+
+pub fn header(volume: &Volume, data_file: &str) -> RawHeader {
+    let count = (volume.size[0] * volume.size[1] * volume.size[2]) as u64;
+    RawHeader {
+        format: FORMAT_NAME.to_string(),
+        version: CURRENT_VERSION,
+        size: volume.size,
+        origin: volume.origin.into(),
+        extent: volume.extent.into(),
+        endianness: "little".to_string(),
+        layout: "x fastest, then y, then z".to_string(),
+        channels: vec![
+            Channel { name: "distance".to_string(), data_type: "f32".to_string(), offset: 0 },
+            Channel { name: "material".to_string(), data_type: "u32".to_string(), offset: count * 4 },
+        ],
+        data: data_file.to_string(),
+    }
+}
+
+//Writes `path` (.raw or .vol) and the JSON header next to it.
+//Given the header path instead, the data goes into a .raw file next to it.
+pub fn save<P: AsRef<Path>>(volume: &Volume, path: P) -> Result<(), VolumeFileError> {
+    let path = path.as_ref();
+    let is_header = path.extension().and_then(|e| e.to_str()).is_some_and(|e| e.eq_ignore_ascii_case("json"));
+    let path = if is_header { path.with_extension("raw") } else { path.to_path_buf() };
+    let data_file = path.file_name().and_then(|n| n.to_str()).unwrap_or("volume.raw");
+
+    let header = header(volume, data_file);
+    let json = serde_json::to_string_pretty(&header).map_err(|e| VolumeFileError::Serialize(e.to_string()))?;
+    fs::write(header_path(&path), json + "\n")?;
+
+    let mut data = Vec::with_capacity(volume.distances.len() * 8);
+    for d in &volume.distances {
+        data.extend_from_slice(&d.to_le_bytes());
+    }
+    for m in &volume.materials {
+        data.extend_from_slice(&m.to_le_bytes());
+    }
+    fs::write(&path, data)?;
+
+    Ok(())
+}
+
+//Takes the path of either the header or the data file
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Volume, VolumeFileError> {
+    let header_path = header_path(path.as_ref());
+    let header: RawHeader = serde_json::from_str(&fs::read_to_string(&header_path)?).map_err(|e| VolumeFileError::Parse(e.to_string()))?;
+    if header.format != FORMAT_NAME {
+        return Err(VolumeFileError::Parse(format!("unknown format '{}'", header.format)));
+    }
+    if header.version > CURRENT_VERSION {
+        return Err(VolumeFileError::Parse(format!("version {} is newer than the supported version {}", header.version, CURRENT_VERSION)));
+    }
+
+    //Only a file next to the header, a header shouldn't be able to point anywhere else
+    let mut components = Path::new(&header.data).components();
+    if !matches!((components.next(), components.next()), (Some(Component::Normal(_)), None)) {
+        return Err(VolumeFileError::Parse(format!("data file '{}' isn't a plain file name", header.data)));
+    }
+    let data_path = header_path.with_file_name(&header.data);
+    let data = fs::read(data_path)?;
+
+    //Empty volumes break sampling, and oversized ones can't have all their data in the file
+    if header.size.contains(&0) {
+        return Err(VolumeFileError::Parse(format!("invalid size {:?}", header.size)));
+    }
+    let count = header.size.iter().try_fold(1usize, |count, &s| count.checked_mul(s))
+        .ok_or_else(|| VolumeFileError::Parse(format!("size {:?} is too large", header.size)))?;
+    let channel_offset = |name: &str| header.channels.iter().find(|c| c.name == name).map(|c| c.offset);
+    let distance_offset = channel_offset("distance").ok_or_else(|| VolumeFileError::Parse("missing distance channel".to_string()))?;
+    let material_offset = channel_offset("material");
+
+    let expected = (count as u64).checked_mul(4)
+        .and_then(|bytes| distance_offset.max(material_offset.unwrap_or(0)).checked_add(bytes))
+        .ok_or_else(|| VolumeFileError::Parse("channels are too large".to_string()))?;
+    if (data.len() as u64) < expected {
+        return Err(VolumeFileError::Size { expected, found: data.len() as u64 });
+    }
+    //Both fit in the data, so they fit in a usize
+    let distance_offset = distance_offset as usize;
+    let material_offset = material_offset.map(|o| o as usize);
+
+    let mut volume = Volume::new(header.size, Vector3::from(header.origin), Vector3::from(header.extent));
+    for i in 0..count {
+        let o = distance_offset + i * 4;
+        volume.distances[i] = f32::from_le_bytes([data[o], data[o + 1], data[o + 2], data[o + 3]]);
+    }
+    if let Some(offset) = material_offset {
+        for i in 0..count {
+            let o = offset + i * 4;
+            volume.materials[i] = u32::from_le_bytes([data[o], data[o + 1], data[o + 2], data[o + 3]]);
+        }
+    }
+
+    Ok(volume)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::eval::Sample;
+
+    fn test_volume() -> Volume {
+        Volume::from_fn([6, 5, 4], Vector3::new(-1.0, 2.0, 0.5), Vector3::new(3.0, 2.5, 2.0), |p| {
+            Sample::new(p.magnitude() - 2.0, (p.x > 0.0) as u32 + 1)
+        })
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn saving_to_the_header_writes_a_raw_file() {
+        let dir = temp_dir("sdf_preview_raw_header");
+        let volume = test_volume();
+        save(&volume, dir.join("volume.json")).unwrap();
+
+        assert!(dir.join("volume.raw").exists());
+        let header: RawHeader = serde_json::from_str(&fs::read_to_string(dir.join("volume.json")).unwrap()).unwrap();
+        assert_eq!(header.data, "volume.raw");
+        assert_eq!(load(dir.join("volume.json")).unwrap(), volume);
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = temp_dir("sdf_preview_raw_round_trip");
+        let volume = test_volume();
+        save(&volume, dir.join("volume.vol")).unwrap();
+        assert_eq!(load(dir.join("volume.vol")).unwrap(), volume);
+        assert_eq!(load(dir.join("volume.json")).unwrap(), volume);
+    }
+
+    //Saves a valid volume, then breaks its header
+    fn load_with_header<F: Fn(&mut RawHeader)>(name: &str, edit: F) -> Result<Volume, VolumeFileError> {
+        let dir = temp_dir(name);
+        save(&test_volume(), dir.join("volume.raw")).unwrap();
+        let mut header: RawHeader = serde_json::from_str(&fs::read_to_string(dir.join("volume.json")).unwrap()).unwrap();
+        edit(&mut header);
+        fs::write(dir.join("volume.json"), serde_json::to_string(&header).unwrap()).unwrap();
+        load(dir.join("volume.raw"))
+    }
+
+    #[test]
+    fn invalid_headers_are_rejected() {
+        let parse_error = |result: Result<Volume, VolumeFileError>| matches!(result, Err(VolumeFileError::Parse(_)));
+        assert!(parse_error(load_with_header("sdf_preview_raw_zero", |h| h.size = [6, 0, 4])));
+        assert!(parse_error(load_with_header("sdf_preview_raw_overflow", |h| h.size = [usize::MAX, 2, 1])));
+        assert!(parse_error(load_with_header("sdf_preview_raw_offset", |h| h.channels[1].offset = u64::MAX)));
+        assert!(parse_error(load_with_header("sdf_preview_raw_parent", |h| h.data = "../volume.raw".to_string())));
+        assert!(parse_error(load_with_header("sdf_preview_raw_absolute", |h| h.data = "/tmp/volume.raw".to_string())));
+        assert!(matches!(load_with_header("sdf_preview_raw_short", |h| h.size = [6, 5, 5]), Err(VolumeFileError::Size { .. })));
+    }
+}
@@ -0,0 +1,69 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use cgmath::*;
+
+use super::Volume;
+use super::file::VolumeFileError;
+
+//The text format written by SDFGen (and a bunch of tools copying it):
+//  ni nj nk
+//  origin_x origin_y origin_z
+//  dx
+//followed by ni * nj * nk distances, i fastest, then j, then k.
+//Sample (i, j, k) sits at origin + (i, j, k) * dx, which is the same layout Volume uses.
+//There are no materials, everything gets material 0.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Volume, VolumeFileError> {
+    parse(&fs::read_to_string(path)?)
+}
+
+pub fn parse(src: &str) -> Result<Volume, VolumeFileError> {
+    let mut tokens = src.split_whitespace();
+    let mut next = |what: &str| tokens.next().ok_or_else(|| VolumeFileError::Parse(format!("unexpected end of file, expected {}", what)));
+
+    let mut size = [0; 3];
+    for s in size.iter_mut() {
+        let token = next("the grid size")?;
+        *s = token.parse::<usize>().map_err(|_| VolumeFileError::Parse(format!("invalid grid size '{}'", token)))?;
+    }
+    if size.contains(&0) {
+        return Err(VolumeFileError::Parse("grid size can't be 0".to_string()));
+    }
+
+    let mut float = |what: &str| -> Result<f32, VolumeFileError> {
+        let token = next(what)?;
+        token.parse::<f32>().map_err(|_| VolumeFileError::Parse(format!("invalid {} '{}'", what, token)))
+    };
+    let origin = Vector3::new(float("origin")?, float("origin")?, float("origin")?);
+    let dx = float("spacing")?;
+    if dx <= 0.0 {
+        return Err(VolumeFileError::Parse(format!("spacing has to be positive, got {}", dx)));
+    }
+
+    let extent = Vector3::new(size[0] as f32, size[1] as f32, size[2] as f32) * dx;
+    let mut volume = Volume::new(size, origin, extent);
+    for d in volume.distances.iter_mut() {
+        *d = float("distance")?;
+    }
+
+    Ok(volume)
+}
+
+//Only works for cubic voxels, which is all the format can describe
+pub fn save<P: AsRef<Path>>(volume: &Volume, path: P) -> Result<(), VolumeFileError> {
+    let spacing = volume.spacing();
+    if (spacing.x - spacing.y).abs() > 1e-6 || (spacing.x - spacing.z).abs() > 1e-6 {
+        return Err(VolumeFileError::Serialize(format!("SDFGen needs cubic voxels, these are {:?}", spacing)));
+    }
+
+    let mut w = BufWriter::new(File::create(path)?);
+    writeln!(w, "{} {} {}", volume.size[0], volume.size[1], volume.size[2])?;
+    writeln!(w, "{} {} {}", volume.origin.x, volume.origin.y, volume.origin.z)?;
+    writeln!(w, "{}", spacing.x)?;
+    for d in &volume.distances {
+        writeln!(w, "{}", d)?;
+    }
+    w.flush()?;
+    Ok(())
+}
@@ -0,0 +1,830 @@
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use cgmath::*;
+
+use super::Volume;
+use super::file::VolumeFileError;
+
+//OpenVDB level sets, only float grids with the standard 5-4-3 tree.
+//Reading handles uncompressed and zip compressed grids, blosc needs the actual library.
+//Writing produces an uncompressed, active mask compressed narrow band level set.
+//
+//The tree is root -> 32^3 internal nodes -> 16^3 internal nodes -> 8^3 leaves,
+//so an upper internal node covers 4096^3 voxels and a lower one 128^3.
+//Inside a node z is the fastest changing axis, unlike Volume where it's x.
+
+const MAGIC: i64 = 0x5644_4220;
+//The oldest version this reads, it's where the format settled into its current shape
+const FILE_VERSION_NODE_MASK_COMPRESSION: u32 = 222;
+const FILE_VERSION: u32 = 224;
+const LIBRARY_VERSION: (u32, u32) = (8, 1);
+
+const COMPRESS_ZIP: u32 = 0x1;
+const COMPRESS_ACTIVE_MASK: u32 = 0x2;
+const COMPRESS_BLOSC: u32 = 0x4;
+
+//Says what follows the metadata byte in front of every value buffer
+const NO_MASK_OR_INACTIVE_VALS: u8 = 0;
+const NO_MASK_AND_MINUS_BG: u8 = 1;
+const NO_MASK_AND_ONE_INACTIVE_VAL: u8 = 2;
+const MASK_AND_NO_INACTIVE_VALS: u8 = 3;
+const MASK_AND_ONE_INACTIVE_VAL: u8 = 4;
+const MASK_AND_TWO_INACTIVE_VALS: u8 = 5;
+const NO_MASK_AND_ALL_VALS: u8 = 6;
+
+const GRID_TYPE: &str = "Tree_float_5_4_3";
+const HALF_FLOAT_SUFFIX: &str = "_HalfFloat";
+
+const LEAF_LOG2: u32 = 3;
+const LOWER_LOG2: u32 = 4;
+const UPPER_LOG2: u32 = 5;
+//Width of each node in voxels, as a power of two
+const LOWER_TOTAL: u32 = LEAF_LOG2 + LOWER_LOG2;
+const UPPER_TOTAL: u32 = LOWER_TOTAL + UPPER_LOG2;
+
+//Narrow band half width in voxels, the OpenVDB default
+pub const HALF_WIDTH: f32 = 3.0;
+
+#[derive(Clone, Debug)]
+struct Mask {
+    words: Vec<u64>,
+}
+
+impl Mask {
+    fn new(log2: u32) -> Mask {
+        Mask {
+            words: vec![0; (1 << (3 * log2)) / 64],
+        }
+    }
+
+    fn is_on(&self, i: usize) -> bool {
+        self.words[i >> 6] & (1 << (i & 63)) != 0
+    }
+
+    fn set(&mut self, i: usize) {
+        self.words[i >> 6] |= 1 << (i & 63);
+    }
+
+    fn count_on(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    fn any(&self) -> bool {
+        self.words.iter().any(|w| *w != 0)
+    }
+}
+
+//Origin of the n-th child or tile of a node
+fn child_origin(origin: [i32; 3], n: usize, log2: u32, child_total: u32) -> [i32; 3] {
+    let dim = (1 << log2) - 1;
+    let local = [(n >> (2 * log2)) as i32, ((n >> log2) & dim) as i32, (n & dim) as i32];
+    [origin[0] + (local[0] << child_total), origin[1] + (local[1] << child_total), origin[2] + (local[2] << child_total)]
+}
+
+//Constant regions and leaves, which is all that's needed to fill a volume
+struct Tree {
+    background: f32,
+    //Origin, width and value
+    tiles: Vec<([i32; 3], i32, f32)>,
+    //Origin and 512 values in VDB order
+    leaves: Vec<([i32; 3], Vec<f32>)>,
+}
+
+//Where index space sits in the world, only axis aligned scales and translations are supported
+#[derive(Clone, Copy, Debug)]
+struct IndexTransform {
+    scale: Vector3<f64>,
+    translation: Vector3<f64>,
+}
+
+//Name, type name and the raw value
+type MetaEntry<'a> = (String, String, &'a [u8]);
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    compression: u32,
+    half_float: bool,
+    background: f32,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], VolumeFileError> {
+        if self.pos + len > self.data.len() {
+            return Err(VolumeFileError::Parse("unexpected end of file".to_string()));
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, VolumeFileError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, VolumeFileError> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn i32(&mut self) -> Result<i32, VolumeFileError> {
+        Ok(self.u32()? as i32)
+    }
+
+    fn i64(&mut self) -> Result<i64, VolumeFileError> {
+        let b = self.bytes(8)?;
+        Ok(i64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+    }
+
+    fn f32(&mut self) -> Result<f32, VolumeFileError> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    fn f64(&mut self) -> Result<f64, VolumeFileError> {
+        Ok(f64::from_bits(self.i64()? as u64))
+    }
+
+    fn vec3d(&mut self) -> Result<Vector3<f64>, VolumeFileError> {
+        Ok(Vector3::new(self.f64()?, self.f64()?, self.f64()?))
+    }
+
+    fn coord(&mut self) -> Result<[i32; 3], VolumeFileError> {
+        Ok([self.i32()?, self.i32()?, self.i32()?])
+    }
+
+    fn string(&mut self) -> Result<String, VolumeFileError> {
+        let len = self.u32()? as usize;
+        Ok(String::from_utf8_lossy(self.bytes(len)?).into_owned())
+    }
+
+    fn mask(&mut self, log2: u32) -> Result<Mask, VolumeFileError> {
+        let mut mask = Mask::new(log2);
+        for word in mask.words.iter_mut() {
+            *word = self.i64()? as u64;
+        }
+        Ok(mask)
+    }
+
+    fn seek(&mut self, pos: i64) -> Result<(), VolumeFileError> {
+        if pos < 0 || pos as usize > self.data.len() {
+            return Err(VolumeFileError::Parse(format!("offset {} is outside the file", pos)));
+        }
+        self.pos = pos as usize;
+        Ok(())
+    }
+
+    //Only the class is interesting, everything else gets skipped by its size
+    fn metadata(&mut self) -> Result<Vec<MetaEntry<'a>>, VolumeFileError> {
+        let count = self.u32()?;
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let name = self.string()?;
+            let type_name = self.string()?;
+            let size = self.u32()? as usize;
+            entries.push((name, type_name, self.bytes(size)?));
+        }
+        Ok(entries)
+    }
+
+    fn transform(&mut self) -> Result<IndexTransform, VolumeFileError> {
+        let map = self.string()?;
+        let (scale, translation) = match map.as_str() {
+            "UniformScaleMap" | "ScaleMap" => {
+                let scale = self.vec3d()?;
+                //Voxel size and a few cached inverses
+                for _ in 0..4 {
+                    self.vec3d()?;
+                }
+                (scale, Vector3::zero())
+            },
+            "UniformScaleTranslateMap" | "ScaleTranslateMap" => {
+                let translation = self.vec3d()?;
+                let scale = self.vec3d()?;
+                for _ in 0..4 {
+                    self.vec3d()?;
+                }
+                (scale, translation)
+            },
+            "TranslationMap" => (Vector3::new(1.0, 1.0, 1.0), self.vec3d()?),
+            //Row major, with the translation in the last row
+            "AffineMap" => {
+                let mut m = [0.0; 16];
+                for v in m.iter_mut() {
+                    *v = self.f64()?;
+                }
+                let off_diagonal = [m[1], m[2], m[4], m[6], m[8], m[9]];
+                if off_diagonal.iter().any(|v| v.abs() > 1e-9) {
+                    return Err(VolumeFileError::Parse("rotated or sheared grids aren't supported".to_string()));
+                }
+                (Vector3::new(m[0], m[5], m[10]), Vector3::new(m[12], m[13], m[14]))
+            },
+            _ => return Err(VolumeFileError::Parse(format!("unsupported transform {}", map))),
+        };
+        Ok(IndexTransform { scale, translation })
+    }
+
+    //readCompressedValues in OpenVDB, fills in inactive values that weren't stored
+    fn values(&mut self, count: usize, value_mask: &Mask, log2: u32) -> Result<Vec<f32>, VolumeFileError> {
+        let mask_compressed = self.compression & COMPRESS_ACTIVE_MASK != 0;
+        let metadata = self.u8()?;
+
+        let mut inactive0 = if metadata == NO_MASK_OR_INACTIVE_VALS { self.background } else { -self.background };
+        let mut inactive1 = self.background;
+        if metadata == NO_MASK_AND_ONE_INACTIVE_VAL || metadata == MASK_AND_ONE_INACTIVE_VAL || metadata == MASK_AND_TWO_INACTIVE_VALS {
+            inactive0 = self.f32()?;
+            if metadata == MASK_AND_TWO_INACTIVE_VALS {
+                inactive1 = self.f32()?;
+            }
+        }
+        let selection = if metadata == MASK_AND_NO_INACTIVE_VALS || metadata == MASK_AND_ONE_INACTIVE_VAL || metadata == MASK_AND_TWO_INACTIVE_VALS {
+            Some(self.mask(log2)?)
+        } else {
+            None
+        };
+
+        let stored = if mask_compressed && metadata != NO_MASK_AND_ALL_VALS { value_mask.count_on() } else { count };
+        let data = self.data(stored)?;
+        if stored == count {
+            return Ok(data);
+        }
+
+        let mut values = Vec::with_capacity(count);
+        let mut stored = data.into_iter();
+        for i in 0..count {
+            if value_mask.is_on(i) {
+                values.push(stored.next().unwrap_or(self.background));
+            } else if matches!(&selection, Some(s) if s.is_on(i)) {
+                values.push(inactive1);
+            } else {
+                values.push(inactive0);
+            }
+        }
+        Ok(values)
+    }
+
+    fn data(&mut self, count: usize) -> Result<Vec<f32>, VolumeFileError> {
+        let value_size = if self.half_float { 2 } else { 4 };
+        let len = count * value_size;
+
+        let bytes = if self.compression & COMPRESS_BLOSC != 0 {
+            return Err(VolumeFileError::Parse("blosc compressed grids aren't supported, save it with zip or no compression".to_string()));
+        } else if self.compression & COMPRESS_ZIP != 0 {
+            //A negative size means the data didn't compress and is stored as is
+            let zipped = self.i64()?;
+            if zipped <= 0 {
+                let size = zipped.checked_neg().ok_or_else(|| VolumeFileError::Parse(format!("invalid compressed size {}", zipped)))?;
+                self.bytes(size as usize)?.to_vec()
+            } else {
+                let mut bytes = Vec::with_capacity(len);
+                flate2::read::ZlibDecoder::new(self.bytes(zipped as usize)?).read_to_end(&mut bytes)?;
+                bytes
+            }
+        } else {
+            self.bytes(len)?.to_vec()
+        };
+        if bytes.len() != len {
+            return Err(VolumeFileError::Size { expected: len as u64, found: bytes.len() as u64 });
+        }
+
+        Ok(if self.half_float {
+            bytes.chunks_exact(2).map(|b| half_to_f32(u16::from_le_bytes([b[0], b[1]]))).collect()
+        } else {
+            bytes.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect()
+        })
+    }
+}
+
+fn half_to_f32(h: u16) -> f32 {
+    let sign = ((h >> 15) as u32) << 31;
+    let exponent = ((h >> 10) & 0x1f) as u32;
+    let mantissa = (h & 0x3ff) as u32;
+    let bits = match exponent {
+        0 if mantissa == 0 => sign,
+        //Subnormal, which is always a normal number as f32
+        0 => {
+            let v = mantissa as f32 * (2.0f32).powi(-24);
+            return if sign != 0 { -v } else { v };
+        },
+        0x1f => sign | 0x7f80_0000 | (mantissa << 13),
+        _ => sign | ((exponent + 127 - 15) << 23) | (mantissa << 13),
+    };
+    f32::from_bits(bits)
+}
+
+//Topology first, which is every node's masks and tile values, then the leaf values in the same order
+fn read_tree(r: &mut Reader) -> Result<Tree, VolumeFileError> {
+    let buffer_count = r.i32()?;
+    if buffer_count != 1 {
+        return Err(VolumeFileError::Parse(format!("trees with {} buffers aren't supported", buffer_count)));
+    }
+
+    let background = r.f32()?;
+    r.background = background;
+    let mut tree = Tree {
+        background,
+        tiles: Vec::new(),
+        leaves: Vec::new(),
+    };
+
+    let tile_count = r.u32()?;
+    let child_count = r.u32()?;
+    for _ in 0..tile_count {
+        let origin = r.coord()?;
+        let value = r.f32()?;
+        let _active = r.u8()?;
+        tree.tiles.push((origin, 1 << UPPER_TOTAL, value));
+    }
+
+    let mut leaf_masks = Vec::new();
+    for _ in 0..child_count {
+        let origin = r.coord()?;
+        read_internal(r, &mut tree, &mut leaf_masks, origin, UPPER_LOG2, LOWER_TOTAL)?;
+    }
+
+    for (origin, topology_mask) in leaf_masks {
+        let value_mask = r.mask(LEAF_LOG2)?;
+        if value_mask.words != topology_mask.words {
+            return Err(VolumeFileError::Parse("leaf masks don't match the topology".to_string()));
+        }
+        let values = r.values(1 << (3 * LEAF_LOG2), &value_mask, LEAF_LOG2)?;
+        tree.leaves.push((origin, values));
+    }
+
+    Ok(tree)
+}
+
+fn read_internal(r: &mut Reader, tree: &mut Tree, leaf_masks: &mut Vec<([i32; 3], Mask)>, origin: [i32; 3], log2: u32, child_total: u32) -> Result<(), VolumeFileError> {
+    let child_mask = r.mask(log2)?;
+    let value_mask = r.mask(log2)?;
+    let count = 1 << (3 * log2);
+    let values = r.values(count, &value_mask, log2)?;
+
+    for (n, value) in values.iter().enumerate() {
+        if !child_mask.is_on(n) {
+            tree.tiles.push((child_origin(origin, n, log2, child_total), 1 << child_total, *value));
+        }
+    }
+
+    for n in (0..count).filter(|n| child_mask.is_on(*n)) {
+        let child = child_origin(origin, n, log2, child_total);
+        if log2 == UPPER_LOG2 {
+            read_internal(r, tree, leaf_masks, child, LOWER_LOG2, LEAF_LOG2)?;
+        } else {
+            leaf_masks.push((child, r.mask(LEAF_LOG2)?));
+        }
+    }
+
+    Ok(())
+}
+
+//Reads the first float grid in the file into a volume just covering its leaves.
+//Voxels the file doesn't store get the background value, with the sign of the tile they're in
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Volume, VolumeFileError> {
+    parse(&fs::read(path)?)
+}
+
+pub fn parse(data: &[u8]) -> Result<Volume, VolumeFileError> {
+    let mut r = Reader {
+        data,
+        pos: 0,
+        compression: 0,
+        half_float: false,
+        background: 0.0,
+    };
+
+    if r.i64()? != MAGIC {
+        return Err(VolumeFileError::Parse("not a VDB file".to_string()));
+    }
+    let version = r.u32()?;
+    if version < FILE_VERSION_NODE_MASK_COMPRESSION {
+        return Err(VolumeFileError::Parse(format!("VDB file version {} is too old, only {} and newer are supported", version, FILE_VERSION_NODE_MASK_COMPRESSION)));
+    }
+    let _library_major = r.u32()?;
+    let _library_minor = r.u32()?;
+    if r.u8()? == 0 {
+        return Err(VolumeFileError::Parse("VDB files without grid offsets aren't supported".to_string()));
+    }
+    let _uuid = r.bytes(36)?;
+    r.metadata()?;
+
+    let grid_count = r.i32()?;
+    for _ in 0..grid_count {
+        let name = r.string()?;
+        let grid_type = r.string()?;
+        let instance_parent = r.string()?;
+        let grid_pos = r.i64()?;
+        let _block_pos = r.i64()?;
+        let end_pos = r.i64()?;
+
+        let half_float = grid_type.ends_with(HALF_FLOAT_SUFFIX);
+        let base_type = grid_type.trim_end_matches(HALF_FLOAT_SUFFIX);
+        if base_type != GRID_TYPE || !instance_parent.is_empty() {
+            info!("Skipping VDB grid '{}' of type {}", name, grid_type);
+            r.seek(end_pos)?;
+            continue;
+        }
+
+        r.seek(grid_pos)?;
+        r.compression = r.u32()?;
+        r.half_float = half_float;
+        for (key, _, value) in r.metadata()? {
+            if key == "class" && value != b"level set" {
+                warn!("VDB grid '{}' is a {}, not a level set", name, String::from_utf8_lossy(value));
+            }
+        }
+        let transform = r.transform()?;
+        let tree = read_tree(&mut r)?;
+        return to_volume(&tree, transform);
+    }
+
+    Err(VolumeFileError::Parse("no float grid in the file".to_string()))
+}
+
+fn to_volume(tree: &Tree, transform: IndexTransform) -> Result<Volume, VolumeFileError> {
+    if tree.leaves.is_empty() {
+        return Err(VolumeFileError::Parse("the grid has no leaves".to_string()));
+    }
+
+    //One voxel of padding so the surface never touches the edge
+    let leaf_dim = 1 << LEAF_LOG2;
+    let mut min = [i32::MAX; 3];
+    let mut max = [i32::MIN; 3];
+    for (origin, _) in &tree.leaves {
+        for i in 0..3 {
+            min[i] = min[i].min(origin[i] - 1);
+            max[i] = max[i].max(origin[i] + leaf_dim + 1);
+        }
+    }
+    let size = [(max[0] - min[0]) as usize, (max[1] - min[1]) as usize, (max[2] - min[2]) as usize];
+
+    let scale = transform.scale.cast::<f32>().unwrap();
+    let origin = Vector3::new(min[0] as f32, min[1] as f32, min[2] as f32).mul_element_wise(scale) + transform.translation.cast::<f32>().unwrap();
+    let extent = Vector3::new(size[0] as f32, size[1] as f32, size[2] as f32).mul_element_wise(scale);
+    let mut volume = Volume::new(size, origin, extent);
+    for d in volume.distances.iter_mut() {
+        *d = tree.background;
+    }
+
+    //Tiles and leaves never overlap, so the order doesn't matter
+    for (tile_origin, width, value) in &tree.tiles {
+        let lo: Vec<i32> = (0..3).map(|i| tile_origin[i].max(min[i])).collect();
+        let hi: Vec<i32> = (0..3).map(|i| (tile_origin[i] + width).min(max[i])).collect();
+        for z in lo[2]..hi[2].max(lo[2]) {
+            for y in lo[1]..hi[1].max(lo[1]) {
+                for x in lo[0]..hi[0].max(lo[0]) {
+                    let i = volume.index((x - min[0]) as usize, (y - min[1]) as usize, (z - min[2]) as usize);
+                    volume.distances[i] = *value;
+                }
+            }
+        }
+    }
+
+    for (leaf_origin, values) in &tree.leaves {
+        for (n, value) in values.iter().enumerate() {
+            let p = child_origin(*leaf_origin, n, LEAF_LOG2, 0);
+            let i = volume.index((p[0] - min[0]) as usize, (p[1] - min[1]) as usize, (p[2] - min[2]) as usize);
+            volume.distances[i] = *value;
+        }
+    }
+
+    Ok(volume)
+}
+
+struct Writer {
+    data: Vec<u8>,
+    background: f32,
+}
+
+impl Writer {
+    fn u8(&mut self, v: u8) {
+        self.data.push(v);
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.data.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn i32(&mut self, v: i32) {
+        self.data.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn i64(&mut self, v: i64) {
+        self.data.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn f32(&mut self, v: f32) {
+        self.data.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn vec3d(&mut self, v: Vector3<f64>) {
+        for c in &[v.x, v.y, v.z] {
+            self.data.extend_from_slice(&c.to_le_bytes());
+        }
+    }
+
+    fn coord(&mut self, c: [i32; 3]) {
+        for v in &c {
+            self.i32(*v);
+        }
+    }
+
+    fn string(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.data.extend_from_slice(s.as_bytes());
+    }
+
+    fn metadata(&mut self, name: &str, type_name: &str, value: &[u8]) {
+        self.string(name);
+        self.string(type_name);
+        self.u32(value.len() as u32);
+        self.data.extend_from_slice(value);
+    }
+
+    fn mask(&mut self, mask: &Mask) {
+        for word in &mask.words {
+            self.i64(*word as i64);
+        }
+    }
+
+    //Only stores the active values. Inactive ones in a level set are plus or minus the background,
+    //which a selection mask can tell apart. Slots holding a child don't matter.
+    fn values(&mut self, values: &[f32], value_mask: &Mask, child_mask: Option<&Mask>, log2: u32) {
+        let bg = self.background;
+        let inactive = (0..values.len()).filter(|i| !value_mask.is_on(*i) && !matches!(child_mask, Some(m) if m.is_on(*i)));
+
+        let mut selection = Mask::new(log2);
+        let mut all_bg = true;
+        let mut all_minus_bg = true;
+        let mut all_either = true;
+        for i in inactive {
+            let v = values[i];
+            all_bg &= v == bg;
+            all_minus_bg &= v == -bg;
+            all_either &= v == bg || v == -bg;
+            if v == bg {
+                selection.set(i);
+            }
+        }
+
+        let metadata = if all_bg {
+            NO_MASK_OR_INACTIVE_VALS
+        } else if all_minus_bg {
+            NO_MASK_AND_MINUS_BG
+        } else if all_either {
+            MASK_AND_NO_INACTIVE_VALS
+        } else {
+            NO_MASK_AND_ALL_VALS
+        };
+        self.u8(metadata);
+        if metadata == MASK_AND_NO_INACTIVE_VALS {
+            self.mask(&selection);
+        }
+
+        for (i, v) in values.iter().enumerate() {
+            if metadata == NO_MASK_AND_ALL_VALS || value_mask.is_on(i) {
+                self.f32(*v);
+            }
+        }
+    }
+}
+
+//The narrow band of the volume, as a tree. Index space is the volume's voxel grid.
+struct Band<'a> {
+    volume: &'a Volume,
+    background: f32,
+}
+
+impl<'a> Band<'a> {
+    //Anything outside the volume counts as outside the surface
+    fn value(&self, p: [i32; 3]) -> f32 {
+        let inside = (0..3).all(|i| p[i] >= 0 && (p[i] as usize) < self.volume.size[i]);
+        if !inside {
+            return self.background;
+        }
+        let d = self.volume.get(p[0] as usize, p[1] as usize, p[2] as usize);
+        d.max(-self.background).min(self.background)
+    }
+
+    fn overlaps(&self, origin: [i32; 3], width: i32) -> bool {
+        (0..3).all(|i| origin[i] + width > 0 && origin[i] < self.volume.size[i] as i32)
+    }
+
+    fn leaf(&self, origin: [i32; 3]) -> (Vec<f32>, Mask) {
+        let mut values = Vec::with_capacity(1 << (3 * LEAF_LOG2));
+        let mut mask = Mask::new(LEAF_LOG2);
+        for n in 0..1 << (3 * LEAF_LOG2) {
+            let v = self.value(child_origin(origin, n, LEAF_LOG2, 0));
+            if v.abs() < self.background {
+                mask.set(n);
+            }
+            values.push(v);
+        }
+        (values, mask)
+    }
+
+    //Child and value masks and tile values of an internal node
+    fn internal(&self, origin: [i32; 3], log2: u32, child_total: u32) -> (Mask, Vec<f32>, Vec<[i32; 3]>) {
+        let mut child_mask = Mask::new(log2);
+        let mut values = vec![0.0; 1 << (3 * log2)];
+        let mut children = Vec::new();
+        for (n, value) in values.iter_mut().enumerate() {
+            let child = child_origin(origin, n, log2, child_total);
+            let has_child = if !self.overlaps(child, 1 << child_total) {
+                false
+            } else if log2 == LOWER_LOG2 {
+                self.leaf(child).1.any()
+            } else {
+                true
+            };
+
+            if has_child {
+                child_mask.set(n);
+                children.push(child);
+            } else {
+                //Inactive tile, its sign decides whether this region is inside or outside
+                let sample = [
+                    child[0].max(0).min(self.volume.size[0] as i32 - 1),
+                    child[1].max(0).min(self.volume.size[1] as i32 - 1),
+                    child[2].max(0).min(self.volume.size[2] as i32 - 1),
+                ];
+                *value = if self.overlaps(child, 1 << child_total) && self.value(sample) < 0.0 { -self.background } else { self.background };
+            }
+        }
+        (child_mask, values, children)
+    }
+}
+
+fn write_internal(w: &mut Writer, band: &Band, leaves: &mut Vec<[i32; 3]>, origin: [i32; 3], log2: u32, child_total: u32) {
+    let (child_mask, values, children) = band.internal(origin, log2, child_total);
+    w.mask(&child_mask);
+    //Tiles are never active in a level set
+    w.mask(&Mask::new(log2));
+    w.values(&values, &Mask::new(log2), Some(&child_mask), log2);
+
+    for child in children {
+        if log2 == UPPER_LOG2 {
+            write_internal(w, band, leaves, child, LOWER_LOG2, LEAF_LOG2);
+        } else {
+            w.mask(&band.leaf(child).1);
+            leaves.push(child);
+        }
+    }
+}
+
+//Writes the volume as a narrow band level set named `name`, HALF_WIDTH voxels wide on either side
+pub fn save<P: AsRef<Path>>(volume: &Volume, path: P, name: &str) -> Result<(), VolumeFileError> {
+    fs::write(path, to_bytes(volume, name))?;
+    Ok(())
+}
+
+pub fn to_bytes(volume: &Volume, name: &str) -> Vec<u8> {
+    let spacing = volume.spacing();
+    let background = HALF_WIDTH * spacing.x.max(spacing.y).max(spacing.z);
+    let mut w = Writer {
+        data: Vec::new(),
+        background,
+    };
+
+    w.i64(MAGIC);
+    w.u32(FILE_VERSION);
+    w.u32(LIBRARY_VERSION.0);
+    w.u32(LIBRARY_VERSION.1);
+    //Grid offsets
+    w.u8(1);
+    w.data.extend_from_slice(uuid().as_bytes());
+    w.u32(1);
+    w.metadata("creator", "string", b"sdf_preview");
+    w.i32(1);
+
+    //Descriptor, the offsets get patched in once they're known
+    w.string(name);
+    w.string(GRID_TYPE);
+    w.string("");
+    let offsets_pos = w.data.len();
+    for _ in 0..3 {
+        w.i64(0);
+    }
+    let grid_pos = w.data.len();
+
+    w.u32(COMPRESS_ACTIVE_MASK);
+    let size = volume.size;
+    let bbox_min = [0u8; 12];
+    let mut bbox_max = Vec::new();
+    for s in &size {
+        bbox_max.extend_from_slice(&(*s as i32 - 1).to_le_bytes());
+    }
+    w.u32(5);
+    w.metadata("class", "string", b"level set");
+    w.metadata("name", "string", name.as_bytes());
+    w.metadata("is_saved_as_half_float", "bool", &[0]);
+    w.metadata("file_bbox_min", "vec3i", &bbox_min);
+    w.metadata("file_bbox_max", "vec3i", &bbox_max);
+
+    //ScaleTranslateMap: translation, scale, voxel size, then the inverse scale, its square and half of it
+    let scale = spacing.cast::<f64>().unwrap();
+    let inverse = Vector3::new(1.0 / scale.x, 1.0 / scale.y, 1.0 / scale.z);
+    w.string("ScaleTranslateMap");
+    w.vec3d(volume.origin.cast::<f64>().unwrap());
+    w.vec3d(scale);
+    w.vec3d(scale);
+    w.vec3d(inverse);
+    w.vec3d(inverse.mul_element_wise(inverse));
+    w.vec3d(inverse * 0.5);
+
+    let band = Band { volume, background };
+    w.i32(1);
+    w.f32(background);
+    let upper_width = 1 << UPPER_TOTAL;
+    let mut roots = Vec::new();
+    for z in (0..size[2] as i32).step_by(upper_width) {
+        for y in (0..size[1] as i32).step_by(upper_width) {
+            for x in (0..size[0] as i32).step_by(upper_width) {
+                roots.push([x, y, z]);
+            }
+        }
+    }
+    //The root keeps its children sorted by x, then y, then z
+    roots.sort();
+    w.u32(0);
+    w.u32(roots.len() as u32);
+    let mut leaves = Vec::new();
+    for root in &roots {
+        w.coord(*root);
+        write_internal(&mut w, &band, &mut leaves, *root, UPPER_LOG2, LOWER_TOTAL);
+    }
+
+    let block_pos = w.data.len();
+    for leaf in leaves {
+        let (values, mask) = band.leaf(leaf);
+        w.mask(&mask);
+        w.values(&values, &mask, None, LEAF_LOG2);
+    }
+    let end_pos = w.data.len();
+
+    for (i, pos) in [grid_pos, block_pos, end_pos].iter().enumerate() {
+        let at = offsets_pos + i * 8;
+        w.data[at..at + 8].copy_from_slice(&(*pos as i64).to_le_bytes());
+    }
+
+    w.data
+}
+
+//OpenVDB uses this to tell files apart when caching, it only needs to be unique-ish
+fn uuid() -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    let hex = format!("{:032x}", nanos);
+    format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::eval::Sample;
+
+    #[test]
+    fn sphere_round_trip() {
+        let center = Vector3::new(1.0, -2.0, 0.5);
+        let volume = Volume::from_fn([32, 32, 32], Vector3::new(-8.0, -10.0, -7.5), Vector3::new(16.0, 16.0, 16.0), |p| {
+            Sample::new((p - center).magnitude() - 5.0, 0)
+        });
+        let loaded = parse(&to_bytes(&volume, "surface")).unwrap();
+        assert_eq!(loaded.spacing(), volume.spacing());
+
+        let background = HALF_WIDTH * volume.spacing().x;
+        for z in 0..volume.size[2] {
+            for y in 0..volume.size[1] {
+                for x in 0..volume.size[0] {
+                    let expected = volume.get(x, y, z);
+                    let v = loaded.to_voxel(volume.position(x, y, z));
+                    let v = [v.x.round() as i64, v.y.round() as i64, v.z.round() as i64];
+                    let inside = (0..3).all(|i| v[i] >= 0 && v[i] < loaded.size[i] as i64);
+                    if !inside {
+                        //Only the narrow band and the region around it gets stored
+                        assert!(expected > background, "voxel {:?} at {} is missing", [x, y, z], expected);
+                        continue;
+                    }
+
+                    let found = loaded.get(v[0] as usize, v[1] as usize, v[2] as usize);
+                    if expected.abs() < background {
+                        assert!((found - expected).abs() < 1e-5, "voxel {:?} is {} instead of {}", [x, y, z], found, expected);
+                    } else {
+                        assert_eq!(found.signum(), expected.signum(), "voxel {:?} has the wrong sign", [x, y, z]);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_file_is_rejected() {
+        let volume = Volume::from_fn([16, 16, 16], Vector3::zero(), Vector3::new(16.0, 16.0, 16.0), |p| {
+            Sample::new((p - Vector3::new(8.0, 8.0, 8.0)).magnitude() - 4.0, 0)
+        });
+        let mut bytes = to_bytes(&volume, "surface");
+        bytes.truncate(bytes.len() / 2);
+        assert!(parse(&bytes).is_err());
+    }
+}
@@ -0,0 +1,343 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use cgmath::*;
+
+use super::Volume;
+use super::distance;
+use super::file::VolumeFileError;
+
+//Empty cells around the voxels, so the surface isn't right at the edge of the volume
+const PADDING: i32 = 2;
+
+//MagicaVoxel files are a tree of RIFF style chunks: a four byte id, the size of the
+//content, the size of the children and then both. Everything is little endian.
+//https://github.com/ephtracy/voxel-model/blob/master/MagicaVoxel-file-format-vox.txt
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader {
+            data,
+            offset: 0,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.offset >= self.data.len()
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], VolumeFileError> {
+        if self.data.len() - self.offset < count {
+            return Err(VolumeFileError::Parse("Unexpected end of file".to_string()));
+        }
+        let bytes = &self.data[self.offset..self.offset + count];
+        self.offset += count;
+        Ok(bytes)
+    }
+
+    fn i32(&mut self) -> Result<i32, VolumeFileError> {
+        let b = self.bytes(4)?;
+        Ok(i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn count(&mut self) -> Result<usize, VolumeFileError> {
+        let count = self.i32()?;
+        if count < 0 {
+            return Err(VolumeFileError::Parse(format!("Negative count {}", count)));
+        }
+        Ok(count as usize)
+    }
+
+    fn string(&mut self) -> Result<String, VolumeFileError> {
+        let len = self.count()?;
+        Ok(String::from_utf8_lossy(self.bytes(len)?).into_owned())
+    }
+
+    fn dict(&mut self) -> Result<HashMap<String, String>, VolumeFileError> {
+        let count = self.count()?;
+        let mut dict = HashMap::new();
+        for _ in 0..count {
+            let key = self.string()?;
+            let value = self.string()?;
+            dict.insert(key, value);
+        }
+        Ok(dict)
+    }
+
+    //The id and the content, the children follow right after in the same reader
+    fn chunk(&mut self) -> Result<([u8; 4], Reader<'a>), VolumeFileError> {
+        let id = self.bytes(4)?;
+        let content_size = self.count()?;
+        let _children_size = self.count()?;
+        let content = self.bytes(content_size)?;
+        Ok(([id[0], id[1], id[2], id[3]], Reader::new(content)))
+    }
+}
+
+struct Model {
+    size: [i32; 3],
+    //x, y, z and the palette index
+    voxels: Vec<[u8; 4]>,
+}
+
+//Only what's needed to place the models, cameras and materials get skipped
+enum SceneNode {
+    Transform { child: i32, rotation: Matrix3<f32>, translation: Vector3<f32>, hidden: bool },
+    Group { children: Vec<i32> },
+    Shape { models: Vec<i32> },
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Volume, VolumeFileError> {
+    parse(&fs::read(path)?)
+}
+
+//Every model of the file in one volume, placed by the scene graph if there is one.
+//MagicaVoxel is Z up, so Z becomes Y here. The palette index ends up as the material,
+//with one world unit per voxel.
+pub fn parse(data: &[u8]) -> Result<Volume, VolumeFileError> {
+    let mut reader = Reader::new(data);
+    if reader.bytes(4)? != b"VOX " {
+        return Err(VolumeFileError::Parse("Not a MagicaVoxel file".to_string()));
+    }
+    let _version = reader.i32()?;
+    let (id, _) = reader.chunk()?;
+    if &id != b"MAIN" {
+        return Err(VolumeFileError::Parse("Missing MAIN chunk".to_string()));
+    }
+
+    let mut models = Vec::new();
+    let mut size = None;
+    let mut nodes = HashMap::new();
+    while !reader.is_empty() {
+        let (id, mut chunk) = reader.chunk()?;
+        match &id {
+            b"SIZE" => size = Some([chunk.i32()?, chunk.i32()?, chunk.i32()?]),
+            b"XYZI" => {
+                let size = size.take().ok_or_else(|| VolumeFileError::Parse("XYZI chunk without a SIZE chunk".to_string()))?;
+                let count = chunk.count()?;
+                let voxels = chunk.bytes(count * 4)?.chunks_exact(4).map(|v| [v[0], v[1], v[2], v[3]]).collect();
+                models.push(Model { size, voxels });
+            },
+            b"nTRN" => {
+                let id = chunk.i32()?;
+                let attributes = chunk.dict()?;
+                let child = chunk.i32()?;
+                let _reserved = chunk.i32()?;
+                let _layer = chunk.i32()?;
+                //Animated transforms have more frames, the first one is the one that's shown
+                let frames = chunk.count()?;
+                let frame = if frames > 0 { chunk.dict()? } else { HashMap::new() };
+                nodes.insert(id, SceneNode::Transform {
+                    child,
+                    rotation: frame.get("_r").map(|r| parse_rotation(r)).transpose()?.unwrap_or_else(Matrix3::identity),
+                    translation: frame.get("_t").map(|t| parse_translation(t)).transpose()?.unwrap_or_else(Vector3::zero),
+                    hidden: matches!(attributes.get("_hidden").map(String::as_str), Some("1")),
+                });
+            },
+            b"nGRP" => {
+                let id = chunk.i32()?;
+                let _attributes = chunk.dict()?;
+                let count = chunk.count()?;
+                let children = (0..count).map(|_| chunk.i32()).collect::<Result<_, _>>()?;
+                nodes.insert(id, SceneNode::Group { children });
+            },
+            b"nSHP" => {
+                let id = chunk.i32()?;
+                let _attributes = chunk.dict()?;
+                let count = chunk.count()?;
+                let mut shape_models = Vec::with_capacity(count);
+                for _ in 0..count {
+                    shape_models.push(chunk.i32()?);
+                    let _model_attributes = chunk.dict()?;
+                }
+                nodes.insert(id, SceneNode::Shape { models: shape_models });
+            },
+            _ => {},
+        }
+    }
+
+    if models.is_empty() {
+        return Err(VolumeFileError::Parse("No models in file".to_string()));
+    }
+
+    //Files from before the scene graph only have a single model without a transform
+    let mut voxels = Vec::new();
+    if nodes.is_empty() {
+        for model in &models {
+            place_model(model, &Matrix3::identity(), &Vector3::zero(), &mut voxels);
+        }
+    } else {
+        place_node(&nodes, &models, 0, &Matrix3::identity(), &Vector3::zero(), 0, &mut voxels)?;
+    }
+
+    voxelize(&voxels)
+}
+
+//Rotation is stored as a byte: the column of the one in the first and second row,
+//the third row gets whatever is left, then the signs of all three rows
+fn parse_rotation(value: &str) -> Result<Matrix3<f32>, VolumeFileError> {
+    let bits: u8 = value.trim().parse().map_err(|_| VolumeFileError::Parse(format!("Invalid rotation '{}'", value)))?;
+    let first = (bits & 3) as usize;
+    let second = ((bits >> 2) & 3) as usize;
+    if first > 2 || second > 2 || first == second {
+        return Err(VolumeFileError::Parse(format!("Invalid rotation '{}'", value)));
+    }
+    let third = 3 - first - second;
+
+    let mut rows = [[0.0; 3]; 3];
+    for (row, column) in [first, second, third].iter().enumerate() {
+        rows[row][*column] = if bits & (1 << (4 + row)) != 0 { -1.0 } else { 1.0 };
+    }
+    //cgmath matrices are built from columns
+    Ok(Matrix3::new(
+        rows[0][0], rows[1][0], rows[2][0],
+        rows[0][1], rows[1][1], rows[2][1],
+        rows[0][2], rows[1][2], rows[2][2],
+    ))
+}
+
+fn parse_translation(value: &str) -> Result<Vector3<f32>, VolumeFileError> {
+    let coords: Vec<f32> = value.split_whitespace().map(|c| c.parse()).collect::<Result<_, _>>()
+        .map_err(|_| VolumeFileError::Parse(format!("Invalid translation '{}'", value)))?;
+    match coords.as_slice() {
+        [x, y, z] => Ok(Vector3::new(*x, *y, *z)),
+        _ => Err(VolumeFileError::Parse(format!("Invalid translation '{}'", value))),
+    }
+}
+
+fn place_node(nodes: &HashMap<i32, SceneNode>, models: &[Model], id: i32, rotation: &Matrix3<f32>, translation: &Vector3<f32>, depth: usize, voxels: &mut Vec<(Vector3<i32>, u8)>) -> Result<(), VolumeFileError> {
+    //Broken files could loop forever otherwise
+    if depth > 64 {
+        return Err(VolumeFileError::Parse("Scene graph is too deep".to_string()));
+    }
+
+    match nodes.get(&id) {
+        Some(SceneNode::Transform { child, rotation: r, translation: t, hidden }) => {
+            if !hidden {
+                place_node(nodes, models, *child, &(rotation * r), &(translation + rotation * t), depth + 1, voxels)?;
+            }
+        },
+        Some(SceneNode::Group { children }) => {
+            for child in children {
+                place_node(nodes, models, *child, rotation, translation, depth + 1, voxels)?;
+            }
+        },
+        Some(SceneNode::Shape { models: shape_models }) => {
+            for model in shape_models {
+                let model = models.get(*model as usize).ok_or_else(|| VolumeFileError::Parse(format!("Missing model {}", model)))?;
+                place_model(model, rotation, translation, voxels);
+            }
+        },
+        None => return Err(VolumeFileError::Parse(format!("Missing scene node {}", id))),
+    }
+    Ok(())
+}
+
+//Models are centred on their transform, the centre rounded down for even sizes
+fn place_model(model: &Model, rotation: &Matrix3<f32>, translation: &Vector3<f32>, voxels: &mut Vec<(Vector3<i32>, u8)>) {
+    let centre = Vector3::new(model.size[0] / 2, model.size[1] / 2, model.size[2] / 2).cast::<f32>().unwrap();
+    for v in &model.voxels {
+        let local = Vector3::new(v[0] as f32, v[1] as f32, v[2] as f32) - centre;
+        //Rotations only swap and flip axes, rounding just gets rid of float noise
+        let p = rotation * local + translation;
+        voxels.push((Vector3::new(p.x.round() as i32, p.y.round() as i32, p.z.round() as i32), v[3]));
+    }
+}
+
+//Z up to Y up, keeping it right handed
+fn to_world(v: Vector3<i32>) -> Vector3<i32> {
+    Vector3::new(v.x, v.z, -v.y)
+}
+
+fn voxelize(voxels: &[(Vector3<i32>, u8)]) -> Result<Volume, VolumeFileError> {
+    //Empty models or every shape hidden, there are no bounds to build a volume in
+    if voxels.is_empty() {
+        return Err(VolumeFileError::Parse("No visible voxels".to_string()));
+    }
+
+    let mut min = Vector3::new(i32::MAX, i32::MAX, i32::MAX);
+    let mut max = Vector3::new(i32::MIN, i32::MIN, i32::MIN);
+    for (v, _) in voxels {
+        let v = to_world(*v);
+        min = Vector3::new(min.x.min(v.x), min.y.min(v.y), min.z.min(v.z));
+        max = Vector3::new(max.x.max(v.x), max.y.max(v.y), max.z.max(v.z));
+    }
+    min -= Vector3::new(PADDING, PADDING, PADDING);
+    max += Vector3::new(PADDING, PADDING, PADDING);
+
+    let size = [(max.x - min.x + 1) as usize, (max.y - min.y + 1) as usize, (max.z - min.z + 1) as usize];
+    let mut cells = vec![None; size[0] * size[1] * size[2]];
+    for (v, index) in voxels {
+        let v = to_world(*v) - min;
+        cells[v.x as usize + size[0] * (v.y as usize + size[1] * v.z as usize)] = Some(*index as u32);
+    }
+
+    //Samples at the voxel centres, so the volume covers exactly the voxels
+    let origin = Vector3::new(min.x as f32, min.y as f32, min.z as f32) + Vector3::new(0.5, 0.5, 0.5);
+    let extent = Vector3::new(size[0] as f32, size[1] as f32, size[2] as f32);
+    Ok(distance::from_occupancy(size, origin, extent, &cells))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8], content: &[u8], children: &[u8]) -> Vec<u8> {
+        let mut data = id.to_vec();
+        data.extend_from_slice(&(content.len() as i32).to_le_bytes());
+        data.extend_from_slice(&(children.len() as i32).to_le_bytes());
+        data.extend_from_slice(content);
+        data.extend_from_slice(children);
+        data
+    }
+
+    //A file with a single model and no scene graph
+    fn file(size: [i32; 3], voxels: &[[u8; 4]]) -> Vec<u8> {
+        let mut size_chunk = Vec::new();
+        for s in &size {
+            size_chunk.extend_from_slice(&s.to_le_bytes());
+        }
+        let mut xyzi = (voxels.len() as i32).to_le_bytes().to_vec();
+        for v in voxels {
+            xyzi.extend_from_slice(v);
+        }
+
+        let mut children = chunk(b"SIZE", &size_chunk, &[]);
+        children.extend(chunk(b"XYZI", &xyzi, &[]));
+        let mut data = b"VOX ".to_vec();
+        data.extend_from_slice(&150i32.to_le_bytes());
+        data.extend(chunk(b"MAIN", &[], &children));
+        data
+    }
+
+    #[test]
+    fn single_model() {
+        let mut voxels = Vec::new();
+        for z in 0..4 {
+            for y in 0..4 {
+                for x in 0..4 {
+                    voxels.push([x, y, z, 7]);
+                }
+            }
+        }
+        let volume = parse(&file([4, 4, 4], &voxels)).unwrap();
+
+        //Centred on the origin, the cube spans -2..2 on every axis
+        assert!(volume.sample(Vector3::zero()) < 0.0);
+        assert_eq!(volume.material(Vector3::zero()), 7);
+        assert!(volume.sample_unbounded(Vector3::new(3.0, 0.0, 0.0)) > 0.0);
+    }
+
+    #[test]
+    fn empty_model_is_rejected() {
+        match parse(&file([4, 4, 4], &[])) {
+            Err(VolumeFileError::Parse(message)) => assert_eq!(message, "No visible voxels"),
+            _ => panic!("an empty model should fail to load"),
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use std::time::Duration;
+
+use notify::{Watcher, RecommendedWatcher, RecursiveMode, DebouncedEvent};
+
+//Watches a set of files and reports which ones changed since the last poll.
+//Watching happens per directory, as a lot of editors save by replacing the file.
+pub struct FileWatcher {
+    _watcher: RecommendedWatcher,
+    rx: Receiver<DebouncedEvent>,
+    files: Vec<PathBuf>,
+}
+
+impl FileWatcher {
+    pub fn new(files: Vec<PathBuf>) -> notify::Result<FileWatcher> {
+        let (tx, rx) = channel();
+        let mut watcher: RecommendedWatcher = Watcher::new(tx, Duration::from_millis(100))?;
+
+        let files: Vec<PathBuf> = files.into_iter().map(absolute).collect();
+        let mut dirs: Vec<&Path> = files.iter().filter_map(|f| f.parent()).collect();
+        dirs.sort();
+        dirs.dedup();
+        for dir in dirs {
+            watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        }
+
+        Ok(FileWatcher {
+            _watcher: watcher,
+            rx: rx,
+            files: files,
+        })
+    }
+
+    //Never blocks, returns every watched file that changed, without duplicates
+    pub fn poll(&self) -> Vec<PathBuf> {
+        let mut changed = Vec::new();
+
+        while let Ok(event) = self.rx.try_recv() {
+            let path = match event {
+                DebouncedEvent::Write(path) | DebouncedEvent::Create(path) | DebouncedEvent::Rename(_, path) => path,
+                DebouncedEvent::Error(err, _) => {
+                    warn!("File watcher error: {}", err);
+                    continue;
+                },
+                _ => continue,
+            };
+
+            let path = absolute(path);
+            if self.files.contains(&path) && !changed.contains(&path) {
+                changed.push(path);
+            }
+        }
+
+        changed
+    }
+}
+
+//Canonical if the file exists, otherwise at least relative to the working directory
+//so there is a parent directory to watch
+fn absolute(path: PathBuf) -> PathBuf {
+    if let Ok(canonical) = path.canonicalize() {
+        return canonical;
+    }
+    match env::current_dir() {
+        Ok(dir) if path.is_relative() => dir.join(path),
+        _ => path,
+    }
+}
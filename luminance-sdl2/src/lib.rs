@@ -19,13 +19,16 @@ use std::ops::DerefMut;
 //Error that can be risen while creating a surface
 #[derive(Debug)]
 pub enum SDL2SurfaceError {
-    GraphicsStateError(StateQueryError)
+    GraphicsStateError(StateQueryError),
+    //Usually means the driver doesn't do the requested GL version
+    ContextCreationError(String),
 }
 
 impl fmt::Display for SDL2SurfaceError {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         match self {
-            SDL2SurfaceError::GraphicsStateError(sqe) => write!(f, "Failed to get graphics state: {}", sqe)
+            SDL2SurfaceError::GraphicsStateError(sqe) => write!(f, "Failed to get graphics state: {}", sqe),
+            SDL2SurfaceError::ContextCreationError(e) => write!(f, "Failed to create GL context: {}", e),
         }
     }
 }
@@ -71,7 +74,7 @@ impl SDL2Surface {
             }
         }
 
-        let _gl_context = window.gl_create_context().expect("Failed to create GL context!");
+        let _gl_context = window.gl_create_context().map_err(SDL2SurfaceError::ContextCreationError)?;
         gl::load_with(|s| video.gl_get_proc_address(s) as *const c_void);
 
         let swap_interval = if vsync {